@@ -0,0 +1,102 @@
+use crate::{ident, Backend};
+use darling::FromMeta;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+#[derive(FromMeta, Debug)]
+pub struct BackendTestsOptions {
+    #[darling(default)]
+    pg: Option<syn::Path>,
+    #[darling(default)]
+    mysql: Option<syn::Path>,
+    #[darling(default)]
+    sqlite: Option<syn::Path>,
+}
+
+impl BackendTestsOptions {
+    fn backends(&self) -> Vec<(&'static str, &syn::Path)> {
+        let backends = vec![
+            ("pg", Backend::Pg, self.pg.as_ref()),
+            ("mysql", Backend::Mysql, self.mysql.as_ref()),
+            ("sqlite", Backend::Sqlite, self.sqlite.as_ref()),
+        ];
+
+        let backends = backends
+            .into_iter()
+            .filter_map(|(name, backend, setup)| Some((name, backend, setup?)))
+            .collect::<Vec<_>>();
+        if backends.is_empty() {
+            panic!("`factory_backend_tests` requires at least one of `pg`, `mysql` or `sqlite`");
+        }
+        // Tests of backends that aren't enabled are left out, like the factory's code for them
+        backends
+            .into_iter()
+            .filter(|(_, backend, _)| backend.is_enabled())
+            .map(|(name, _, setup)| (name, setup))
+            .collect()
+    }
+}
+
+pub fn backend_tests(input: &DeriveInput, options: &BackendTestsOptions) -> TokenStream {
+    use heck::SnakeCase;
+
+    let factory = &input.ident;
+    let module = ident(&format!(
+        "{}_backend_tests",
+        factory.to_string().to_snake_case()
+    ));
+
+    let tests = options
+        .backends()
+        .into_iter()
+        .map(|(backend, setup)| {
+            let insert = ident(&format!("insert_{}", backend));
+            let try_insert = ident(&format!("try_insert_{}", backend));
+            let insert_many = ident(&format!("insert_many_{}", backend));
+
+            quote! {
+                #[test]
+                fn #insert() {
+                    let con = #setup();
+                    diesel_factories::InsertWith::insert_with(#factory::default(), &con);
+                }
+
+                #[test]
+                fn #try_insert() {
+                    let con = #setup();
+                    let result = diesel_factories::factory_v2::Insert::insert(#factory::default(), &con);
+                    assert!(result.is_ok(), "Insert failed: {:?}", result.err());
+                }
+
+                #[test]
+                fn #insert_many() {
+                    let con = #setup();
+                    let models = diesel_factories::factory_v2::InsertBatch::insert_batch(
+                        vec![#factory::default(), #factory::default(), #factory::default()],
+                        &con,
+                    )
+                    .unwrap_or_else(|err| panic!("Batch insert failed: {:?}", err));
+                    let ids = models
+                        .iter()
+                        .map(|model| <#factory as diesel_factories::Factory>::id_for_model(model).clone())
+                        .collect::<Vec<_>>();
+                    assert_eq!(3, ids.len());
+                    assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    if tests.is_empty() {
+        return quote! {};
+    }
+
+    quote! {
+        #[cfg(test)]
+        mod #module {
+            use super::*;
+
+            #(#tests)*
+        }
+    }
+}
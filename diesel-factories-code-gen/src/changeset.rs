@@ -0,0 +1,190 @@
+use crate::{field_options, ident, named_fields, TypeExtension};
+use darling::FromDeriveInput;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+#[derive(FromDeriveInput, Debug)]
+#[darling(attributes(factory), forward_attrs(doc, cfg, allow))]
+pub struct ChangesetOptions {
+    changeset: syn::Path,
+}
+
+pub struct ChangesetDeriveData {
+    input: DeriveInput,
+    options: ChangesetOptions,
+    tokens: TokenStream,
+}
+
+impl ChangesetDeriveData {
+    pub fn new(input: DeriveInput, options: ChangesetOptions) -> Self {
+        Self {
+            input,
+            options,
+            tokens: quote! {},
+        }
+    }
+
+    pub fn build_derive_output(mut self) -> TokenStream {
+        self.gen_changeset_factory_impl();
+        self.gen_builder_methods();
+        self.gen_default_impl();
+
+        self.tokens
+    }
+
+    fn gen_changeset_factory_impl(&mut self) {
+        let factory = &self.input.ident;
+        let generics = &self.input.generics;
+        let changeset = &self.options.changeset;
+        let fields = named_fields(&self.input)
+            .map(|field| {
+                let name = &field.ident;
+                match (field_options(field).map, field.ty.is_inside_option()) {
+                    (Some(map), true) => quote! { #name: self.#name.map(#map) },
+                    (Some(map), false) => quote! { #name: #map(self.#name) },
+                    (None, _) => quote! { #name: self.#name },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::ChangesetFactory for #factory#generics {
+                type Changeset = #changeset;
+
+                fn build(self) -> Self::Changeset {
+                    #changeset {
+                        #(#fields),*
+                    }
+                }
+            }
+        });
+    }
+
+    fn gen_builder_methods(&mut self) {
+        let factory = &self.input.ident;
+        let generics = &self.input.generics;
+        let methods = named_fields(&self.input)
+            .filter_map(|field| {
+                let name = &field.ident;
+                let method_name = field_options(field).builder_name(field)?;
+                let method = match option_inner_type(&field.ty) {
+                    Some(inner) if inner.is_container_field() => quote! {
+                        #[allow(missing_docs, dead_code)]
+                        pub fn #method_name<T: diesel_factories::IntoContainer<#inner>>(mut self, t: T) -> Self {
+                            self.#name = Some(t.into_container());
+                            self
+                        }
+                    },
+                    Some(inner) => quote! {
+                        #[allow(missing_docs, dead_code)]
+                        pub fn #method_name<T: Into<#inner>>(mut self, t: T) -> Self {
+                            self.#name = Some(t.into());
+                            self
+                        }
+                    },
+                    None if field.ty.is_container_field() => {
+                        let ty = &field.ty;
+                        quote! {
+                            #[allow(missing_docs, dead_code)]
+                            pub fn #method_name<T: diesel_factories::IntoContainer<#ty>>(mut self, t: T) -> Self {
+                                self.#name = t.into_container();
+                                self
+                            }
+                        }
+                    }
+                    None => {
+                        let ty = &field.ty;
+                        quote! {
+                            #[allow(missing_docs, dead_code)]
+                            pub fn #method_name<T: Into<#ty>>(mut self, t: T) -> Self {
+                                self.#name = t.into();
+                                self
+                            }
+                        }
+                    }
+                };
+                if !field.ty.is_inside_option() {
+                    return Some(method);
+                }
+                let clear_name = ident(&format!("clear_{}", method_name));
+                Some(quote! {
+                    #method
+
+                    #[allow(missing_docs, dead_code)]
+                    pub fn #clear_name(mut self) -> Self {
+                        self.#name = None;
+                        self
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            impl#generics #factory#generics {
+                #(#methods)*
+            }
+        });
+    }
+
+    /// Optional fields default to `None`, which leaves the column unchanged. `Default` is derived
+    /// if every other field has `default`, `default_with` or `fake`.
+    fn gen_default_impl(&mut self) {
+        let mut missing = Vec::new();
+        let mut any_field_has_default = false;
+        let mut fields = Vec::new();
+
+        for field in named_fields(&self.input) {
+            let name = &field.ident;
+            let options = field_options(field);
+            let value = match options.default_value() {
+                Some(value) => {
+                    any_field_has_default = true;
+                    value
+                }
+                None if field.ty.is_inside_option() => quote! { None },
+                None => {
+                    missing.push(name.as_ref().unwrap().to_string());
+                    continue;
+                }
+            };
+            fields.push(quote! { #name: #value });
+        }
+
+        if !missing.is_empty() {
+            if any_field_has_default {
+                panic!(
+                    "`default`, `default_with` or `fake` is required on every non-optional field to derive `Default`. Missing on: {}",
+                    missing.join(", ")
+                );
+            }
+            return;
+        }
+
+        let factory = &self.input.ident;
+        let generics = &self.input.generics;
+        self.tokens.extend(quote! {
+            impl#generics Default for #factory#generics {
+                fn default() -> Self {
+                    Self {
+                        #(#fields),*
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn option_inner_type(ty: &syn::Type) -> Option<&syn::Type> {
+    if !ty.is_inside_option() {
+        return None;
+    }
+
+    match &ty.extract_outermost_type().arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.iter().next() {
+            Some(syn::GenericArgument::Type(inner)) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
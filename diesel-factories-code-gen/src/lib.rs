@@ -5,13 +5,16 @@
 extern crate proc_macro;
 extern crate proc_macro2;
 
-use darling::FromDeriveInput;
+use darling::{FromDeriveInput, FromField, FromMeta};
 use proc_macro2::Span;
 use proc_macro2::TokenStream;
 use quote::quote;
 use quote::ToTokens;
 use syn::{parse_macro_input, DeriveInput};
 
+mod backend_tests;
+mod changeset;
+
 macro_rules! if_let_or_none {
     ( $path:path , $($tokens:tt)* ) => {
         if let $path(inner) = $($tokens)* {
@@ -35,15 +38,267 @@ pub fn derive_factory(input: proc_macro::TokenStream) -> proc_macro::TokenStream
     tokens.into()
 }
 
+#[proc_macro_derive(ChangesetFactory, attributes(factory))]
+pub fn derive_changeset_factory(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let options = match changeset::ChangesetOptions::from_derive_input(&ast) {
+        Ok(options) => options,
+        Err(err) => panic!("{}", err),
+    };
+
+    let out = changeset::ChangesetDeriveData::new(ast, options);
+    let tokens = out.build_derive_output();
+    tokens.into()
+}
+
+#[proc_macro_attribute]
+pub fn factory_backend_tests(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args = parse_macro_input!(args as syn::AttributeArgs);
+    let item = input.clone();
+    let ast = parse_macro_input!(item as DeriveInput);
+    let options = match backend_tests::BackendTestsOptions::from_list(&args) {
+        Ok(options) => options,
+        Err(err) => panic!("{}", err),
+    };
+
+    let input = TokenStream::from(input);
+    let tests = backend_tests::backend_tests(&ast, &options);
+    let tokens = quote! {
+        #input
+        #tests
+    };
+    tokens.into()
+}
+
 #[derive(FromDeriveInput, Debug)]
 #[darling(attributes(factory), forward_attrs(doc, cfg, allow))]
 struct Options {
     model: syn::Ident,
-    #[darling(default)]
-    connection: Option<syn::Path>,
+    #[darling(default, multiple)]
+    connection: Vec<syn::Path>,
     #[darling(default)]
     id: Option<syn::Ident>,
+    #[darling(default)]
+    primary_key: Option<syn::Ident>,
+    #[darling(default)]
+    reselect: bool,
+    #[darling(default)]
+    backend: Option<String>,
+    table: syn::Path,
+    #[darling(default)]
+    after_insert: Option<syn::Path>,
+    #[darling(default)]
+    on_conflict: Option<String>,
+    #[darling(default)]
+    conflict_target: Option<String>,
+    #[darling(default)]
+    returning: Option<String>,
+    #[darling(default)]
+    version: Option<String>,
+    #[darling(default)]
+    since_migration: Option<String>,
+    #[darling(default, multiple)]
+    has_many: Vec<HasManyOptions>,
+    #[darling(default, multiple)]
+    joins: Vec<JoinsOptions>,
+    #[darling(default, rename = "abstract")]
+    is_abstract: bool,
+    #[darling(default)]
+    build: bool,
+    #[darling(default)]
+    from_model: bool,
+    #[darling(default)]
+    traits: Option<PathList>,
+    #[darling(default)]
+    profile: Option<syn::Path>,
+    #[darling(default)]
+    timestamps: Option<Timestamps>,
+    #[darling(default)]
+    cfg: Option<String>,
+    #[darling(default)]
+    scoped_column: Option<syn::Ident>,
+    #[darling(default)]
+    scope_type: Option<syn::Path>,
+}
+
+#[derive(FromMeta, Debug)]
+struct HasManyOptions {
+    factory: String,
+    association: syn::Ident,
+}
+
+#[derive(FromMeta, Debug)]
+struct JoinsOptions {
     table: syn::Path,
+    left: syn::Ident,
+    right: syn::Ident,
+    factory: String,
+    name: syn::Ident,
+}
+
+#[derive(FromField, Debug)]
+#[darling(attributes(factory))]
+struct FieldOptions {
+    #[darling(default)]
+    map: Option<syn::Path>,
+    #[darling(default, rename = "default")]
+    default_value: Option<String>,
+    #[darling(default)]
+    default_with: Option<syn::Path>,
+    #[darling(default)]
+    sql_type: Option<syn::Path>,
+    #[darling(default)]
+    polymorphic: Option<String>,
+    #[darling(default)]
+    types: Option<PathList>,
+    #[darling(default)]
+    primary_key: Option<syn::Ident>,
+    #[darling(default)]
+    foreign_key: Option<syn::Ident>,
+    #[darling(default)]
+    anonymize: Option<syn::Path>,
+    #[darling(default)]
+    fake: Option<String>,
+    #[darling(default)]
+    rename: Option<syn::Ident>,
+    #[darling(default)]
+    no_builder: bool,
+}
+
+/// Providers `fake` accepts, as `"Module.function"` in the `diesel_factories::fake` module.
+const FAKE_PROVIDERS: &[&str] = &[
+    "Name.first_name",
+    "Name.last_name",
+    "Name.name",
+    "Internet.username",
+    "Internet.domain",
+    "Internet.email",
+    "Internet.url",
+    "Lorem.word",
+    "Lorem.sentence",
+    "Address.city",
+    "Address.country",
+    "Date.past_datetime",
+    "Date.future_datetime",
+];
+
+/// Paths listed as string literals, such as `types("Post", "Photo")`.
+#[derive(Debug)]
+struct PathList(Vec<syn::Path>);
+
+impl FromMeta for PathList {
+    fn from_list(items: &[syn::NestedMeta]) -> darling::Result<Self> {
+        items
+            .iter()
+            .map(|item| match item {
+                syn::NestedMeta::Literal(syn::Lit::Str(lit)) => lit
+                    .parse::<syn::Path>()
+                    .map_err(|_| darling::Error::unknown_value(&lit.value())),
+                _ => Err(darling::Error::unexpected_type("non-string")),
+            })
+            .collect::<darling::Result<Vec<_>>>()
+            .map(PathList)
+    }
+}
+
+/// Columns set to the current time on insert. `timestamps` alone means `created_at` and
+/// `updated_at`, or the columns can be listed like `timestamps("inserted_at")`.
+#[derive(Debug)]
+struct Timestamps(Vec<syn::Ident>);
+
+impl FromMeta for Timestamps {
+    fn from_word() -> darling::Result<Self> {
+        Ok(Timestamps(vec![ident("created_at"), ident("updated_at")]))
+    }
+
+    fn from_list(items: &[syn::NestedMeta]) -> darling::Result<Self> {
+        items
+            .iter()
+            .map(|item| match item {
+                syn::NestedMeta::Literal(syn::Lit::Str(lit)) => lit
+                    .parse::<syn::Ident>()
+                    .map_err(|_| darling::Error::unknown_value(&lit.value())),
+                _ => Err(darling::Error::unexpected_type("non-string")),
+            })
+            .collect::<darling::Result<Vec<_>>>()
+            .map(Timestamps)
+    }
+}
+
+impl FieldOptions {
+    /// The column prefix and model types of a polymorphic association field.
+    fn polymorphic(&self) -> Option<(&str, &[syn::Path])> {
+        match (&self.polymorphic, &self.types) {
+            (Some(prefix), Some(types)) => Some((prefix, &types.0)),
+            (Some(_), None) => panic!("`polymorphic` requires `types` to also be set"),
+            (None, Some(_)) => panic!("`types` requires `polymorphic` to also be set"),
+            (None, None) => None,
+        }
+    }
+
+    /// The key field of the models a polymorphic association points to, `id` by default.
+    fn polymorphic_key(&self) -> syn::Ident {
+        match (&self.primary_key, &self.polymorphic) {
+            (Some(_), None) => {
+                panic!("`primary_key` on a field requires `polymorphic` to also be set")
+            }
+            (Some(primary_key), Some(_)) => primary_key.clone(),
+            (None, _) => ident("id"),
+        }
+    }
+
+    /// The column an association field's foreign key is in, `<field>_id` unless `foreign_key` is
+    /// set.
+    fn foreign_key(&self, field: &syn::Field) -> syn::Ident {
+        match &self.foreign_key {
+            Some(foreign_key) => foreign_key.clone(),
+            None => ident(&format!("{}_id", field.ident.as_ref().unwrap())),
+        }
+    }
+
+    /// Name of the field's builder method, or `None` with `no_builder`. Methods derived from it,
+    /// such as `<name>_with` for associations, use the same name.
+    fn builder_name(&self, field: &syn::Field) -> Option<syn::Ident> {
+        match (&self.rename, self.no_builder) {
+            (Some(_), true) => panic!("`rename` and `no_builder` can't both be used on a field"),
+            (_, true) => None,
+            (Some(rename), false) => Some(rename.clone()),
+            (None, false) => field.ident.clone(),
+        }
+    }
+
+    /// The field's value in a derived `Default`, from `default`, `default_with` or `fake`.
+    fn default_value(&self) -> Option<TokenStream> {
+        match (&self.default_value, &self.default_with, &self.fake) {
+            (Some(value), None, None) => {
+                let value = value
+                    .parse::<TokenStream>()
+                    .unwrap_or_else(|_| panic!("Invalid `default` expression: {}", value));
+                Some(quote! { #value })
+            }
+            (None, Some(path), None) => Some(quote! { #path() }),
+            (None, None, Some(provider)) => {
+                use heck::SnakeCase;
+
+                if !FAKE_PROVIDERS.contains(&provider.as_str()) {
+                    panic!(
+                        "Unknown `fake` provider {:?}. Known providers: {}",
+                        provider,
+                        FAKE_PROVIDERS.join(", ")
+                    );
+                }
+                let segments = provider
+                    .split('.')
+                    .map(|segment| ident(&segment.to_snake_case()));
+                Some(quote! { diesel_factories::fake::#(#segments)::*().into() })
+            }
+            (None, None, None) => None,
+            _ => panic!("Only one of `default`, `default_with` and `fake` can be used on a field"),
+        }
+    }
 }
 
 struct DeriveData {
@@ -54,17 +309,29 @@ struct DeriveData {
 
 trait PathSegmentExtension {
     fn normalize_lifetime_names(&self) -> TokenStream;
+    fn with_static_lifetime(&self) -> TokenStream;
 }
 
 impl PathSegmentExtension for syn::PathSegment {
     fn normalize_lifetime_names(&self) -> TokenStream {
         if let syn::PathArguments::AngleBracketed(_args) = &self.arguments {
             let ident = &self.ident;
-            return quote! {
+            quote! {
                 #ident<'z>
-            };
+            }
+        } else {
+            self.into_token_stream()
+        }
+    }
+
+    fn with_static_lifetime(&self) -> TokenStream {
+        if let syn::PathArguments::AngleBracketed(_args) = &self.arguments {
+            let ident = &self.ident;
+            quote! {
+                #ident<'static>
+            }
         } else {
-            return self.into_token_stream();
+            self.into_token_stream()
         }
     }
 }
@@ -74,8 +341,13 @@ trait TypeExtension {
     fn extract_outermost_type(&self) -> &syn::PathSegment;
     fn is_inside_option(&self) -> bool;
     fn extract_outermost_non_optional(&self) -> Option<&syn::PathSegment>;
+    fn association_type_arguments(&self) -> Option<Vec<&syn::Type>>;
     fn extract_model_and_factory(&self) -> Option<(TokenStream, TokenStream)>;
+    fn path_without_arguments(&self) -> syn::Path;
+    fn lifetimes(&self) -> Vec<syn::Lifetime>;
     fn is_association_field(&self) -> bool;
+    fn is_container_field(&self) -> bool;
+    fn is_field_value(&self) -> bool;
     fn parse_association_type(&self) -> Option<Association>;
 }
 
@@ -83,21 +355,45 @@ impl TypeExtension for syn::Type {
     fn parse_association_type(&self) -> Option<Association> {
         let is_option = self.is_inside_option();
 
-        let (model, factory) = if_let_or_none!(Some, self.extract_model_and_factory());
+        let (model, factory) = self.extract_model_and_factory()?;
+        let factory_type = self.association_type_arguments()?.pop()?.clone();
         Some(Association {
             is_option,
             model,
             factory,
+            factory_type,
         })
     }
 
     fn is_association_field(&self) -> bool {
         match self.extract_outermost_non_optional() {
             None => false,
-            Some(extracted) => extracted.ident.to_string() == "Association",
+            Some(extracted) => extracted.ident == "Association",
+        }
+    }
+
+    /// A `Vec` or `Option<Vec>` field, other than `Vec<u8>` which is usually binary data.
+    fn is_container_field(&self) -> bool {
+        let segment = match self.extract_outermost_non_optional() {
+            Some(segment) if segment.ident == "Vec" => segment,
+            _ => return false,
+        };
+        match &segment.arguments {
+            syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+                Some(arg) => match arg.value() {
+                    syn::GenericArgument::Type(ty) => ty.into_token_stream().to_string() != "u8",
+                    _ => true,
+                },
+                None => true,
+            },
+            _ => true,
         }
     }
 
+    fn is_field_value(&self) -> bool {
+        self.extract_outermost_type().ident == "FieldValue"
+    }
+
     fn to_string(&self) -> String {
         use quote::ToTokens;
         let mut tokenized = quote! {};
@@ -112,19 +408,19 @@ impl TypeExtension for syn::Type {
                 segments,
             } = path;
 
-            &segments.last().unwrap().value()
+            segments.last().unwrap().value()
         } else {
             panic!("Expected a TypePath here");
         }
     }
 
     fn is_inside_option(&self) -> bool {
-        self.extract_outermost_type().ident.to_string() == "Option"
+        self.extract_outermost_type().ident == "Option"
     }
 
     fn extract_outermost_non_optional(&self) -> Option<&syn::PathSegment> {
         if !self.is_inside_option() {
-            return Some(self.extract_outermost_type());
+            Some(self.extract_outermost_type())
         } else {
             let item = if_let_or_none!(
                 syn::PathArguments::AngleBracketed,
@@ -134,16 +430,12 @@ impl TypeExtension for syn::Type {
                 syn::GenericArgument::Type,
                 &item.args.last().unwrap().value()
             );
-            return Some(&unwrapped_type.extract_outermost_type());
+            Some(unwrapped_type.extract_outermost_type())
         }
     }
 
-    fn extract_model_and_factory(&self) -> Option<(TokenStream, TokenStream)> {
-        let path_segment;
-        match self.extract_outermost_non_optional() {
-            None => return None,
-            Some(extracted) => path_segment = extracted,
-        }
+    fn association_type_arguments(&self) -> Option<Vec<&syn::Type>> {
+        let path_segment = self.extract_outermost_non_optional()?;
         let syn::PathSegment {
             ident: _,
             arguments,
@@ -155,12 +447,43 @@ impl TypeExtension for syn::Type {
             .iter()
             .filter_map(|token| {
                 let extracted = if_let_or_none!(syn::GenericArgument::Type, token);
-                return Some(extracted);
+                Some(extracted)
             })
             .collect();
         if types_we_care_about.len() != 2 {
             return None;
         }
+        Some(types_we_care_about)
+    }
+
+    fn path_without_arguments(&self) -> syn::Path {
+        if let syn::Type::Path(syn::TypePath { qself: _, path }) = self {
+            let mut path = path.clone();
+            for segment in path.segments.iter_mut() {
+                segment.arguments = syn::PathArguments::None;
+            }
+            path
+        } else {
+            panic!("Expected a TypePath here");
+        }
+    }
+
+    fn lifetimes(&self) -> Vec<syn::Lifetime> {
+        match &self.extract_outermost_type().arguments {
+            syn::PathArguments::AngleBracketed(args) => args
+                .args
+                .iter()
+                .filter_map(|arg| {
+                    let lifetime = if_let_or_none!(syn::GenericArgument::Lifetime, arg);
+                    Some(lifetime.clone())
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn extract_model_and_factory(&self) -> Option<(TokenStream, TokenStream)> {
+        let types_we_care_about = self.association_type_arguments()?;
         let model_tokens = types_we_care_about
             .first()
             .unwrap()
@@ -171,75 +494,1547 @@ impl TypeExtension for syn::Type {
             .unwrap()
             .extract_outermost_type()
             .normalize_lifetime_names();
-        return Some((model_tokens, factory_tokens));
+        Some((model_tokens, factory_tokens))
     }
 }
 
-impl DeriveData {
-    fn new(input: DeriveInput, options: Options) -> Self {
-        Self {
-            input,
-            options,
-            tokens: quote! {},
+impl DeriveData {
+    fn new(input: DeriveInput, options: Options) -> Self {
+        Self {
+            input,
+            options,
+            tokens: quote! {},
+        }
+    }
+
+    fn build_derive_output(mut self) -> TokenStream {
+        if !self.options.is_abstract {
+            self.gen_factory_methods_impl();
+            self.gen_insert_impls();
+            self.gen_find_model_impls();
+            self.gen_versioned_factory_impl();
+            self.gen_has_many_impls();
+            self.gen_joins_impls();
+            self.gen_build_factory_impl();
+            self.gen_plan_impl();
+            self.gen_dry_run_impl();
+            self.gen_scoped_factory_impl();
+            self.gen_insert_with_associations();
+            self.gen_registration();
+        }
+        self.gen_builder_methods();
+        self.gen_with_traits();
+        self.gen_default_impl();
+        self.gen_from_model_constructors();
+        self.gen_set_association_traits();
+
+        match &self.options.cfg {
+            Some(cfg) => gate_items(self.tokens, cfg),
+            None => self.tokens,
+        }
+    }
+
+    fn gen_factory_methods_impl(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+        let id_type = self.id_type();
+        let primary_key = self.primary_key();
+        let connection_type = self.connection_type();
+        let table_path = self.table_path();
+        let load = self.load_by_key_code(false);
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::Factory for #factory#generics {
+                type Model = #model_type;
+                type Id = #id_type;
+                type Connection = #connection_type;
+
+                fn insert(self, con: &Self::Connection) -> Self::Model {
+                    diesel_factories::InsertWith::<Self::Connection>::insert_with(self, con)
+                }
+
+                fn id_for_model(model: &Self::Model) -> &Self::Id {
+                    &model.#primary_key
+                }
+            }
+
+            impl#generics diesel_factories::FindById for #factory#generics {
+                fn find_by_id(
+                    diesel_factories_id: &Self::Id,
+                    con: &Self::Connection,
+                ) -> diesel::result::QueryResult<Self::Model> {
+                    use #table_path::dsl::*;
+                    use #table_path as table;
+                    use diesel::prelude::*;
+
+                    let diesel_factories_key = diesel_factories_id.clone();
+                    #load
+                }
+            }
+
+            impl#generics diesel_factories::factory_v2::Factory for #factory#generics {
+                type Model = #model_type;
+                type Id = #id_type;
+
+                fn id_for_model(model: &Self::Model) -> &Self::Id {
+                    &model.#primary_key
+                }
+            }
+        });
+    }
+
+    fn gen_insert_impls(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+        let table_path = self.table_path();
+        let id_type = self.id_type();
+
+        let mut impl_generics = generics.clone();
+        impl_generics.params.insert(
+            0,
+            syn::GenericParam::Lifetime(syn::LifetimeDef::new(syn::Lifetime::new(
+                "'diesel_factories_con",
+                Span::call_site(),
+            ))),
+        );
+
+        let impls = self
+            .connection_types()
+            .into_iter()
+            .map(|connection_type| {
+                let backend = self.backend(&connection_type);
+                let insert_batch_code = self.insert_batch_code(false, backend);
+                // `after_insert` needs the model, so it's loaded anyway
+                let insert_returning_id = if self.options.after_insert.is_some() {
+                    quote! {
+                        let model = diesel_factories::factory_v2::Insert::insert(self, con)?;
+                        Ok(<Self as diesel_factories::factory_v2::Factory>::id_for_model(&model).clone())
+                    }
+                } else {
+                    let insert_ids_code = self.insert_batch_code(true, backend);
+                    quote! {
+                        use #table_path::dsl::*;
+                        use #table_path as table;
+                        use diesel::prelude::*;
+
+                        let diesel_factories_factories = vec![self];
+                        let diesel_factories_insert_ids = || -> diesel::result::QueryResult<Vec<#id_type>> {
+                            #insert_ids_code
+                        };
+                        Ok(diesel_factories_insert_ids()?.remove(0))
+                    }
+                };
+
+                quote! {
+                    impl#impl_generics diesel_factories::factory_v2::Insert<&'diesel_factories_con #connection_type>
+                        for #factory#generics
+                    {
+                        fn insert(
+                            self,
+                            con: &'diesel_factories_con #connection_type,
+                        ) -> diesel::result::QueryResult<#model_type> {
+                            let mut models = <Self as diesel_factories::factory_v2::InsertBatch<
+                                &'diesel_factories_con #connection_type,
+                            >>::insert_batch(vec![self], con)?;
+                            Ok(models.remove(0))
+                        }
+                    }
+
+                    impl#impl_generics diesel_factories::factory_v2::InsertBatch<&'diesel_factories_con #connection_type>
+                        for #factory#generics
+                    {
+                        fn insert_batch(
+                            diesel_factories_factories: Vec<Self>,
+                            con: &'diesel_factories_con #connection_type,
+                        ) -> diesel::result::QueryResult<Vec<#model_type>> {
+                            use #table_path::dsl::*;
+                            use #table_path as table;
+                            use diesel::prelude::*;
+
+                            #insert_batch_code
+                        }
+                    }
+
+                    impl#impl_generics diesel_factories::factory_v2::InsertReturningId<&'diesel_factories_con #connection_type>
+                        for #factory#generics
+                    {
+                        fn insert_returning_id(
+                            self,
+                            con: &'diesel_factories_con #connection_type,
+                        ) -> diesel::result::QueryResult<#id_type> {
+                            #insert_returning_id
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            #(#impls)*
+        });
+    }
+
+    fn gen_find_model_impls(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+        let table_path = self.table_path();
+
+        // Models are loaded from the `returning` columns if they are given
+        let filtered = quote! { diesel::dsl::Filter<#table_path::table, DieselFactoriesFilter> };
+        let filter = quote! { #table_path::table.filter(filter) };
+        let (query, select_bound, select) = match self.returning_columns() {
+            Some(columns) => {
+                let columns = columns
+                    .iter()
+                    .map(|column| quote! { #table_path::#column })
+                    .collect::<Vec<_>>();
+                let columns_type = quote! { ( #(#columns),* ) };
+                (
+                    quote! { diesel::dsl::Select<#filtered, #columns_type> },
+                    quote! { #filtered: diesel::query_dsl::methods::SelectDsl<#columns_type>, },
+                    quote! {
+                        diesel::query_dsl::methods::SelectDsl::select(
+                            #filter,
+                            #columns_type,
+                        )
+                    },
+                )
+            }
+            None => (filtered, quote! {}, filter),
+        };
+
+        let mut impl_generics = generics.clone();
+        impl_generics.params.insert(
+            0,
+            syn::GenericParam::Lifetime(syn::LifetimeDef::new(syn::Lifetime::new(
+                "'diesel_factories_con",
+                Span::call_site(),
+            ))),
+        );
+        impl_generics
+            .params
+            .push(syn::GenericParam::Type(syn::TypeParam::from(ident(
+                "DieselFactoriesFilter",
+            ))));
+
+        let impls = self
+            .connection_types()
+            .into_iter()
+            .map(|connection_type| {
+                quote! {
+                    impl#impl_generics diesel_factories::FindModel<
+                        DieselFactoriesFilter,
+                        &'diesel_factories_con #connection_type,
+                    > for #factory#generics
+                    where
+                        #table_path::table:
+                            diesel::query_dsl::methods::FilterDsl<DieselFactoriesFilter>,
+                        #select_bound
+                        #query:
+                            diesel::query_dsl::methods::LimitDsl
+                                + diesel::RunQueryDsl<#connection_type>,
+                        diesel::dsl::Limit<#query>:
+                            diesel::query_dsl::LoadQuery<#connection_type, #model_type>,
+                    {
+                        fn find_model(
+                            filter: DieselFactoriesFilter,
+                            con: &'diesel_factories_con #connection_type,
+                        ) -> diesel::result::QueryResult<#model_type> {
+                            use diesel::prelude::*;
+
+                            #select.first(con)
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            #(#impls)*
+        });
+    }
+
+    fn gen_versioned_factory_impl(&mut self) {
+        let since_migration = match &self.options.since_migration {
+            Some(since_migration) => since_migration,
+            None => {
+                if self.options.version.is_some() {
+                    panic!("`version` requires `since_migration` to also be set");
+                }
+                return;
+            }
+        };
+        let version = self.options.version.as_ref().unwrap_or(since_migration);
+
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::VersionedFactory for #factory#generics {
+                fn version() -> &'static str {
+                    #version
+                }
+
+                fn since_migration() -> &'static str {
+                    #since_migration
+                }
+
+                fn is_current(con: &Self::Connection) -> bool {
+                    diesel_factories::migration_applied(#since_migration, con)
+                }
+            }
+        });
+    }
+
+    fn gen_has_many_impls(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+
+        let impls = self
+            .options
+            .has_many
+            .iter()
+            .map(|has_many| {
+                let child: syn::Type = syn::parse_str(&has_many.factory).unwrap_or_else(|_| {
+                    panic!(
+                        "`has_many` factory must be a type, got {:?}",
+                        has_many.factory
+                    )
+                });
+                let child_path = child.path_without_arguments();
+                let association = &has_many.association;
+
+                let mut impl_generics = generics.clone();
+                for lifetime in child.lifetimes() {
+                    let already_declared = impl_generics
+                        .lifetimes()
+                        .any(|declared| declared.lifetime == lifetime);
+                    if !already_declared {
+                        impl_generics
+                            .params
+                            .push(syn::GenericParam::Lifetime(syn::LifetimeDef::new(lifetime)));
+                    }
+                }
+
+                quote! {
+                    impl#impl_generics diesel_factories::HasMany<#child> for #factory#generics {
+                        fn insert_children(
+                            parent: &Self::Model,
+                            count: usize,
+                            con: &Self::Connection,
+                        ) -> Vec<<#child as diesel_factories::Factory>::Model> {
+                            (0..count)
+                                .map(|_| {
+                                    let mut child = <#child_path>::default();
+                                    child.#association =
+                                        diesel_factories::IntoAssociation::into_association(parent);
+                                    diesel_factories::Factory::insert(child, con)
+                                })
+                                .collect()
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            #(#impls)*
+        });
+    }
+
+    fn gen_joins_impls(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+
+        let impls = self
+            .options
+            .joins
+            .iter()
+            .map(|joins| {
+                let child: syn::Type = syn::parse_str(&joins.factory).unwrap_or_else(|_| {
+                    panic!("`joins` factory must be a type, got {:?}", joins.factory)
+                });
+                let join_table = &joins.table;
+                let left = &joins.left;
+                let right = &joins.right;
+                let method_name = &joins.name;
+                let fixture = if self.backend(&self.connection_type()) == Backend::Pg {
+                    let join_table_name = join_table.segments.last().unwrap().value().ident.to_string();
+                    quote! {
+                        diesel_factories::fixtures::record_row(con, || {
+                            #join_table::table
+                                .filter(#join_table::#left.eq(
+                                    <Self as diesel_factories::Factory>::id_for_model(parent).clone(),
+                                ))
+                                .filter(#join_table::#right.eq(
+                                    <#child as diesel_factories::Factory>::id_for_model(child).clone(),
+                                ))
+                                .select(diesel_factories::fixtures::row_sql(#join_table_name))
+                                .first(con)
+                        })
+                        .unwrap_or_else(|err| {
+                            panic!("Failed to record row of {}: {}", stringify!(#join_table), err)
+                        });
+                    }
+                } else {
+                    quote! {
+                        diesel_factories::fixtures::assert_not_recording(con, stringify!(#factory));
+                    }
+                };
+
+                let child_lifetimes = child
+                    .lifetimes()
+                    .into_iter()
+                    .filter(|lifetime| {
+                        !generics
+                            .lifetimes()
+                            .any(|declared| declared.lifetime == *lifetime)
+                    })
+                    .collect::<Vec<_>>();
+                let mut impl_generics = generics.clone();
+                for lifetime in &child_lifetimes {
+                    impl_generics
+                        .params
+                        .push(syn::GenericParam::Lifetime(syn::LifetimeDef::new(
+                            lifetime.clone(),
+                        )));
+                }
+
+                quote! {
+                    impl#impl_generics diesel_factories::Joins<#child> for #factory#generics {
+                        fn insert_join_row(
+                            parent: &Self::Model,
+                            child: &<#child as diesel_factories::Factory>::Model,
+                            con: &Self::Connection,
+                        ) {
+                            use diesel::prelude::*;
+
+                            diesel::insert_into(#join_table::table)
+                                .values((
+                                    #join_table::#left.eq(
+                                        <Self as diesel_factories::Factory>::id_for_model(parent),
+                                    ),
+                                    #join_table::#right.eq(
+                                        <#child as diesel_factories::Factory>::id_for_model(child),
+                                    ),
+                                ))
+                                .execute(con)
+                                .unwrap_or_else(|err| {
+                                    panic!(
+                                        "Failed to insert into {}: {}",
+                                        stringify!(#join_table),
+                                        err
+                                    )
+                                });
+                            #fixture
+                        }
+                    }
+
+                    impl#generics #factory#generics {
+                        #[allow(missing_docs, dead_code)]
+                        pub fn #method_name<#(#child_lifetimes),*>(
+                            self,
+                            children: Vec<#child>,
+                        ) -> diesel_factories::WithJoins<Self, #child> {
+                            diesel_factories::WithJoins::new(self, children)
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            #(#impls)*
+        });
+    }
+
+    fn gen_default_impl(&mut self) {
+        let values = self
+            .struct_fields()
+            .map(|field| (field, self.default_field_value(field)))
+            .collect::<Vec<_>>();
+
+        let any_field_has_default = self.options.profile.is_some()
+            || self
+                .struct_fields()
+                .any(|field| self.has_default_attribute(field));
+        if !any_field_has_default {
+            return;
+        }
+
+        let missing = values
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(field, _)| field.ident.as_ref().unwrap().to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            panic!(
+                "`default`, `default_with` or `fake` is required on every non-association field to derive `Default`. Missing on: {}",
+                missing.join(", ")
+            );
+        }
+
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let fields = values
+            .into_iter()
+            .map(|(field, value)| {
+                let name = &field.ident;
+                let value = value.unwrap();
+                quote! { #name: #value }
+            })
+            .collect::<Vec<_>>();
+
+        let providers = self.options.profile.as_ref().map(|profile| {
+            quote! {
+                #[allow(unused_variables)]
+                let diesel_factories_providers = diesel_factories::profile::providers::<#profile>();
+            }
+        });
+
+        self.tokens.extend(quote! {
+            impl#generics Default for #factory#generics {
+                fn default() -> Self {
+                    #providers
+                    Self {
+                        #(#fields),*
+                    }
+                }
+            }
+        });
+    }
+
+    /// `from_model` with the `from_model` attribute, and `from_model_anonymized` if any field
+    /// uses `anonymize`. Both copy the fields from the model and point associations to the model's
+    /// foreign keys, and `from_model_anonymized` passes the `anonymize` fields through their
+    /// functions.
+    fn gen_from_model_constructors(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+
+        let any_field_is_anonymized = self
+            .struct_fields()
+            .any(|field| self.field_options(field).anonymize.is_some());
+        let from_model_anonymized = if any_field_is_anonymized {
+            let fields = self.fields_from_model(true);
+            quote! {
+                impl#generics #factory#generics {
+                    #[allow(missing_docs, dead_code)]
+                    pub fn from_model_anonymized(model: &#model_type) -> Self {
+                        Self {
+                            #(#fields),*
+                        }
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let from_model = if self.options.from_model {
+            let fields = self.fields_from_model(false);
+            quote! {
+                impl#generics #factory#generics {
+                    #[allow(missing_docs, dead_code)]
+                    pub fn from_model(model: &#model_type) -> Self {
+                        Self {
+                            #(#fields),*
+                        }
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        self.tokens.extend(from_model_anonymized);
+        self.tokens.extend(from_model);
+    }
+
+    /// The factory's fields copied from `model`, with associations pointing to the model's foreign
+    /// keys.
+    fn fields_from_model(&self, anonymized: bool) -> Vec<TokenStream> {
+        self.struct_fields()
+            .map(|field| {
+                let name = field.ident.as_ref().unwrap();
+                let options = self.field_options(field);
+                let value = if let Some((prefix, types)) = options.polymorphic() {
+                    let id_column = ident(&format!("{}_id", prefix));
+                    let type_column = ident(&format!("{}_type", prefix));
+                    let arms = types.iter().map(type_name).map(|type_name| {
+                        quote! {
+                            #type_name => diesel_factories::PolymorphicAssociation::model(
+                                #type_name,
+                                model.#id_column.clone(),
+                            ),
+                        }
+                    });
+                    quote! {
+                        match model.#type_column.as_str() {
+                            #(#arms)*
+                            other => panic!(
+                                "{} isn't one of the polymorphic association's types",
+                                other
+                            ),
+                        }
+                    }
+                } else if let Some(association) = field.ty.parse_association_type() {
+                    let foreign_key_field = options.foreign_key(field);
+                    if association.is_option {
+                        quote! {
+                            model.#foreign_key_field.clone().map(diesel_factories::Association::id)
+                        }
+                    } else {
+                        quote! { diesel_factories::Association::id(model.#foreign_key_field.clone()) }
+                    }
+                } else if let (Some(anonymize), true) = (options.anonymize, anonymized) {
+                    quote! { #anonymize(&model.#name) }
+                } else if options.map.is_some() {
+                    quote! {
+                        diesel_factories::copy_model_value::<Self, _>(stringify!(#name), &model.#name)
+                    }
+                } else if field.ty.is_field_value() {
+                    quote! { model.#name.clone().into() }
+                } else {
+                    quote! { model.#name.clone() }
+                };
+                quote! { #name: #value }
+            })
+            .collect()
+    }
+
+    fn has_default_attribute(&self, field: &syn::Field) -> bool {
+        let options = self.field_options(field);
+        options.default_value.is_some() || options.default_with.is_some() || options.fake.is_some()
+    }
+
+    fn default_field_value(&self, field: &syn::Field) -> Option<TokenStream> {
+        let options = self.field_options(field);
+
+        options
+            .default_value()
+            .or_else(|| match field.ty.parse_association_type() {
+                Some(ref association) if association.is_option => Some(quote! { None }),
+                Some(_) => Some(quote! { diesel_factories::Association::default() }),
+                None => None,
+            })
+            .or_else(|| {
+                let profile = self.options.profile.as_ref()?;
+                let factory = self.factory_name();
+                let name = &field.ident;
+                let ty = &field.ty;
+                Some(quote! {
+                    diesel_factories::profile::value::<#profile, #ty>(
+                        &diesel_factories_providers,
+                        stringify!(#factory),
+                        stringify!(#name),
+                    )
+                })
+            })
+    }
+
+    fn gen_build_factory_impl(&mut self) {
+        if !self.options.build {
+            return;
+        }
+
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+        let primary_key = self.primary_key();
+        let timestamps = self
+            .timestamp_columns()
+            .into_iter()
+            .map(|column| quote! { #column: std::time::SystemTime::now().into() });
+        let scope = self.scoped_column().map(|column| {
+            let message = format!("Building {} requires a scope, see `with_scope`", factory);
+            quote! {
+                #column: <Self as diesel_factories::ScopedFactory>::any_scope().expect(#message)
+            }
+        });
+        let fields = self
+            .struct_fields()
+            .map(|field| self.model_field_value(field))
+            .chain(timestamps)
+            .chain(scope)
+            .collect::<Vec<_>>();
+
+        // A client supplied key is kept rather than replaced by the fake id
+        let id = if self.key_field().is_some() {
+            quote! { let _ = id; }
+        } else {
+            quote! { let #primary_key = id; }
+        };
+        let id_field = if self.key_field().is_some() {
+            quote! {}
+        } else {
+            quote! { #primary_key, }
+        };
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::BuildFactory for #factory#generics {
+                fn build_with_id(self, id: Self::Id) -> Self::Model {
+                    #id
+                    #model_type {
+                        #id_field
+                        #(#fields),*
+                    }
+                }
+            }
+        });
+    }
+
+    fn gen_plan_impl(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let table_name = self.table_name();
+
+        let foreign_keys = self
+            .struct_fields()
+            .filter_map(|field| {
+                let name = field.ident.as_ref().unwrap();
+                if let Some((prefix, _)) = self.field_options(field).polymorphic() {
+                    let column = format!("{}_id", prefix);
+                    return Some(quote! { (#column, self.#name.plan_key(plan)) });
+                }
+
+                let association = field.ty.parse_association_type()?;
+                let column = self.field_options(field).foreign_key(field).to_string();
+                if association.is_option {
+                    Some(quote! {
+                        (
+                            #column,
+                            match &self.#name {
+                                Some(inner) => inner.plan_key(plan),
+                                None => diesel_factories::plan::PlannedKey::Null,
+                            },
+                        )
+                    })
+                } else {
+                    Some(quote! { (#column, self.#name.plan_key(plan)) })
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::plan::Plan for #factory#generics {
+                fn plan_into(&self, plan: &mut diesel_factories::plan::InsertPlan) -> usize {
+                    let foreign_keys = vec![#(#foreign_keys),*];
+                    plan.push(stringify!(#factory), #table_name, foreign_keys)
+                }
+            }
+        });
+    }
+
+    fn gen_scoped_factory_impl(&mut self) {
+        let column = match &self.options.scoped_column {
+            Some(column) => column.to_string(),
+            None => return,
+        };
+
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let scope_type = self
+            .options
+            .scope_type
+            .as_ref()
+            .map(|inner| quote! { #inner })
+            .unwrap_or_else(|| quote! { i32 });
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::ScopedFactory for #factory#generics {
+                type Scope = #scope_type;
+
+                fn scoped_column() -> &'static str {
+                    #column
+                }
+            }
+        });
+    }
+
+    fn gen_dry_run_impl(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let table_path = self.table_path();
+        let connection_type = self.connection_type();
+
+        let query = if self.no_fields() {
+            quote! { diesel::insert_into(table::table).default_values() }
+        } else {
+            let values = self.dry_run_values();
+            quote! { diesel::insert_into(table::table).values(( #(#values),* )) }
+        };
+
+        self.tokens.extend(quote! {
+            impl#generics diesel_factories::dry_run::DryRun for #factory#generics {
+                fn to_sql(&self) -> String {
+                    use #table_path::dsl::*;
+                    use #table_path as table;
+                    use diesel::prelude::*;
+
+                    let diesel_factories_factory = self;
+                    let diesel_factories_query = #query;
+                    diesel::debug_query::<<#connection_type as diesel::Connection>::Backend, _>(
+                        &diesel_factories_query,
+                    )
+                    .to_string()
+                }
+            }
+        });
+    }
+
+    /// `InsertWithAssociations`, along with `insert_with_associations` and the struct of
+    /// associated models it returns for factories with associations. Polymorphic associations
+    /// aren't included since their type isn't known.
+    fn gen_insert_with_associations(&mut self) {
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let model_type = self.model_type();
+        let connection_type = self.connection_type();
+
+        let associations = self
+            .struct_fields()
+            .filter(|field| self.field_options(field).polymorphic().is_none())
+            .filter_map(|field| Some((field, field.ty.parse_association_type()?)))
+            .collect::<Vec<_>>();
+        if associations.is_empty() {
+            self.tokens.extend(quote! {
+                impl#generics diesel_factories::InsertWithAssociations for #factory#generics {
+                    type Associations = ();
+
+                    fn insert_returning_associations(
+                        self,
+                        con: &#connection_type,
+                    ) -> diesel::result::QueryResult<(#model_type, ())> {
+                        Ok((diesel_factories::factory_v2::Insert::insert(self, con)?, ()))
+                    }
+                }
+            });
+            return;
+        }
+
+        let vis = &self.input.vis;
+        let struct_name = ident(&format!("{}Associations", factory));
+        let struct_doc = format!(
+            "The associated models inserted or loaded by `{}::insert_with_associations`.",
+            factory
+        );
+        let error = format!("Inserting {} with its associations failed: {{}}", factory);
+
+        let mut fields = Vec::new();
+        let mut inserts = Vec::new();
+        let mut names = Vec::new();
+        for (field, association) in associations {
+            let name = field.ident.as_ref().unwrap();
+            let nested_name = ident(&format!("{}_associations", name));
+            let model = &association.model;
+            // The struct has no lifetimes, and the associations don't depend on them
+            let other_factory = association
+                .factory_type
+                .extract_outermost_type()
+                .with_static_lifetime();
+            let nested = quote! {
+                Option<Box<<#other_factory as diesel_factories::InsertWithAssociations>::Associations>>
+            };
+            if association.is_option {
+                fields.push(quote! { pub #name: Option<#model> });
+                fields.push(quote! { pub #nested_name: #nested });
+                inserts.push(quote! {
+                    let (#name, #nested_name) = match self.#name.take() {
+                        Some(diesel_factories_association) => {
+                            let (diesel_factories_model, diesel_factories_nested) =
+                                diesel_factories_association.insert_model_with_associations(con)?;
+                            (Some(diesel_factories_model), diesel_factories_nested)
+                        }
+                        None => (None, None),
+                    };
+                    self.#name = #name
+                        .as_ref()
+                        .map(diesel_factories::Association::to_inserted);
+                });
+            } else {
+                fields.push(quote! { pub #name: #model });
+                fields.push(quote! { pub #nested_name: #nested });
+                inserts.push(quote! {
+                    let (#name, #nested_name) =
+                        self.#name.take().insert_model_with_associations(con)?;
+                    self.#name = diesel_factories::Association::to_inserted(&#name);
+                });
+            }
+            names.push(name.clone());
+            names.push(nested_name);
+        }
+
+        self.tokens.extend(quote! {
+            #[doc = #struct_doc]
+            ///
+            /// Associations inserted from factories have the models of their own associations in
+            /// the `_associations` fields, which are `None` for rows that already exist.
+            #[allow(missing_docs, missing_debug_implementations, dead_code)]
+            #vis struct #struct_name {
+                #(#fields),*
+            }
+
+            impl#generics diesel_factories::InsertWithAssociations for #factory#generics {
+                type Associations = #struct_name;
+
+                fn insert_returning_associations(
+                    mut self,
+                    con: &#connection_type,
+                ) -> diesel::result::QueryResult<(#model_type, #struct_name)> {
+                    #(#inserts)*
+                    let diesel_factories_model = diesel_factories::factory_v2::Insert::insert(self, con)?;
+                    Ok((diesel_factories_model, #struct_name { #(#names),* }))
+                }
+            }
+
+            impl#generics #factory#generics {
+                /// Insert the factory along with its associations, returning the associated models
+                /// as well.
+                #[allow(dead_code)]
+                pub fn insert_with_associations(
+                    self,
+                    con: &#connection_type,
+                ) -> (#model_type, #struct_name) {
+                    diesel_factories::InsertWithAssociations::insert_returning_associations(self, con)
+                        .unwrap_or_else(|err| panic!(#error, err))
+                }
+            }
+        });
+    }
+
+    /// Like `diesel_insert_values`, with the foreign keys of associations that aren't inserted yet
+    /// left unknown rather than inserting them.
+    fn dry_run_values(&self) -> Vec<TokenStream> {
+        let timestamps = self
+            .timestamp_columns()
+            .into_iter()
+            .map(|column| quote! { #column.eq(diesel::dsl::now) });
+
+        self.struct_fields()
+            .map(|field| {
+                let name = field.ident.as_ref().unwrap();
+                if let Some((prefix, _)) = self.field_options(field).polymorphic() {
+                    let id_column = ident(&format!("{}_id", prefix));
+                    let type_column = ident(&format!("{}_type", prefix));
+                    quote! {
+                        (
+                            #id_column.eq(diesel_factories_factory.#name.dry_run_key()),
+                            #type_column.eq(diesel_factories_factory.#name.type_name()),
+                        )
+                    }
+                } else if let Some(association) = field.ty.parse_association_type() {
+                    let foreign_key_field = self.field_options(field).foreign_key(field);
+                    if association.is_option {
+                        quote! {
+                            #foreign_key_field.eq(diesel_factories::dry_run::Key::nullable(
+                                diesel_factories_factory
+                                    .#name
+                                    .as_ref()
+                                    .map(|diesel_factories_association| {
+                                        diesel_factories_association.dry_run_key()
+                                    }),
+                            ))
+                        }
+                    } else {
+                        quote! {
+                            #foreign_key_field.eq(diesel_factories_factory.#name.dry_run_key())
+                        }
+                    }
+                } else {
+                    self.diesel_insert_value(field)
+                }
+            })
+            .chain(timestamps)
+            .chain(self.scoped_column().map(|column| {
+                quote! {
+                    #column.eq(<Self as diesel_factories::ScopedFactory>::dry_run_scope())
+                }
+            }))
+            .collect()
+    }
+
+    /// Only generated with the `inventory` feature, which `diesel-factories` enables along with its
+    /// own feature of the same name.
+    fn gen_registration(&mut self) {
+        if !cfg!(feature = "inventory") {
+            return;
+        }
+
+        let factory = self.factory_name();
+        let model_type = self.model_type();
+        let table_name = self.table_name();
+        let associations = self
+            .struct_fields()
+            .filter_map(|field| {
+                let association = field.ty.parse_association_type()?;
+                let column = self.field_options(field).foreign_key(field).to_string();
+                let other_factory = association
+                    .factory_type
+                    .extract_outermost_type()
+                    .ident
+                    .to_string();
+                Some(quote! { (#column, #other_factory) })
+            })
+            .collect::<Vec<_>>();
+        let polymorphic_associations = self
+            .struct_fields()
+            .filter_map(|field| {
+                let options = self.field_options(field);
+                let (prefix, types) = options.polymorphic()?;
+                let id_column = format!("{}_id", prefix);
+                let type_column = format!("{}_type", prefix);
+                let type_names = types.iter().map(type_name);
+                Some(quote! { (#id_column, #type_column, &[#(#type_names),*]) })
+            })
+            .collect::<Vec<_>>();
+
+        self.tokens.extend(quote! {
+            diesel_factories::registry::inventory::submit! {
+                diesel_factories::registry::RegisteredFactory {
+                    name: stringify!(#factory),
+                    module_path: module_path!(),
+                    model: stringify!(#model_type),
+                    table: #table_name,
+                    associations: &[#(#associations),*],
+                    polymorphic_associations: &[#(#polymorphic_associations),*],
+                }
+            }
+        });
+    }
+
+    fn model_field_value(&self, field: &syn::Field) -> TokenStream {
+        let name = field
+            .ident
+            .as_ref()
+            .unwrap_or_else(|| panic!("Factory can only be derived for named fields"));
+
+        let options = self.field_options(field);
+
+        if options.polymorphic().is_some() {
+            panic!("`build` is not supported with polymorphic associations");
+        }
+
+        if let Some(association) = field.ty.parse_association_type() {
+            let foreign_key_field = options.foreign_key(field);
+            if association.is_option {
+                quote! {
+                    #foreign_key_field: self.#name.map(|inner| inner.build_returning_id())
+                }
+            } else {
+                quote! {
+                    #foreign_key_field: self.#name.build_returning_id()
+                }
+            }
+        } else if let Some(map) = options.map {
+            let value = self.mapped_value(field, &map, quote! { self.#name });
+            quote! {
+                #name: #value
+            }
+        } else if field.ty.is_field_value() {
+            quote! {
+                #name: self.#name.into()
+            }
+        } else {
+            quote! {
+                #name: self.#name
+            }
+        }
+    }
+
+    /// Rows are built from `diesel_factories_factories`. Associations are inserted first, merging
+    /// the ones of the same type, and rows are then inserted with one statement where possible.
+    ///
+    /// With `ids_only` only the primary keys are returned, rather than the models.
+    fn insert_batch_code(&self, ids_only: bool, backend: Backend) -> TokenStream {
+        let factory = self.factory_name();
+        let table_name = self.table_name();
+        let model_type = self.model_type();
+        let associations = self.association_ids_code();
+        let (returning, loaded) = if ids_only {
+            let primary_key = self.primary_key();
+            (quote! { .returning(#primary_key) }, self.id_type())
+        } else {
+            let returning = self
+                .returning_columns()
+                .map(|columns| quote! { .returning(( #(#columns),* )) });
+            (returning.unwrap_or_default(), quote! { #model_type })
+        };
+
+        // Only generated with the `tracing` feature, which `diesel-factories` enables along with
+        // its own feature of the same name, so ids don't have to be `Debug` without it
+        let trace = |id: TokenStream| {
+            if cfg!(feature = "tracing") {
+                quote! { diesel_factories_insert_scope.inserted(#id); }
+            } else {
+                quote! {}
+            }
+        };
+        // Rows are rendered for fixtures by Postgres, see `diesel_factories::fixtures`
+        let primary_key = self.primary_key();
+        let fixture = |id: TokenStream| {
+            if backend == Backend::Pg {
+                quote! {
+                    diesel_factories::fixtures::record_row(con, || {
+                        table::table
+                            .filter(table::#primary_key.eq(#id.clone()))
+                            .select(diesel_factories::fixtures::row_sql(#table_name))
+                            .get_result(con)
+                    })
+                    .map_err(|err| diesel_factories_insert_scope.fail(err))?;
+                }
+            } else {
+                quote! {
+                    diesel_factories::fixtures::assert_not_recording(con, stringify!(#factory));
+                }
+            }
+        };
+        let record = if ids_only {
+            let trace = trace(quote! { diesel_factories_id });
+            let fixture = fixture(quote! { diesel_factories_id });
+            quote! {
+                for diesel_factories_id in &diesel_factories_loaded {
+                    diesel_factories::record_insert::<#model_type, _, _>(con, diesel_factories_id);
+                    #trace
+                    #fixture
+                }
+            }
+        } else {
+            let after_insert = self.after_insert_code();
+            let id = quote! {
+                <Self as diesel_factories::factory_v2::Factory>::id_for_model(diesel_factories_model)
+            };
+            let trace = trace(id.clone());
+            let fixture = fixture(id.clone());
+            quote! {
+                for diesel_factories_model in &diesel_factories_loaded {
+                    diesel_factories::record_insert::<#model_type, _, _>(con, #id);
+                    #trace
+                    #fixture
+                    #after_insert
+                }
+            }
+        };
+
+        let insert = if backend == Backend::Pg {
+            self.returning_insert_code(ids_only, returning, loaded)
+        } else {
+            self.without_returning_insert_code(ids_only, backend)
+        };
+
+        quote! {
+            if diesel_factories_factories.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let diesel_factories_insert_scope =
+                diesel_factories::InsertScope::enter(stringify!(#factory), #table_name);
+            diesel_factories::assert_inserts_allowed(stringify!(#factory));
+            #associations
+            let diesel_factories_loaded =
+                { #insert }.map_err(|err| diesel_factories_insert_scope.fail(err))?;
+            #record
+            Ok(diesel_factories_loaded)
+        }
+    }
+
+    /// Insert the rows using `RETURNING` to load them, unless the factory uses `reselect`.
+    fn returning_insert_code(
+        &self,
+        ids_only: bool,
+        returning: TokenStream,
+        loaded: TokenStream,
+    ) -> TokenStream {
+        if self.no_fields() {
+            quote! {
+                diesel_factories_factories
+                    .iter()
+                    .map(|_| {
+                        diesel::insert_into(table::table)
+                            .default_values()
+                            #returning
+                            .get_result::<#loaded>(con)
+                    })
+                    .collect::<diesel::result::QueryResult<Vec<_>>>()
+            }
+        } else {
+            let insert_rows = if self.options.reselect {
+                self.reselect_insert_code(ids_only)
+            } else if self.options.on_conflict.as_deref() == Some("do_nothing") {
+                self.do_nothing_insert_code(ids_only, returning, loaded)
+            } else if self.options.on_conflict.is_some() {
+                // One statement per row, since Postgres rejects a statement updating the same
+                // conflicting row twice
+                let on_conflict = self.on_conflict_code();
+                quote! {
+                    diesel_factories_values
+                        .into_iter()
+                        .map(|diesel_factories_values| {
+                            diesel::insert_into(table::table)
+                                .values(diesel_factories_values)
+                                #on_conflict
+                                #returning
+                                .get_result::<#loaded>(con)
+                        })
+                        .collect::<diesel::result::QueryResult<Vec<_>>>()
+                }
+            } else {
+                quote! {
+                    diesel::insert_into(table::table)
+                        .values(diesel_factories_values)
+                        #returning
+                        .get_results::<#loaded>(con)
+                }
+            };
+
+            let values = self.insert_values_code();
+            quote! {
+                #values
+                #insert_rows
+            }
+        }
+    }
+
+    /// Insert the rows one at a time with `ON CONFLICT DO NOTHING`. `RETURNING` returns nothing for
+    /// a conflicting row, so it's selected by the factory's `conflict_target` fields instead.
+    fn do_nothing_insert_code(
+        &self,
+        ids_only: bool,
+        returning: TokenStream,
+        loaded: TokenStream,
+    ) -> TokenStream {
+        let target = self.conflict_target();
+        // Filtering on the values that were inserted, so `map` and `sql_type` apply
+        let filters = target
+            .iter()
+            .map(|column| {
+                let field = self
+                    .struct_fields()
+                    .find(|field| field.ident.as_ref() == Some(column))
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "`on_conflict = \"do_nothing\"` selects conflicting rows by the `conflict_target` fields, but {} has no field `{}`",
+                            self.factory_name(),
+                            column
+                        )
+                    });
+                if field.ty.is_association_field() || field.ty.is_field_value() {
+                    panic!(
+                        "The `conflict_target` field `{}` of {} can't be an association or a `FieldValue`",
+                        column,
+                        self.factory_name()
+                    );
+                }
+                let value = self.diesel_insert_value(field);
+                quote! { .filter(#value) }
+            })
+            .collect::<Vec<_>>();
+
+        let select = if ids_only {
+            let primary_key = self.primary_key();
+            quote! { .select(#primary_key) }
+        } else {
+            self.returning_columns()
+                .map(|columns| quote! { .select(( #(#columns),* )) })
+                .unwrap_or_default()
+        };
+
+        quote! {
+            diesel_factories_factories
+                .iter()
+                .zip(diesel_factories_values)
+                .map(|(diesel_factories_factory, diesel_factories_values)| {
+                    let inserted = diesel::insert_into(table::table)
+                        .values(diesel_factories_values)
+                        .on_conflict(( #(#target),* ))
+                        .do_nothing()
+                        #returning
+                        .get_result::<#loaded>(con)
+                        .optional()?;
+                    match inserted {
+                        Some(inserted) => Ok(inserted),
+                        None => table::table
+                            #(#filters)*
+                            #select
+                            .first::<#loaded>(con),
+                    }
+                })
+                .collect::<diesel::result::QueryResult<Vec<_>>>()
+        }
+    }
+
+    /// Insert the rows one at a time, for backends without `RETURNING`. Rows are loaded again by
+    /// the key the factory supplies, or else by the key the database generated.
+    fn without_returning_insert_code(&self, ids_only: bool, backend: Backend) -> TokenStream {
+        if self.options.on_conflict.is_some() {
+            panic!("`on_conflict` is only supported on Postgres");
+        }
+        if self.options.reselect || self.key_field().is_some() {
+            let values = self.insert_values_code();
+            let insert_rows = self.reselect_insert_code(ids_only);
+            return quote! {
+                #values
+                #insert_rows
+            };
+        }
+
+        let primary_key = self.primary_key();
+        let id_type = self.id_type();
+        let last_insert_id = backend.last_insert_id();
+        let load = self.load_by_key_code(ids_only);
+        let load = quote! {
+            let diesel_factories_key = diesel::select(diesel::dsl::sql::<
+                <table::#primary_key as diesel::Expression>::SqlType,
+            >(#last_insert_id))
+            .get_result::<#id_type>(con)?;
+            #load
+        };
+
+        if self.no_fields() {
+            quote! {
+                diesel_factories_factories
+                    .iter()
+                    .map(|_| {
+                        diesel::insert_into(table::table)
+                            .default_values()
+                            .execute(con)?;
+                        #load
+                    })
+                    .collect::<diesel::result::QueryResult<Vec<_>>>()
+            }
+        } else {
+            let values = self.insert_values_code();
+            quote! {
+                #values
+                diesel_factories_values
+                    .into_iter()
+                    .map(|diesel_factories_values| {
+                        diesel::insert_into(table::table)
+                            .values(diesel_factories_values)
+                            .execute(con)?;
+                        #load
+                    })
+                    .collect::<diesel::result::QueryResult<Vec<_>>>()
+            }
+        }
+    }
+
+    /// Bind the values to insert for each of `diesel_factories_factories` to
+    /// `diesel_factories_values`.
+    fn insert_values_code(&self) -> TokenStream {
+        let values = self.diesel_insert_values();
+        quote! {
+            let diesel_factories_values = diesel_factories_factories
+                .iter()
+                .map(|diesel_factories_factory| -> diesel::result::QueryResult<_> {
+                    Ok(( #(#values),* ))
+                })
+                .collect::<diesel::result::QueryResult<Vec<_>>>()?;
+        }
+    }
+
+    /// Insert the association fields, grouped by type so each group is inserted together, and
+    /// bind an iterator over each field's ids to `diesel_factories_<field>_ids`. The associations
+    /// are taken out of the factories, leaving `Association::Latest`.
+    fn association_ids_code(&self) -> TokenStream {
+        let mut groups: Vec<(String, Vec<&syn::Field>)> = Vec::new();
+        for field in self.struct_fields() {
+            if field.ty.parse_association_type().is_none()
+                || self.field_options(field).polymorphic().is_some()
+            {
+                continue;
+            }
+
+            let key = field
+                .ty
+                .extract_outermost_non_optional()
+                .unwrap()
+                .into_token_stream()
+                .to_string();
+            match groups.iter_mut().find(|(group_key, _)| *group_key == key) {
+                Some((_, fields)) => fields.push(field),
+                None => groups.push((key, vec![field])),
+            }
+        }
+
+        if groups.is_empty() {
+            return quote! {};
+        }
+
+        let groups = groups.into_iter().map(|(_, fields)| {
+            let associations = fields.iter().map(|field| {
+                let name = &field.ident;
+                if field.ty.is_inside_option() {
+                    quote! {
+                        diesel_factories_factories
+                            .iter_mut()
+                            .map(|diesel_factories_factory| diesel_factories_factory.#name.take())
+                    }
+                } else {
+                    quote! {
+                        diesel_factories_factories
+                            .iter_mut()
+                            .map(|diesel_factories_factory| Some(diesel_factories_factory.#name.take()))
+                    }
+                }
+            });
+            let ids = fields.iter().map(|field| {
+                let ids = association_ids_ident(field);
+                quote! {
+                    let mut #ids = (&mut diesel_factories_ids)
+                        .take(diesel_factories_factories.len())
+                        .collect::<Vec<_>>()
+                        .into_iter();
+                }
+            });
+
+            quote! {
+                let mut diesel_factories_associations = Vec::new();
+                #(diesel_factories_associations.extend(#associations);)*
+                let mut diesel_factories_ids =
+                    diesel_factories::insert_associations(diesel_factories_associations, con)?
+                        .into_iter();
+                #(#ids)*
+            }
+        });
+
+        quote! {
+            let mut diesel_factories_factories = diesel_factories_factories;
+            #(#groups)*
         }
     }
 
-    fn build_derive_output(mut self) -> TokenStream {
-        self.gen_factory_methods_impl();
-        self.gen_builder_methods();
-        self.gen_set_association_traits();
+    /// Insert without `RETURNING` and load each row again by its client supplied key, for backends
+    /// like SQLite and MySQL.
+    fn reselect_insert_code(&self, ids_only: bool) -> TokenStream {
+        let primary_key = self.primary_key();
+        let key_field = self.key_field().unwrap_or_else(|| {
+            panic!(
+                "`reselect` requires a `{}` field to find the inserted row by",
+                primary_key
+            )
+        });
+        let key = match self.field_options(key_field).map {
+            Some(map) => quote! { #map(diesel_factories_factory.#primary_key.clone()) },
+            None => quote! { diesel_factories_factory.#primary_key.clone() },
+        };
+        let load = self.load_by_key_code(ids_only);
 
-        self.tokens
+        quote! {
+            let diesel_factories_keys = diesel_factories_factories
+                .iter()
+                .map(|diesel_factories_factory| #key)
+                .collect::<Vec<_>>();
+            diesel_factories_values
+                .into_iter()
+                .zip(diesel_factories_keys)
+                .map(|(diesel_factories_values, diesel_factories_key)| {
+                    diesel::insert_into(table::table)
+                        .values(diesel_factories_values)
+                        .execute(con)?;
+                    #load
+                })
+                .collect::<diesel::result::QueryResult<Vec<_>>>()
+        }
     }
 
-    fn gen_factory_methods_impl(&mut self) {
-        let factory = self.factory_name();
-        let generics = self.factory_generics();
-        let model_type = self.model_type();
-        let id_type = self.id_type();
-        let connection_type = self.connection_type();
-        let table_path = self.table_path();
-        let insert_code = self.insert_code();
-
-        self.tokens.extend(quote! {
-            impl#generics diesel_factories::Factory for #factory#generics {
-                type Model = #model_type;
-                type Id = #id_type;
-                type Connection = #connection_type;
+    /// Load the row keyed by `diesel_factories_key`, or with `ids_only` just return the key.
+    fn load_by_key_code(&self, ids_only: bool) -> TokenStream {
+        if ids_only {
+            return quote! { Ok(diesel_factories_key) };
+        }
 
-                fn insert(self, con: &Self::Connection) -> Self::Model {
-                    use #table_path::dsl::*;
-                    use #table_path as table;
-                    use diesel::prelude::*;
+        let model_type = self.model_type();
+        let select = self
+            .returning_columns()
+            .map(|columns| quote! { .select(( #(#columns),* )) });
+        quote! {
+            table::table
+                .find(diesel_factories_key)
+                #select
+                .get_result::<#model_type>(con)
+        }
+    }
 
-                    #insert_code
-                }
+    /// The `returning` columns, which are also selected when loading models in other ways.
+    fn returning_columns(&self) -> Option<Vec<syn::Ident>> {
+        self.options.returning.as_ref().map(|columns| {
+            columns
+                .split(',')
+                .map(|column| ident(column.trim()))
+                .collect()
+        })
+    }
 
-                fn id_for_model(model: &Self::Model) -> &Self::Id {
-                    &model.id
+    fn on_conflict_code(&self) -> TokenStream {
+        let mode = match &self.options.on_conflict {
+            Some(mode) => mode,
+            None => {
+                if self.options.conflict_target.is_some() {
+                    panic!("`conflict_target` requires `on_conflict` to also be set");
                 }
+                return quote! {};
             }
+        };
+
+        let target = self.conflict_target();
+
+        // `do_nothing` is inserted by `do_nothing_insert_code`
+        let columns = match mode.as_str() {
+            "do_update" => self
+                .struct_fields()
+                .flat_map(|field| self.column_names(field))
+                .collect::<Vec<_>>(),
+            other => panic!(
+                "`on_conflict` must be \"do_nothing\" or \"do_update\", got {:?}",
+                other
+            ),
+        };
+
+        let assignments = columns.iter().map(|column| {
+            quote! { #column.eq(diesel::pg::upsert::excluded(#column)) }
         });
+
+        quote! {
+            .on_conflict(( #(#target),* ))
+            .do_update()
+            .set(( #(#assignments),* ))
+        }
     }
 
-    fn insert_code(&self) -> TokenStream {
-        let values = self.diesel_insert_values();
+    fn conflict_target(&self) -> Vec<syn::Ident> {
+        self.options
+            .conflict_target
+            .as_ref()
+            .unwrap_or_else(|| panic!("`on_conflict` requires `conflict_target` to also be set"))
+            .split(',')
+            .map(|column| ident(column.trim()))
+            .collect()
+    }
 
-        if self.no_fields() {
-            quote! {
-                diesel::insert_into(table::table)
-                    .default_values()
-                    .get_result::<Self::Model>(con)
-                    .unwrap()
-            }
+    fn column_names(&self, field: &syn::Field) -> Vec<syn::Ident> {
+        let name = field.ident.as_ref().unwrap();
+        let options = self.field_options(field);
+        if let Some((prefix, _)) = options.polymorphic() {
+            vec![
+                ident(&format!("{}_id", prefix)),
+                ident(&format!("{}_type", prefix)),
+            ]
+        } else if field.ty.is_association_field() {
+            vec![options.foreign_key(field)]
         } else {
-            quote! {
-                let values = ( #(#values),* );
-                diesel::insert_into(table::table)
-                    .values(values)
-                    .get_result::<Self::Model>(con)
-                    .unwrap()
-            }
+            vec![name.clone()]
+        }
+    }
+
+    fn after_insert_code(&self) -> TokenStream {
+        match &self.options.after_insert {
+            Some(hook) => quote! { #hook(diesel_factories_model, con); },
+            None => quote! {},
         }
     }
 
@@ -255,6 +2050,41 @@ impl DeriveData {
         })
     }
 
+    fn gen_with_traits(&mut self) {
+        let traits = match &self.options.traits {
+            Some(traits) => &traits.0,
+            None => return,
+        };
+
+        let factory = self.factory_name();
+        let generics = self.factory_generics();
+        let names = traits
+            .iter()
+            .map(|path| path.into_token_stream().to_string())
+            .collect::<Vec<_>>();
+        let known = names.join(", ");
+        let arms = traits.iter().zip(&names).map(|(method, name)| {
+            quote! { #name => factory.#method(), }
+        });
+
+        self.tokens.extend(quote! {
+            impl#generics #factory#generics {
+                #[allow(missing_docs, dead_code)]
+                pub fn with_traits(self, traits: &[&str]) -> Self {
+                    traits.iter().fold(self, |factory, name| match *name {
+                        #(#arms)*
+                        other => panic!(
+                            "{} has no trait named {:?}. Known traits: {}",
+                            stringify!(#factory),
+                            other,
+                            #known
+                        ),
+                    })
+                }
+            }
+        });
+    }
+
     fn factory_name(&self) -> &syn::Ident {
         &self.input.ident
     }
@@ -271,73 +2101,260 @@ impl DeriveData {
             .unwrap_or_else(|| quote! { i32 })
     }
 
-    fn connection_type(&self) -> TokenStream {
+    fn primary_key(&self) -> syn::Ident {
         self.options
-            .connection
-            .as_ref()
-            .map(|inner| quote! { #inner })
-            .unwrap_or_else(|| quote! { diesel::pg::PgConnection })
+            .primary_key
+            .clone()
+            .unwrap_or_else(|| ident("id"))
+    }
+
+    /// The factory field holding a client supplied primary key, if there is one.
+    fn key_field(&self) -> Option<&syn::Field> {
+        let primary_key = self.primary_key();
+        self.struct_fields()
+            .find(|field| field.ident.as_ref() == Some(&primary_key))
+    }
+
+    fn connection_type(&self) -> TokenStream {
+        self.connection_types().remove(0)
+    }
+
+    /// The `connection` types whose backends are enabled, see `Backend::is_enabled`. All of them
+    /// if none are, provided the factory is gated with `cfg`.
+    fn connection_types(&self) -> Vec<TokenStream> {
+        let connection_types = if self.options.connection.is_empty() {
+            vec![quote! { diesel::pg::PgConnection }]
+        } else {
+            self.options
+                .connection
+                .iter()
+                .map(|inner| quote! { #inner })
+                .collect()
+        };
+
+        let enabled = connection_types
+            .iter()
+            .filter(|connection_type| self.backend(connection_type).is_enabled())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !enabled.is_empty() {
+            enabled
+        } else if self.options.cfg.is_some() {
+            connection_types
+        } else {
+            panic!(
+                "None of the backends of {}'s connections are enabled by the `postgres`, `mysql` and `sqlite` features of diesel-factories. Enable one, or gate the factory with `cfg`",
+                self.factory_name()
+            )
+        }
+    }
+
+    /// The backend of a connection type, from the `backend` attribute or else the type's name.
+    /// Connection types that aren't recognized are assumed to support `RETURNING`.
+    fn backend(&self, connection_type: &TokenStream) -> Backend {
+        if let Some(backend) = &self.options.backend {
+            return match backend.as_str() {
+                "pg" => Backend::Pg,
+                "mysql" => Backend::Mysql,
+                "sqlite" => Backend::Sqlite,
+                other => panic!(
+                    "`backend` must be \"pg\", \"mysql\" or \"sqlite\", got {:?}",
+                    other
+                ),
+            };
+        }
+
+        let connection_type = connection_type.to_string();
+        if connection_type.ends_with("MysqlConnection") {
+            Backend::Mysql
+        } else if connection_type.ends_with("SqliteConnection") {
+            Backend::Sqlite
+        } else {
+            Backend::Pg
+        }
     }
 
     fn table_path(&self) -> &syn::Path {
         &self.options.table
     }
 
+    /// Name of the table, without the schema module path.
+    fn table_name(&self) -> String {
+        self.table_path()
+            .segments
+            .last()
+            .unwrap()
+            .value()
+            .ident
+            .to_string()
+    }
+
     fn factory_generics(&self) -> &syn::Generics {
         &self.input.generics
     }
 
-    fn struct_fields(&self) -> syn::punctuated::Iter<syn::Field> {
-        match &self.input.data {
-            syn::Data::Union(_) => panic!("Factory can only be derived on structs"),
-            syn::Data::Enum(_) => panic!("Factory can only be derived on structs"),
-            syn::Data::Struct(data) => match &data.fields {
-                syn::Fields::Named(named) => named.named.iter(),
-                syn::Fields::Unit => {
-                    panic!("Factory can only be derived on structs with named fields")
-                }
-                syn::Fields::Unnamed(_) => {
-                    panic!("Factory can only be derived on structs with named fields")
-                }
-            },
-        }
+    fn struct_fields(&self) -> syn::punctuated::Iter<'_, syn::Field> {
+        named_fields(&self.input)
+    }
+
+    fn field_options(&self, field: &syn::Field) -> FieldOptions {
+        field_options(field)
     }
 
     fn no_fields(&self) -> bool {
         self.struct_fields().count() == 0
+            && self.timestamp_columns().is_empty()
+            && self.scoped_column().is_none()
+    }
+
+    /// The `scoped_column`, unless the factory has a field for it.
+    fn scoped_column(&self) -> Option<&syn::Ident> {
+        self.options.scoped_column.as_ref().filter(|column| {
+            self.struct_fields()
+                .all(|field| field.ident.as_ref() != Some(*column))
+        })
+    }
+
+    /// The `timestamps` columns, except those the factory has a field for.
+    fn timestamp_columns(&self) -> Vec<&syn::Ident> {
+        let columns = match &self.options.timestamps {
+            Some(Timestamps(columns)) => columns,
+            None => return Vec::new(),
+        };
+        columns
+            .iter()
+            .filter(|column| {
+                self.struct_fields()
+                    .all(|field| field.ident.as_ref() != Some(*column))
+            })
+            .collect()
     }
 
     fn diesel_insert_values(&self) -> Vec<TokenStream> {
+        let timestamps = self
+            .timestamp_columns()
+            .into_iter()
+            .map(|column| quote! { #column.eq(diesel::dsl::now) });
+        let scope = self.scoped_column().map(|column| {
+            quote! { #column.eq(<Self as diesel_factories::ScopedFactory>::scope(con)) }
+        });
+
         self.struct_fields()
             .map(|field| self.diesel_insert_value(field))
+            .chain(timestamps)
+            .chain(scope)
             .collect()
     }
 
+    /// A field's value passed through its `map` function, unless `from_model` copied it from a model
+    /// whose value was already mapped.
+    fn mapped_value(&self, field: &syn::Field, map: &syn::Path, value: TokenStream) -> TokenStream {
+        let copies_models = self.options.from_model
+            || self
+                .struct_fields()
+                .any(|field| self.field_options(field).anonymize.is_some());
+        if !copies_models {
+            return quote! { #map(#value) };
+        }
+
+        let name = field.ident.as_ref().unwrap();
+        quote! {{
+            let diesel_factories_value = #value;
+            if diesel_factories::is_copied_model_value::<Self, _>(
+                stringify!(#name),
+                &diesel_factories_value,
+            ) {
+                diesel_factories_value
+            } else {
+                #map(diesel_factories_value)
+            }
+        }}
+    }
+
     fn diesel_insert_value(&self, field: &syn::Field) -> TokenStream {
         let name = field
             .ident
             .as_ref()
             .unwrap_or_else(|| panic!("Factory can only be derived for named fields"));
 
-        if let Some(association) = field.ty.parse_association_type() {
-            let foreign_key_field = ident(&format!("{}_id", name));
+        let options = self.field_options(field);
+
+        if options.foreign_key.is_some() {
+            if options.polymorphic.is_some() {
+                panic!("`foreign_key` can't be used with `polymorphic`, which sets `<prefix>_id`");
+            }
+            if !field.ty.is_association_field() {
+                panic!("`foreign_key` is only supported on association fields");
+            }
+        }
+
+        if let Some((prefix, types)) = options.polymorphic() {
+            let id_column = ident(&format!("{}_id", prefix));
+            let type_column = ident(&format!("{}_type", prefix));
+            let type_names = types.iter().map(type_name);
+            quote! {
+                (
+                    #id_column.eq(
+                        diesel_factories_factory.#name.insert_returning_id(con, &[#(#type_names),*])?
+                    ),
+                    #type_column.eq(diesel_factories_factory.#name.type_name()),
+                )
+            }
+        } else if let Some(association) = field.ty.parse_association_type() {
+            if options.map.is_some() {
+                panic!("`map` is not supported on association fields");
+            }
+            if options.sql_type.is_some() {
+                panic!("`sql_type` is not supported on association fields");
+            }
+
+            let foreign_key_field = options.foreign_key(field);
+            let ids = association_ids_ident(field);
             if association.is_option {
                 quote! {
-                    {
-                        let value = self.#name.map(|inner| {
-                            inner.insert_returning_id(con)
-                        });
-                        #foreign_key_field.eq(value)
-                    }
+                    #foreign_key_field.eq(#ids.next().unwrap())
                 }
             } else {
                 quote! {
-                    #foreign_key_field.eq(self.#name.insert_returning_id(con))
+                    #foreign_key_field.eq(#ids.next().unwrap().unwrap())
                 }
             }
+        } else if field.ty.is_field_value() {
+            if options.map.is_some() {
+                panic!("`map` is not supported on `FieldValue` fields");
+            }
+            if options.sql_type.is_some() {
+                panic!("`sql_type` is not supported on `FieldValue` fields");
+            }
+
+            // `None` leaves the column out, so the database default is used
+            quote! {
+                diesel_factories_factory
+                    .#name
+                    .insert_value()
+                    .map(|diesel_factories_value| #name.eq(diesel_factories_value))
+            }
+        } else if let Some(sql_type) = options.sql_type {
+            let value = quote! { diesel_factories_factory.#name.clone() };
+            let value = match options.map {
+                Some(map) => self.mapped_value(field, &map, value),
+                None => value,
+            };
+            quote! {
+                #name.eq(diesel::expression::AsExpression::<#sql_type>::as_expression(#value))
+            }
+        } else if let Some(map) = options.map {
+            let value = self.mapped_value(
+                field,
+                &map,
+                quote! { diesel_factories_factory.#name.clone() },
+            );
+            quote! {
+                #name.eq(#value)
+            }
         } else {
             quote! {
-                #name.eq(&self.#name)
+                #name.eq(&diesel_factories_factory.#name)
             }
         }
     }
@@ -351,17 +2368,199 @@ impl DeriveData {
     fn builder_method(&self, field: &syn::Field) -> Option<TokenStream> {
         let name = &field.ident;
         let ty = &field.ty;
+        let method_name = self.field_options(field).builder_name(field)?;
 
         if field.ty.is_association_field() {
-            None
+            self.association_builder_method(field)
+        } else if field.ty.is_container_field() {
+            Some(quote! {
+                #[allow(missing_docs, dead_code)]
+                pub fn #method_name<T: diesel_factories::IntoContainer<#ty>>(mut self, t: T) -> Self {
+                    self.#name = t.into_container();
+                    self
+                }
+            })
         } else {
+            let polymorphic_methods = self.polymorphic_builder_methods(field, &method_name);
             Some(quote! {
                 #[allow(missing_docs, dead_code)]
-                pub fn #name<T: Into<#ty>>(mut self, t: T) -> Self {
+                pub fn #method_name<T: Into<#ty>>(mut self, t: T) -> Self {
                     self.#name = t.into();
                     self
                 }
+
+                #(#polymorphic_methods)*
+            })
+        }
+    }
+
+    fn polymorphic_builder_methods(
+        &self,
+        field: &syn::Field,
+        builder_name: &syn::Ident,
+    ) -> Vec<TokenStream> {
+        use heck::SnakeCase;
+
+        let name = field.ident.as_ref().unwrap();
+        let options = self.field_options(field);
+        let primary_key = options.polymorphic_key();
+        let types = match options.polymorphic() {
+            Some((_, types)) => types.to_vec(),
+            None => return Vec::new(),
+        };
+
+        types
+            .iter()
+            .map(|model_type| {
+                let type_name = type_name(model_type);
+                let method_name = ident(&format!("{}_{}", builder_name, type_name.to_snake_case()));
+                quote! {
+                    #[allow(missing_docs, dead_code)]
+                    pub fn #method_name(mut self, model: &#model_type) -> Self {
+                        self.#name = diesel_factories::PolymorphicAssociation::model(
+                            #type_name,
+                            model.#primary_key.clone(),
+                        );
+                        self
+                    }
+                }
             })
+            .collect()
+    }
+
+    fn association_builder_method(&self, field: &syn::Field) -> Option<TokenStream> {
+        let name = field.ident.as_ref()?;
+        let builder_name = self.field_options(field).builder_name(field)?;
+        let association = field.ty.parse_association_type()?;
+        let other_factory = &association.factory_type;
+        let method_name = ident(&format!("{}_with", builder_name));
+        let latest_method_name = ident(&format!("{}_latest", builder_name));
+        let sampled_method_name = ident(&format!("{}_sampled_from", builder_name));
+        let weighted_method_name = ident(&format!("{}_sampled_from_weighted", builder_name));
+        let id_method_name = ident(&format!("{}_id", builder_name));
+        let strategy_method_name = ident(&format!("{}_strategy", builder_name));
+
+        let model_type = field.ty.association_type_arguments()?[0];
+        let lifetime = association_lifetime(&field.ty)?;
+        let depth_method = self.association_depth_method(field, &builder_name, &association);
+
+        let (new_value, latest_value, sampled_value, id_value, strategy_value) =
+            if association.is_option {
+                (
+                    quote! {
+                        Some(match self.#name {
+                            Some(diesel_factories_association) => {
+                                diesel_factories_association.map_factory(F::default_of, f)
+                            }
+                            None => diesel_factories::Association::new_factory(f(F::default_of())),
+                        })
+                    },
+                    quote! { Some(diesel_factories::Association::Latest) },
+                    quote! { Some(diesel_factories::Association::Model(model)) },
+                    quote! { Some(diesel_factories::Association::id(id)) },
+                    quote! { Some(strategy.apply(self.#name)) },
+                )
+            } else {
+                (
+                    quote! { self.#name.map_factory(F::default_of, f) },
+                    quote! { diesel_factories::Association::Latest },
+                    quote! { diesel_factories::Association::Model(model) },
+                    quote! { diesel_factories::Association::id(id) },
+                    quote! { strategy.apply(Some(self.#name)) },
+                )
+            };
+
+        Some(quote! {
+            #[allow(missing_docs, dead_code)]
+            pub fn #method_name<F>(mut self, f: F) -> Self
+            where
+                F: FnOnce(#other_factory) -> #other_factory
+                    + diesel_factories::DefaultOf<#other_factory>,
+            {
+                self.#name = #new_value;
+                self
+            }
+
+            #[allow(missing_docs, dead_code)]
+            pub fn #latest_method_name(mut self) -> Self {
+                self.#name = #latest_value;
+                self
+            }
+
+            #[allow(missing_docs, dead_code)]
+            pub fn #sampled_method_name(mut self, models: &#lifetime [#model_type]) -> Self {
+                let model = diesel_factories::random::choose(models);
+                self.#name = #sampled_value;
+                self
+            }
+
+            #[allow(missing_docs, dead_code)]
+            pub fn #weighted_method_name(mut self, models: &[(&#lifetime #model_type, u32)]) -> Self {
+                let model = *diesel_factories::random::choose_weighted(models);
+                self.#name = #sampled_value;
+                self
+            }
+
+            #[allow(missing_docs, dead_code)]
+            pub fn #id_method_name(
+                mut self,
+                id: <#other_factory as diesel_factories::factory_v2::Factory>::Id,
+            ) -> Self {
+                self.#name = #id_value;
+                self
+            }
+
+            #[allow(missing_docs, dead_code)]
+            pub fn #strategy_method_name<S>(mut self, strategy: S) -> Self
+            where
+                S: diesel_factories::AssociationStrategy<#lifetime, #model_type, #other_factory>,
+            {
+                self.#name = #strategy_value;
+                self
+            }
+
+            #depth_method
+        })
+    }
+
+    /// `<association>_depth` for associations to the factory's own type, setting the association
+    /// to a chain of new factories, each associated with the next, such as a category's
+    /// ancestors. Such associations have to be optional, since every factory would otherwise
+    /// need another one. The factories are built with a function given to the method, so the
+    /// factory doesn't have to be `Default`.
+    fn association_depth_method(
+        &self,
+        field: &syn::Field,
+        builder_name: &syn::Ident,
+        association: &Association,
+    ) -> TokenStream {
+        let factory = self.factory_name();
+        if !association.is_to(factory) {
+            return quote! {};
+        }
+        let name = field.ident.as_ref().unwrap();
+        if !association.is_option {
+            panic!(
+                "The association `{}` to {} itself must be an `Option`",
+                name, factory
+            );
+        }
+
+        let method_name = ident(&format!("{}_depth", builder_name));
+        quote! {
+            #[allow(missing_docs, dead_code)]
+            pub fn #method_name<B>(mut self, depth: usize, mut base: B) -> Self
+            where
+                B: FnMut() -> Self,
+            {
+                self.#name = (0..depth).fold(None, |diesel_factories_next, _| {
+                    Some(diesel_factories::Association::new_factory(Self {
+                        #name: diesel_factories_next,
+                        ..base()
+                    }))
+                });
+                self
+            }
         }
     }
 
@@ -386,6 +2585,7 @@ impl DeriveData {
             let factory = self.factory_name();
             let field_name = field.ident.as_ref().expect("field without name");
             let camel_field_name = field_name.to_string().to_camel_case();
+            let method_name = self.field_options(field).builder_name(field)?;
 
             let association = field.ty.parse_association_type().unwrap_or_else(|| {
                 use std::fmt::Write;
@@ -414,10 +2614,18 @@ impl DeriveData {
                 other_factory_without_lifetime, factory, camel_field_name
             ));
 
+            // Factories without a lifetime hold `'static` associations
+            let (impl_lifetime, lifetime, factory_type) =
+                if self.factory_generics().lifetimes().next().is_some() {
+                    (quote! { <'z> }, quote! { 'z }, quote! { #factory<'z> })
+                } else {
+                    (quote! {}, quote! { 'static }, quote! { #factory })
+                };
+
             let model_impl = if association.is_option {
                 quote! {
-                    impl<'z> #trait_name<Option<&'z #model>> for #factory<'z> {
-                        fn #field_name(mut self, t: Option<&'z #model>) -> Self {
+                    impl#impl_lifetime #trait_name<Option<&#lifetime #model>> for #factory_type {
+                        fn #method_name(mut self, t: Option<&#lifetime #model>) -> Self {
                             self.#field_name = t.map(|k| diesel_factories::Association::new_model(k));
                             self
                         }
@@ -425,8 +2633,8 @@ impl DeriveData {
                 }
             } else {
                 quote! {
-                    impl<'z> #trait_name<&'z #model> for #factory<'z> {
-                        fn #field_name(mut self, t: &'z #model) -> Self {
+                    impl#impl_lifetime #trait_name<&#lifetime #model> for #factory_type {
+                        fn #method_name(mut self, t: &#lifetime #model) -> Self {
                             self.#field_name = diesel_factories::Association::new_model(t);
                             self
                         }
@@ -434,10 +2642,30 @@ impl DeriveData {
                 }
             };
 
+            let owned_impl = if association.is_option {
+                quote! {
+                    impl#impl_lifetime #trait_name<Option<std::sync::Arc<#model>>> for #factory_type {
+                        fn #method_name(mut self, t: Option<std::sync::Arc<#model>>) -> Self {
+                            self.#field_name = t.map(|k| diesel_factories::Association::new_owned(k));
+                            self
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    impl#impl_lifetime #trait_name<std::sync::Arc<#model>> for #factory_type {
+                        fn #method_name(mut self, t: std::sync::Arc<#model>) -> Self {
+                            self.#field_name = diesel_factories::Association::new_owned(t);
+                            self
+                        }
+                    }
+                }
+            };
+
             let factory_impl = if association.is_option {
                 quote! {
-                    impl<'z> #trait_name<Option<#other_factory>> for #factory<'z> {
-                        fn #field_name(mut self, t: Option<#other_factory>) -> Self {
+                    impl#impl_lifetime #trait_name<Option<#other_factory>> for #factory_type {
+                        fn #method_name(mut self, t: Option<#other_factory>) -> Self {
                             self.#field_name = t.map(|k| diesel_factories::Association::new_factory(k));
                             self
                         }
@@ -445,8 +2673,8 @@ impl DeriveData {
                 }
             } else {
                 quote! {
-                    impl<'z> #trait_name<#other_factory> for #factory<'z> {
-                        fn #field_name(mut self, t: #other_factory) -> Self {
+                    impl#impl_lifetime #trait_name<#other_factory> for #factory_type {
+                        fn #method_name(mut self, t: #other_factory) -> Self {
                             self.#field_name = diesel_factories::Association::new_factory(t);
                             self
                         }
@@ -454,15 +2682,39 @@ impl DeriveData {
                 }
             };
 
+            let shared_impl = if association.is_option {
+                quote! {
+                    impl#impl_lifetime #trait_name<Option<diesel_factories::Shared<#model, #other_factory>>> for #factory_type {
+                        fn #method_name(mut self, t: Option<diesel_factories::Shared<#model, #other_factory>>) -> Self {
+                            self.#field_name = t.map(|k| diesel_factories::Association::new_shared(k));
+                            self
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    impl#impl_lifetime #trait_name<diesel_factories::Shared<#model, #other_factory>> for #factory_type {
+                        fn #method_name(mut self, t: diesel_factories::Shared<#model, #other_factory>) -> Self {
+                            self.#field_name = diesel_factories::Association::new_shared(t);
+                            self
+                        }
+                    }
+                }
+            };
+
             Some(quote! {
                 #[allow(missing_docs, dead_code)]
                 pub trait #trait_name<T> {
-                    fn #field_name(self, t: T) -> Self;
+                    fn #method_name(self, t: T) -> Self;
                 }
 
                 #model_impl
 
                 #factory_impl
+
+                #shared_impl
+
+                #owned_impl
             })
         } else {
             None
@@ -470,12 +2722,119 @@ impl DeriveData {
     }
 }
 
+fn association_lifetime(ty: &syn::Type) -> Option<&syn::Lifetime> {
+    let segment = ty.extract_outermost_non_optional()?;
+    let args = if_let_or_none!(syn::PathArguments::AngleBracketed, &segment.arguments);
+    args.args.iter().find_map(|arg| match arg {
+        syn::GenericArgument::Lifetime(lifetime) => Some(lifetime),
+        _ => None,
+    })
+}
+
+fn named_fields(input: &DeriveInput) -> syn::punctuated::Iter<'_, syn::Field> {
+    match &input.data {
+        syn::Data::Union(_) => panic!("Factory can only be derived on structs"),
+        syn::Data::Enum(_) => panic!("Factory can only be derived on structs"),
+        syn::Data::Struct(data) => match &data.fields {
+            syn::Fields::Named(named) => named.named.iter(),
+            syn::Fields::Unit => panic!("Factory can only be derived on structs with named fields"),
+            syn::Fields::Unnamed(_) => {
+                panic!("Factory can only be derived on structs with named fields")
+            }
+        },
+    }
+}
+
+fn field_options(field: &syn::Field) -> FieldOptions {
+    match FieldOptions::from_field(field) {
+        Ok(options) => options,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+fn association_ids_ident(field: &syn::Field) -> syn::Ident {
+    ident(&format!(
+        "diesel_factories_{}_ids",
+        field.ident.as_ref().unwrap()
+    ))
+}
+
 fn ident(s: &str) -> syn::Ident {
     syn::Ident::new(s, Span::call_site())
 }
 
+/// Put `#[cfg(<predicate>)]` on each of the generated items.
+fn gate_items(tokens: TokenStream, predicate: &str) -> TokenStream {
+    let predicate = predicate
+        .parse::<TokenStream>()
+        .unwrap_or_else(|err| panic!("Invalid `cfg` {:?}: {:?}", predicate, err));
+    let file = syn::parse2::<syn::File>(tokens).expect("Generated code isn't a list of items");
+    let items = file
+        .items
+        .iter()
+        .map(|item| quote! { #[cfg(#predicate)] #item })
+        .collect::<Vec<_>>();
+    quote! {
+        #(#items)*
+    }
+}
+
+/// The name stored in a polymorphic association's type column for a model type.
+fn type_name(model_type: &syn::Path) -> String {
+    model_type
+        .segments
+        .last()
+        .unwrap()
+        .value()
+        .ident
+        .to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Pg,
+    Mysql,
+    Sqlite,
+}
+
+impl Backend {
+    /// SQL returning the key generated by the connection's last insert, for backends without
+    /// `RETURNING`.
+    fn last_insert_id(self) -> &'static str {
+        match self {
+            Backend::Pg => panic!("Postgres inserts use `RETURNING`"),
+            Backend::Mysql => "LAST_INSERT_ID()",
+            Backend::Sqlite => "last_insert_rowid()",
+        }
+    }
+
+    /// Whether code is generated for connections of the backend. `diesel-factories` enables the
+    /// features along with its own `postgres`, `mysql` and `sqlite` features. With none of them
+    /// enabled every backend is, so factories work without picking features.
+    fn is_enabled(self) -> bool {
+        let enabled = match self {
+            Backend::Pg => cfg!(feature = "postgres"),
+            Backend::Mysql => cfg!(feature = "mysql"),
+            Backend::Sqlite => cfg!(feature = "sqlite"),
+        };
+        enabled
+            || !(cfg!(feature = "postgres") || cfg!(feature = "mysql") || cfg!(feature = "sqlite"))
+    }
+}
+
 struct Association {
     is_option: bool,
     model: proc_macro2::TokenStream,
     factory: proc_macro2::TokenStream,
+    factory_type: syn::Type,
+}
+
+impl Association {
+    /// Whether the association is to the factory it's on, such as a parent category.
+    fn is_to(&self, factory: &syn::Ident) -> bool {
+        match &self.factory_type {
+            syn::Type::Path(path) => *factory == type_name(&path.path),
+            _ => false,
+        }
+    }
 }
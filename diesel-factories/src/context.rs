@@ -0,0 +1,252 @@
+use crate::fixtures::{self, Format};
+use crate::{random, restore_latest_inserts, take_latest_inserts, InsertWith, LatestInserts};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Sequence counters of a context, used instead of the global ones while it's active.
+#[derive(Default)]
+struct Sequences {
+    /// Identifies the context, so it removes its own counters when it's dropped.
+    id: usize,
+    counter: usize,
+    named: HashMap<String, usize>,
+}
+
+thread_local! {
+    static SEQUENCES: RefCell<Vec<Sequences>> = const { RefCell::new(Vec::new()) };
+    static SEQUENCES_COUNTER: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Start counters for a new context, returning their id.
+fn start_sequences() -> usize {
+    let id = SEQUENCES_COUNTER.with(|counter| {
+        counter.set(counter.get() + 1);
+        counter.get()
+    });
+    SEQUENCES.with(|sequences| {
+        sequences.borrow_mut().push(Sequences {
+            id,
+            ..Sequences::default()
+        })
+    });
+    id
+}
+
+/// Remove a context's counters. Contexts can be dropped in any order.
+fn stop_sequences(id: usize) {
+    SEQUENCES.with(|sequences| {
+        sequences
+            .borrow_mut()
+            .retain(|sequences| sequences.id != id)
+    });
+}
+
+/// The next value of the active context's sequence, or of its named sequence. `None` if no context
+/// is active on the thread.
+pub(crate) fn next_sequence(name: Option<&str>) -> Option<usize> {
+    SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let sequences = sequences.last_mut()?;
+        let count = match name {
+            Some(name) => sequences.named.entry(name.to_string()).or_insert(0),
+            None => &mut sequences.counter,
+        };
+        *count += 1;
+        Some(*count)
+    })
+}
+
+/// Reset the active context's named sequence, or all of its sequences. Returns whether a context
+/// is active on the thread.
+pub(crate) fn reset_sequences(name: Option<&str>) -> bool {
+    SEQUENCES.with(|sequences| match sequences.borrow_mut().last_mut() {
+        Some(sequences) => {
+            match name {
+                Some(name) => {
+                    sequences.named.remove(name);
+                }
+                None => {
+                    sequences.counter = 0;
+                    sequences.named.clear();
+                }
+            }
+            true
+        }
+        None => false,
+    })
+}
+
+/// A connection along with factory state of its own, for isolating tests from each other.
+///
+/// While a context is alive, [`sequence`] and [`sequence_in`] count from 1 with counters of the
+/// context's, and the [`random`] generator is seeded with the context's seed. Tests that each use a
+/// context therefore generate the same values however many tests run, in whatever order or in
+/// parallel. The seed is `0`, unless the `DIESEL_FACTORIES_SEED` environment variable is set, and
+/// can be changed with [`seed`]. The rows [`latest_inserted_id`] knows of are the context's as
+/// well, so the latest inserts of a test can't leak into the next one.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// use diesel_factories::{sequence, TestContext};
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let ctx = TestContext::new(con);
+///
+/// let country = ctx.insert(CountryFactory::default().name(sequence(|n| format!("Land {}", n))));
+/// assert_eq!("Land 1", country.name);
+///
+/// // Reference records are inserted the first time they're asked for
+/// let denmark = ctx.reference("denmark", || CountryFactory::default().name("Denmark"));
+/// let city = ctx.insert(CityFactory::default().country(&denmark));
+/// assert_eq!(denmark.id, ctx.reference("denmark", CountryFactory::default).id);
+/// # }
+/// ```
+///
+/// The state belongs to the thread that created the context, so it can't be sent to other
+/// threads. Contexts can be nested, in which case the innermost one is used until it's dropped.
+///
+/// [`sequence`]: fn.sequence.html
+/// [`sequence_in`]: fn.sequence_in.html
+/// [`random`]: random/index.html
+/// [`seed`]: #method.seed
+/// [`latest_inserted_id`]: fn.latest_inserted_id.html
+pub struct TestContext<Con> {
+    con: Con,
+    seed: u64,
+    references: RefCell<HashMap<String, Box<dyn Any>>>,
+    random: (u64, u64),
+    sequences: usize,
+    latest_inserts: LatestInserts,
+    fixtures: Option<usize>,
+    not_send: PhantomData<Rc<()>>,
+}
+
+impl<Con> TestContext<Con> {
+    /// Create a context using the connection, and make it the active one on the current thread.
+    pub fn new(con: Con) -> Self {
+        let random = random::save();
+        let seed = random::env_seed().unwrap_or(0);
+        random::set_seed(seed);
+        let sequences = start_sequences();
+        let latest_inserts = take_latest_inserts();
+
+        TestContext {
+            con,
+            seed,
+            references: RefCell::new(HashMap::new()),
+            random,
+            sequences,
+            latest_inserts,
+            fixtures: None,
+            not_send: PhantomData,
+        }
+    }
+
+    /// Restart the random generator from a different seed.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        random::set_seed(seed);
+        self
+    }
+
+    /// Record every row factories insert on the thread from now on, until the context is dropped,
+    /// so they can be dumped with [`dump_fixtures`]. See the [`fixtures`] module.
+    ///
+    /// [`dump_fixtures`]: #method.dump_fixtures
+    /// [`fixtures`]: fixtures/index.html
+    pub fn record_fixtures(mut self) -> Self {
+        if self.fixtures.is_none() {
+            self.fixtures = Some(fixtures::start(None));
+        }
+        self
+    }
+
+    /// The rows recorded so far in the given format, such as for writing a seed file.
+    ///
+    /// # Panics
+    /// Panics if the context isn't recording fixtures.
+    pub fn dump_fixtures(&self, format: Format) -> String {
+        let id = self
+            .fixtures
+            .expect("The context isn't recording fixtures, see `TestContext::record_fixtures`");
+        fixtures::recorded(id).dump(format)
+    }
+
+    /// The context's connection.
+    pub fn connection(&self) -> &Con {
+        &self.con
+    }
+
+    /// Insert a factory using the context's connection.
+    ///
+    /// # Panics
+    /// Panics if the insert fails, like [`Factory::insert`].
+    ///
+    /// [`Factory::insert`]: trait.Factory.html#tymethod.insert
+    pub fn insert<F>(&self, factory: F) -> F::Model
+    where
+        F: InsertWith<Con>,
+    {
+        factory.insert_with(&self.con)
+    }
+
+    /// The reference record with the given name, inserting the factory returned by `factory` if
+    /// the context hasn't inserted it yet.
+    ///
+    /// Useful for records that many factories in a test can share, such as a default country.
+    ///
+    /// # Panics
+    /// Panics if the insert fails, or if the name was used for a model of another type.
+    pub fn reference<F, M>(&self, name: &str, factory: M) -> F::Model
+    where
+        F: InsertWith<Con>,
+        F::Model: Clone + 'static,
+        M: FnOnce() -> F,
+    {
+        if let Some(model) = self.references.borrow().get(name) {
+            return model
+                .downcast_ref::<F::Model>()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Reference {:?} is not a {}",
+                        name,
+                        std::any::type_name::<F::Model>()
+                    )
+                })
+                .clone();
+        }
+
+        let model = self.insert(factory());
+        self.references
+            .borrow_mut()
+            .insert(name.to_string(), Box::new(model.clone()));
+        model
+    }
+}
+
+impl<Con> Drop for TestContext<Con> {
+    fn drop(&mut self) {
+        stop_sequences(self.sequences);
+        random::restore(self.random);
+        restore_latest_inserts(std::mem::take(&mut self.latest_inserts));
+        if let Some(id) = self.fixtures {
+            fixtures::stop(id);
+        }
+    }
+}
+
+impl<Con> fmt::Debug for TestContext<Con> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestContext")
+            .field("seed", &self.seed)
+            .field("references", &self.references.borrow().keys())
+            .field("fixtures", &self.fixtures.map(fixtures::recorded))
+            .finish()
+    }
+}
@@ -0,0 +1,127 @@
+//! In-memory SQLite databases for examples in doc tests. Requires the `sqlite` feature.
+//!
+//! Doc tests of crates using factories usually can't reach the Postgres database their tests run
+//! against. Giving a factory SQLite as an additional `connection` lets its examples insert into an
+//! in-memory database instead, created from the `CREATE TABLE` statements the example needs:
+//!
+//! ```
+//! #[macro_use]
+//! extern crate diesel;
+//!
+//! use diesel::sqlite::SqliteConnection;
+//! use diesel_factories::{doctest_connection, Association, Factory, InsertWith};
+//!
+//! mod schema {
+//!     table! {
+//!         countries (id) {
+//!             id -> Integer,
+//!             name -> Text,
+//!         }
+//!     }
+//!
+//!     table! {
+//!         cities (id) {
+//!             id -> Integer,
+//!             name -> Text,
+//!             country_id -> Integer,
+//!         }
+//!     }
+//! }
+//!
+//! #[derive(Clone, Queryable)]
+//! struct Country {
+//!     pub id: i32,
+//!     pub name: String,
+//! }
+//!
+//! #[derive(Clone, Queryable)]
+//! struct City {
+//!     pub id: i32,
+//!     pub name: String,
+//!     pub country_id: i32,
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "Country",
+//!     table = "crate::schema::countries",
+//!     connection = "diesel::pg::PgConnection",
+//!     connection = "diesel::sqlite::SqliteConnection",
+//! )]
+//! struct CountryFactory {
+//!     #[factory(default = "\"Denmark\".into()")]
+//!     pub name: String,
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "City",
+//!     table = "crate::schema::cities",
+//!     connection = "diesel::pg::PgConnection",
+//!     connection = "diesel::sqlite::SqliteConnection",
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(default = "\"Copenhagen\".into()")]
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! fn main() {
+//!     let con: SqliteConnection = doctest_connection!(
+//!         "CREATE TABLE countries (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL)",
+//!         "CREATE TABLE cities (
+//!             id INTEGER PRIMARY KEY AUTOINCREMENT,
+//!             name TEXT NOT NULL,
+//!             country_id INTEGER NOT NULL
+//!         )",
+//!     );
+//!
+//!     let city = CityFactory::default().insert_with(&con);
+//!     assert_eq!(1, city.country_id);
+//! }
+//! ```
+//!
+//! `insert` only works with the first `connection`, so examples use [`InsertWith`]. The
+//! factories' crate needs the `sqlite` feature of `diesel` in its `[dependencies]`, or as a
+//! feature of its own enabled when documenting, since the factory types are compiled with the
+//! crate rather than the doc test. Since enabling `sqlite` leaves out the code for other backends,
+//! the `postgres` feature is needed as well for the Postgres connection. See
+//! [Backend features](../index.html#backend-features).
+//!
+//! [`InsertWith`]: ../trait.InsertWith.html
+
+use diesel::connection::{Connection, SimpleConnection};
+use diesel::sqlite::SqliteConnection;
+
+/// A new in-memory SQLite database with `statements` run on it.
+///
+/// Prefer [`doctest_connection!`], which is shorter to write in examples.
+///
+/// # Panics
+///
+/// Panics if a statement fails, with the statement and the error.
+///
+/// [`doctest_connection!`]: ../macro.doctest_connection.html
+pub fn sqlite_connection(statements: &[&str]) -> SqliteConnection {
+    let con = SqliteConnection::establish(":memory:")
+        .unwrap_or_else(|e| panic!("Failed to open an in-memory SQLite database: {}", e));
+    for statement in statements {
+        con.batch_execute(statement)
+            .unwrap_or_else(|e| panic!("Failed to run `{}`: {}", statement, e));
+    }
+    con
+}
+
+/// A new in-memory SQLite database with the given statements run on it. Requires the `sqlite`
+/// feature.
+///
+/// See the [`doctest`] module for an example.
+///
+/// [`doctest`]: doctest/index.html
+#[cfg(feature = "sqlite")]
+#[macro_export]
+macro_rules! doctest_connection {
+    ( $($statement:expr),* $(,)? ) => {
+        $crate::doctest::sqlite_connection(&[$($statement),*])
+    };
+}
@@ -0,0 +1,112 @@
+//! The SQL a factory would insert, for inspecting a factory without running the insert.
+//!
+//! [`DryRun`] is implemented by `#[derive(Factory)]`. [`to_sql`] returns the `INSERT` statement
+//! for the factory's own row, with its bind values, as Diesel's [`debug_query`] prints it for the
+//! backend of the factory's first `connection`:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
+//! use diesel_factories::dry_run::DryRun;
+//!
+//! # fn main() {
+//! let sql = CountryFactory::default().to_sql();
+//! assert_eq!(
+//!     r#"INSERT INTO "countries" ("name") VALUES ($1) -- binds: ["Denmark"]"#,
+//!     sql,
+//! );
+//!
+//! let country = Country { id: 1, name: "Denmark".into() };
+//! let sql = CityFactory::default().name("Copenhagen").country(&country).to_sql();
+//! assert_eq!(
+//!     r#"INSERT INTO "cities" ("name", "country_id") VALUES ($1, $2) -- binds: ["Copenhagen", 1]"#,
+//!     sql,
+//! );
+//!
+//! let sql = CityFactory::default().to_sql();
+//! assert!(sql.contains("/* id of new Country */"));
+//! # }
+//! ```
+//!
+//! Associations that would be inserted or looked up first don't have an id yet, so their foreign
+//! keys are shown as comments naming the model, which means the SQL can't always be run as is.
+//! The `RETURNING` and `ON CONFLICT` clauses are left out as well.
+//!
+//! [`DryRun`]: trait.DryRun.html
+//! [`to_sql`]: trait.DryRun.html#tymethod.to_sql
+//! [`debug_query`]: https://docs.rs/diesel/1/diesel/fn.debug_query.html
+
+use diesel::backend::Backend;
+use diesel::expression::{AppearsOnTable, Expression};
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::result::QueryResult;
+use diesel::serialize::ToSql;
+use diesel::sql_types::{HasSqlType, NotNull, Nullable};
+use std::marker::PhantomData;
+
+/// A factory whose insert can be printed without a database.
+///
+/// You shouldn't have to implement this trait yourself. It is implemented by
+/// `#[derive(Factory)]`.
+pub trait DryRun {
+    /// The `INSERT` statement inserting the factory would run, with its bind values.
+    fn to_sql(&self) -> String;
+}
+
+/// A foreign key in the SQL of a dry run. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+#[derive(Debug)]
+pub enum Key<T, ST> {
+    Bound(T, PhantomData<ST>),
+    Pending(String),
+}
+
+impl<T, ST> Key<T, ST> {
+    /// The key of an existing row.
+    pub fn bound(value: T) -> Self {
+        Key::Bound(value, PhantomData)
+    }
+
+    /// A key that isn't known until inserting, shown as a comment describing it.
+    pub fn pending(description: String) -> Self {
+        Key::Pending(format!("/* {} */", description))
+    }
+}
+
+impl<T, ST: NotNull> Key<Option<T>, Nullable<ST>> {
+    /// The key of an optional association, `NULL` if it isn't set.
+    pub fn nullable(key: Option<Key<T, ST>>) -> Self {
+        match key {
+            Some(Key::Bound(value, _)) => Key::bound(Some(value)),
+            Some(Key::Pending(description)) => Key::Pending(description),
+            None => Key::bound(None),
+        }
+    }
+}
+
+impl<T, ST> Expression for Key<T, ST> {
+    type SqlType = ST;
+}
+
+impl<T, ST, QS> AppearsOnTable<QS> for Key<T, ST> {}
+
+impl<T, ST> QueryId for Key<T, ST> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T, ST, DB> QueryFragment<DB> for Key<T, ST>
+where
+    DB: Backend + HasSqlType<ST>,
+    T: ToSql<ST, DB>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, DB>) -> QueryResult<()> {
+        match self {
+            Key::Bound(value, _) => out.push_bind_param::<ST, T>(value),
+            Key::Pending(description) => {
+                out.push_sql(description);
+                Ok(())
+            }
+        }
+    }
+}
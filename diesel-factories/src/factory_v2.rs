@@ -0,0 +1,111 @@
+//! The next version of the factory traits.
+//!
+//! The traits in the crate root panic when an insert fails and are tied to a single connection
+//! type. The traits in this module return errors instead, and are generic over the connection
+//! handle used for inserting, such as `&PgConnection`, which leaves room for connections that
+//! must be borrowed mutably.
+//!
+//! `#[derive(Factory)]` implements both versions. The crate root traits are implemented in terms
+//! of these, so test suites can move over one call site at a time:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! use diesel_factories::factory_v2;
+//!
+//! // The current API, which panics if the insert fails
+//! let denmark = CountryFactory::default().insert(&con);
+//!
+//! // The new API
+//! let norway = factory_v2::Insert::insert(CountryFactory::default().name("Norway"), &con);
+//! assert_eq!("Norway", norway.unwrap().name);
+//! # }
+//! ```
+//!
+//! Both versions provide `insert`, so calls to the new version should be qualified with the trait
+//! as above while the crate root `Factory` trait is in scope.
+
+use diesel::result::QueryResult;
+
+/// A factory for a model.
+///
+/// You shouldn't ever have to implement this trait yourself. It is implemented by
+/// `#[derive(Factory)]`.
+pub trait Factory: Sized {
+    /// The model type the factory inserts.
+    type Model;
+
+    /// The primary key type your model uses.
+    type Id: Clone;
+
+    /// Get the primary id value for a model type.
+    fn id_for_model(model: &Self::Model) -> &Self::Id;
+}
+
+/// Insert a factory using a connection handle of type `Con`.
+///
+/// `#[derive(Factory)]` implements this for `&'a Con` where `Con` is each connection type given
+/// with the `connection` attribute.
+pub trait Insert<Con>: Factory {
+    /// Insert the factory into the database.
+    ///
+    /// Errors from inserting the factory's associations are returned as well.
+    fn insert(self, con: Con) -> QueryResult<Self::Model>;
+}
+
+/// Insert several factories at once using a connection handle of type `Con`.
+///
+/// Rows for the same table are inserted with one multi-row statement, starting with the
+/// factories' associations. Associations of the same type are merged as well, across factories
+/// and across fields, so a graph such as a user with a home city and a current city, each with a
+/// country, takes one statement per table instead of one per row. Inserting a single factory with
+/// [`Insert`] does this for its associations.
+///
+/// The models are returned in the same order as the factories.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// use diesel_factories::factory_v2::InsertBatch;
+///
+/// let countries = InsertBatch::insert_batch(
+///     vec![
+///         CountryFactory::default().name("Denmark"),
+///         CountryFactory::default().name("Norway"),
+///     ],
+///     &con,
+/// )
+/// .unwrap();
+///
+/// assert_eq!("Norway", countries[1].name);
+/// # }
+/// ```
+///
+/// Factories using `on_conflict` or `reselect`, or that have no fields, still have their
+/// associations merged but insert their own rows one at a time.
+///
+/// `#[derive(Factory)]` implements this along with [`Insert`].
+///
+/// [`Insert`]: trait.Insert.html
+pub trait InsertBatch<Con>: Insert<Con> {
+    /// Insert the factories into the database, returning their models in order.
+    fn insert_batch(factories: Vec<Self>, con: Con) -> QueryResult<Vec<Self::Model>>;
+}
+
+/// Insert a factory using a connection handle of type `Con`, returning only its primary key.
+///
+/// The insert uses `RETURNING` with just the primary key, so it works even if the model can't
+/// be loaded from the inserted row. Factories with `after_insert` still load the model, since the
+/// hook needs it.
+///
+/// `#[derive(Factory)]` implements this along with [`Insert`].
+///
+/// [`Insert`]: trait.Insert.html
+pub trait InsertReturningId<Con>: Factory {
+    /// Insert the factory into the database, returning its primary key.
+    fn insert_returning_id(self, con: Con) -> QueryResult<Self::Id>;
+}
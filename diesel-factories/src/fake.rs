@@ -0,0 +1,199 @@
+//! Fake data for factory defaults. Requires the `faker` feature.
+//!
+//! Values are generated with the [`random`] module, so they follow its seed. Setting the
+//! `DIESEL_FACTORIES_SEED` environment variable, or calling [`random::set_seed`], makes a test
+//! generate the same data again:
+//!
+//! ```
+//! use diesel_factories::{fake, random};
+//!
+//! random::set_seed(7);
+//! let email = fake::internet::email();
+//!
+//! random::set_seed(7);
+//! assert_eq!(email, fake::internet::email());
+//! ```
+//!
+//! The field attribute `#[factory(fake = "Internet.email")]` uses these functions for the derived
+//! `Default`. The provider name is the module and function, so `"Internet.email"` calls
+//! [`internet::email`].
+//!
+//! [`random`]: ../random/index.html
+//! [`random::set_seed`]: ../random/fn.set_seed.html
+//! [`internet::email`]: internet/fn.email.html
+
+use crate::random;
+
+fn pick(items: &[&str]) -> String {
+    (*random::choose(items)).to_string()
+}
+
+/// Names of people.
+pub mod name {
+    use super::pick;
+
+    const FIRST_NAMES: &[&str] = &[
+        "Alice", "Bob", "Carla", "David", "Emma", "Frederik", "Grace", "Hiroshi", "Ingrid",
+        "Jonas", "Karin", "Lars", "Maria", "Nikolaj", "Olivia", "Peter", "Rosa", "Sofie", "Tobias",
+        "Yuki",
+    ];
+
+    const LAST_NAMES: &[&str] = &[
+        "Andersen", "Brown", "Carlsen", "Diaz", "Eriksen", "Fischer", "Garcia", "Hansen", "Jensen",
+        "Kowalski", "Larsen", "Meyer", "Nielsen", "Olsen", "Pedersen", "Rossi", "Schmidt",
+        "Tanaka",
+    ];
+
+    /// A first name.
+    pub fn first_name() -> String {
+        pick(FIRST_NAMES)
+    }
+
+    /// A last name.
+    pub fn last_name() -> String {
+        pick(LAST_NAMES)
+    }
+
+    /// A first and last name.
+    pub fn name() -> String {
+        format!("{} {}", first_name(), last_name())
+    }
+}
+
+/// Usernames, emails and other internet things.
+pub mod internet {
+    use super::{name, pick};
+    use crate::random;
+
+    const DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+    /// A lowercase username with a number, like `"alice.hansen4821"`.
+    ///
+    /// The number makes collisions unlikely, but not impossible, for columns with unique
+    /// constraints.
+    pub fn username() -> String {
+        format!(
+            "{}.{}{}",
+            name::first_name().to_lowercase(),
+            name::last_name().to_lowercase(),
+            random::below(10_000),
+        )
+    }
+
+    /// A domain reserved for examples, so no real emails are sent.
+    pub fn domain() -> String {
+        pick(DOMAINS)
+    }
+
+    /// An email address at one of the [`domain`]s.
+    ///
+    /// [`domain`]: fn.domain.html
+    pub fn email() -> String {
+        format!("{}@{}", username(), domain())
+    }
+
+    /// An `https` URL at one of the [`domain`]s.
+    ///
+    /// [`domain`]: fn.domain.html
+    pub fn url() -> String {
+        format!("https://{}/{}", domain(), super::lorem::word())
+    }
+}
+
+/// Placeholder text.
+pub mod lorem {
+    use super::pick;
+    use crate::random;
+
+    const WORDS: &[&str] = &[
+        "lorem",
+        "ipsum",
+        "dolor",
+        "sit",
+        "amet",
+        "consectetur",
+        "adipiscing",
+        "elit",
+        "sed",
+        "do",
+        "eiusmod",
+        "tempor",
+        "incididunt",
+        "ut",
+        "labore",
+        "et",
+        "dolore",
+        "magna",
+        "aliqua",
+    ];
+
+    /// A single lowercase word.
+    pub fn word() -> String {
+        pick(WORDS)
+    }
+
+    /// A sentence of four to ten words, starting with a capital letter and ending with a period.
+    pub fn sentence() -> String {
+        let words = (0..4 + random::below(7))
+            .map(|_| word())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut chars = words.chars();
+        match chars.next() {
+            Some(first) => format!("{}{}.", first.to_uppercase(), chars.as_str()),
+            None => words,
+        }
+    }
+}
+
+/// Places.
+pub mod address {
+    use super::pick;
+
+    const CITIES: &[&str] = &[
+        "Copenhagen",
+        "Aarhus",
+        "Berlin",
+        "Lisbon",
+        "Oslo",
+        "Osaka",
+        "Toronto",
+        "Valencia",
+    ];
+
+    const COUNTRIES: &[&str] = &[
+        "Denmark", "Germany", "Portugal", "Norway", "Japan", "Canada", "Spain", "Sweden",
+    ];
+
+    /// A city name.
+    pub fn city() -> String {
+        pick(CITIES)
+    }
+
+    /// A country name.
+    pub fn country() -> String {
+        pick(COUNTRIES)
+    }
+}
+
+/// Points in time.
+pub mod date {
+    use crate::random;
+    use std::time::{Duration, SystemTime};
+
+    const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+    /// A time within the last year.
+    ///
+    /// The offset from now follows the seed, the current time doesn't.
+    pub fn past_datetime() -> SystemTime {
+        SystemTime::now() - Duration::from_secs(1 + random::below(SECONDS_PER_YEAR))
+    }
+
+    /// A time within the next year.
+    ///
+    /// The offset from now follows the seed, the current time doesn't.
+    pub fn future_datetime() -> SystemTime {
+        SystemTime::now() + Duration::from_secs(1 + random::below(SECONDS_PER_YEAR))
+    }
+}
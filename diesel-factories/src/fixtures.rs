@@ -0,0 +1,250 @@
+//! Recording the rows factories insert, for turning test setups into seed files.
+//!
+//! [`record`] runs a closure and captures every row factories insert on the connection while it
+//! runs, including associations and join rows. The [`Fixtures`] can then be dumped as SQL
+//! `INSERT` statements or as JSON, in the order the rows were inserted, so associated rows come
+//! before the rows pointing to them:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
+//! use diesel_factories::fixtures::{self, Format};
+//!
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! let (city, fixtures) = fixtures::record(&con, || {
+//!     CityFactory::default().name("Copenhagen").insert(&con)
+//! });
+//!
+//! assert_eq!(2, fixtures.len());
+//! let sql = fixtures.dump(Format::Sql);
+//! assert!(sql.starts_with(&format!(
+//!     "INSERT INTO countries (id, name) VALUES ('{}', 'Denmark');\n\
+//!      INSERT INTO cities (id, name, country_id",
+//!     city.country_id,
+//! )));
+//!
+//! let json = fixtures.dump(Format::Json);
+//! assert!(json.contains(&format!(
+//!     r#"{{"table" : "countries", "row" : {{"id":{},"name":"Denmark"}}}}"#,
+//!     city.country_id,
+//! )));
+//! # }
+//! ```
+//!
+//! The rows are rendered by the database as they were inserted, so values of any column type
+//! survive the round trip. The SQL quotes every value as a string literal and leaves the casts to
+//! the column types. Rendering needs Postgres' JSON functions, so recording a factory inserting on
+//! another backend panics.
+//!
+//! A [`TestContext`] can record everything inserted while it's alive, see
+//! [`TestContext::record_fixtures`].
+//!
+//! [`record`]: fn.record.html
+//! [`Fixtures`]: struct.Fixtures.html
+//! [`TestContext`]: ../struct.TestContext.html
+//! [`TestContext::record_fixtures`]: ../struct.TestContext.html#method.record_fixtures
+
+use crate::{connection_address, underlying_connection_address};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+use diesel::result::QueryResult;
+use diesel::sql_types::Text;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+
+/// A recording, capturing rows inserted on the connection at `address`, or on any connection.
+struct Recording {
+    id: usize,
+    address: Option<usize>,
+    fixtures: Fixtures,
+}
+
+thread_local! {
+    static RECORDINGS: RefCell<Vec<Recording>> = const { RefCell::new(Vec::new()) };
+    static RECORDING_COUNTER: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The rows recorded by [`record`] or a [`TestContext`].
+///
+/// [`record`]: fn.record.html
+/// [`TestContext`]: ../struct.TestContext.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fixtures {
+    rows: Vec<Row>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Row {
+    json: String,
+    sql: String,
+}
+
+/// The format [`Fixtures`] are dumped in.
+///
+/// [`Fixtures`]: struct.Fixtures.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// An `INSERT` statement per row, one per line.
+    Sql,
+    /// An array of `{"table": ..., "row": {...}}` objects, one per line.
+    Json,
+}
+
+impl Fixtures {
+    /// The recorded rows in the given format.
+    pub fn dump(&self, format: Format) -> String {
+        match format {
+            Format::Sql => self
+                .rows
+                .iter()
+                .map(|row| format!("{}\n", row.sql))
+                .collect(),
+            Format::Json => {
+                let rows = self
+                    .rows
+                    .iter()
+                    .map(|row| format!("  {}", row.json))
+                    .collect::<Vec<_>>();
+                format!("[\n{}\n]\n", rows.join(",\n"))
+            }
+        }
+    }
+
+    /// The number of recorded rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether no rows were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+/// Run a closure while recording every row factories insert on the connection.
+///
+/// Recordings can be nested, in which case the rows are recorded by each of them. The connection
+/// is identified by its address, and an r2d2 pooled connection by the connection it dereferences
+/// to, which is what factories insert with.
+///
+/// See the [module documentation](index.html) for more info.
+pub fn record<Con, T, F>(con: &Con, f: F) -> (T, Fixtures)
+where
+    Con: Any,
+    F: FnOnce() -> T,
+{
+    struct Guard(usize);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            stop(self.0);
+        }
+    }
+
+    let guard = Guard(start(Some(underlying_connection_address(con))));
+    let value = f();
+    let fixtures = recorded(guard.0);
+    (value, fixtures)
+}
+
+/// Start a recording on the connection at `address`, or on every connection of the thread.
+/// Returns the id of the recording.
+pub(crate) fn start(address: Option<usize>) -> usize {
+    let id = RECORDING_COUNTER.with(|counter| {
+        counter.set(counter.get() + 1);
+        counter.get()
+    });
+    RECORDINGS.with(|recordings| {
+        recordings.borrow_mut().push(Recording {
+            id,
+            address,
+            fixtures: Fixtures::default(),
+        })
+    });
+    id
+}
+
+/// The rows recorded so far by the recording.
+pub(crate) fn recorded(id: usize) -> Fixtures {
+    RECORDINGS.with(|recordings| {
+        recordings
+            .borrow()
+            .iter()
+            .find(|recording| recording.id == id)
+            .map(|recording| recording.fixtures.clone())
+            .unwrap_or_default()
+    })
+}
+
+/// Stop the recording. Recordings can be stopped in any order.
+pub(crate) fn stop(id: usize) {
+    RECORDINGS.with(|recordings| {
+        recordings
+            .borrow_mut()
+            .retain(|recording| recording.id != id)
+    });
+}
+
+fn is_recording<Con: ?Sized>(con: &Con) -> bool {
+    let address = connection_address(con);
+    RECORDINGS.with(|recordings| {
+        recordings
+            .borrow()
+            .iter()
+            .any(|recording| recording.address.is_none() || recording.address == Some(address))
+    })
+}
+
+/// Record a row inserted on the connection, loading it with `row` only if a recording is
+/// capturing the connection. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+pub fn record_row<Con, F>(con: &Con, row: F) -> QueryResult<()>
+where
+    Con: ?Sized,
+    F: FnOnce() -> QueryResult<(String, String)>,
+{
+    if !is_recording(con) {
+        return Ok(());
+    }
+
+    let (json, sql) = row()?;
+    let address = connection_address(con);
+    RECORDINGS.with(|recordings| {
+        for recording in recordings.borrow_mut().iter_mut() {
+            if recording.address.is_none() || recording.address == Some(address) {
+                recording.fixtures.rows.push(Row {
+                    json: json.clone(),
+                    sql: sql.clone(),
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The select clause rendering a row of the table as JSON and as an `INSERT` statement, for
+/// [`record_row`]. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+pub fn row_sql(table: &str) -> SqlLiteral<(Text, Text)> {
+    sql(&format!(
+        "json_build_object('table', '{table}', 'row', row_to_json(\"{table}\"))::text, \
+         (SELECT format('INSERT INTO %I (%s) VALUES (%s);', '{table}', \
+             string_agg(quote_ident(key), ', ' ORDER BY n), \
+             string_agg(coalesce(quote_literal(value), 'NULL'), ', ' ORDER BY n)) \
+          FROM json_each_text(row_to_json(\"{table}\")) WITH ORDINALITY AS columns(key, value, n))",
+        table = table
+    ))
+}
+
+/// Panic if a recording is capturing the connection, for factories on backends rows can't be
+/// rendered for. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+pub fn assert_not_recording<Con: ?Sized>(con: &Con, factory: &str) {
+    if is_recording(con) {
+        panic!(
+            "{} inserted a row while recording fixtures, which is only supported on Postgres",
+            factory
+        );
+    }
+}
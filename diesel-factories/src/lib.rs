@@ -190,8 +190,307 @@
 //! |---|---|---|---|
 //! | `model` | Model type your factory inserts | `"City"` | None, required |
 //! | `table` | Table your model belongs to | `"crate::schema::cities"` | None, required |
-//! | `connection` | The connection type your app uses | `"MysqlConnection"` | `"diesel::pg::PgConnection"` |
+//! | `connection` | The connection type your app uses. Can be given several times, see [`InsertWith`] | `"MysqlConnection"` | `"diesel::pg::PgConnection"` |
 //! | `id` | The type of your table's primary key | `"i64"` | `"i32"` |
+//! | `primary_key` | The model field and column of your table's primary key | `"slug"` | `"id"` |
+//! | `reselect` | Insert without `RETURNING` and load the row again by the factory's `primary_key` field | `reselect` | Uses `RETURNING` |
+//! | `backend` | The backend of the `connection` types, `"pg"`, `"mysql"` or `"sqlite"`. Only needed for connection types that don't end in `MysqlConnection` or `SqliteConnection` | `"mysql"` | Detected from the connection type |
+//! | `returning` | Comma separated columns the model is loaded from, in the order of its fields. Used for `RETURNING` and whenever the model is selected | `"id, name, country_id"` | All columns |
+//! | `after_insert` | Function called with `(&Model, &Connection)` after each insert | `"index_city"` | None |
+//! | `on_conflict` | What to do when an insert conflicts with an existing row. `"do_nothing"` returns the existing row, `"do_update"` updates it with the factory's values. Postgres only | `"do_nothing"` | None, conflicts fail |
+//! | `conflict_target` | Comma separated columns of the unique constraint for `on_conflict` | `"code"` | None |
+//! | `since_migration` | Migration this factory requires. See [`Versioned`] | `"2019-05-10-072830_cities"` | None |
+//! | `version` | Name of the factory's version. Requires `since_migration` | `"v2"` | The migration |
+//! | `abstract` | Only generate builder methods, not a [`Factory`] implementation, so the factory can't be inserted | `abstract` | Not abstract |
+//! | `build` | Also implement [`BuildFactory`] for building models without the database | `build` | Not buildable |
+//! | `from_model` | Generate a `from_model` constructor copying the fields of an existing model | `from_model` | Not generated |
+//! | `has_many` | A child factory and its association pointing back. See [`HasMany`] | `has_many(factory = "CityFactory<'a>", association = "country")` | None |
+//! | `joins` | A join table, the other factory, and the name of the builder method taking them. See [`Joins`] | `joins(table = "crate::schema::users_roles", left = "user_id", right = "role_id", factory = "RoleFactory", name = "roles")` | None |
+//! | `traits` | Methods that `with_traits` can apply by name | `traits("admin", "banned")` | None |
+//! | `profile` | A [`Profile`] the derived `Default` takes values from, for fields without `default`, `default_with` or `fake` | `"EcommerceProfile"` | None |
+//! | `cfg` | A `cfg` predicate put on all of the generated code. See [Backend features](#backend-features) | `"feature = \"mysql-tests\""` | Always compiled |
+//! | `timestamps` | Timestamp columns set to `now` on insert, without fields on the factory. A field with the column's name is used instead. `build` sets them to `SystemTime::now().into()` | `timestamps("inserted_at")` | `created_at` and `updated_at` when given without columns |
+//! | `scoped_column` | Column filled from the scope set with [`with_scope`] on insert, without a field on the factory. A field with the column's name is used instead | `"tenant_id"` | None |
+//! | `scope_type` | The type of the scope for `scoped_column` | `"uuid::Uuid"` | `"i32"` |
+//!
+//! `on_conflict` is useful for reference data that many tests insert. `"do_nothing"` inserts with
+//! `ON CONFLICT ... DO NOTHING`, which returns nothing for a conflicting row, so the existing row is
+//! then selected by the factory's fields named like the `conflict_target` columns. The factory
+//! needs a field for each of them. `"do_update"` uses `ON CONFLICT ... DO UPDATE`, which returns
+//! the updated row. Associations are inserted before the row either way, so they're still
+//! inserted when the row already exists.
+//!
+//! Factories and models don't have to implement `Clone`. Inserting takes the associations out of
+//! the factory rather than cloning them, and `insert_with_associations` loads models that already
+//! exist by their id. Building and [`PolymorphicAssociation::factory`] do clone the factories
+//! they're given.
+//!
+//! [`PolymorphicAssociation::factory`]: struct.PolymorphicAssociation.html#method.factory
+//!
+//! `after_insert` is meant for things every inserted record requires, such as populating
+//! denormalized columns or inserting rows into side tables. It runs inside `insert` so it also
+//! runs when the factory is inserted as an association of another factory.
+//!
+//! `traits` names methods you've written on the factory, each setting up a variant such as an
+//! admin user. The derive generates `with_traits`, which applies them by name in order. That's
+//! handy for table driven tests, and panics on names that weren't listed:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "Country",
+//!     table = "crate::schema::countries",
+//!     traits("nordic", "renamed"),
+//! )]
+//! struct TraitCountryFactory {
+//!     #[factory(default = "\"Germany\".into()")]
+//!     pub name: String,
+//! }
+//!
+//! impl TraitCountryFactory {
+//!     fn nordic(self) -> Self {
+//!         self.name("Sweden")
+//!     }
+//!
+//!     fn renamed(self) -> Self {
+//!         let name = format!("Kingdom of {}", self.name);
+//!         self.name(name)
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let factory = TraitCountryFactory::default().with_traits(&["nordic", "renamed"]);
+//! assert_eq!("Kingdom of Sweden", factory.name);
+//! # }
+//! ```
+//!
+//! MySQL and SQLite don't support `RETURNING`, so factories for them insert each row on its own
+//! and load it again by the key the database generated, from `LAST_INSERT_ID()` or
+//! `last_insert_rowid()`. If the factory has a field named after the primary key, the row is
+//! loaded by that field instead.
+//!
+//! The MySQL code path isn't covered by this crate's test suite, which runs against Postgres and
+//! SQLite only, so treat it as untested and please report any problems you run into.
+//!
+//! Tables keyed by something other than an `id` column, such as a `TEXT` slug, set `primary_key`
+//! and `id`. When the key is supplied by the factory rather than generated by the database, add a
+//! field with the key's name. With `reselect`, every backend inserts without `RETURNING` and then
+//! finds the row by that field:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! # mod tag_schema {
+//! #     table! {
+//! #         tags (slug) {
+//! #             slug -> Text,
+//! #             name -> Text,
+//! #         }
+//! #     }
+//! # }
+//! #
+//! #[derive(Queryable, Clone)]
+//! struct Tag {
+//!     pub slug: String,
+//!     pub name: String,
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "Tag",
+//!     table = "crate::tag_schema::tags",
+//!     id = "String",
+//!     primary_key = "slug",
+//!     reselect,
+//! )]
+//! struct TagFactory {
+//!     pub slug: String,
+//!     pub name: String,
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! `returning` is for tables with columns the model doesn't map, such as a `tsvector` or
+//! `geometry`, which would otherwise make loading the inserted row fail. When the model isn't
+//! needed at all, `insert_returning_id` returns only the primary key:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Queryable, Clone)]
+//! struct CountryName {
+//!     pub id: i32,
+//!     pub name: String,
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "CountryName",
+//!     table = "crate::schema::countries",
+//!     returning = "id, name",
+//! )]
+//! struct CountryNameFactory {
+//!     pub name: String,
+//! }
+//! #
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! let country = CountryNameFactory { name: "Denmark".into() }.insert(&con);
+//! assert_eq!("Denmark", country.name);
+//!
+//! let id: i32 = CountryNameFactory { name: "Norway".into() }.insert_returning_id(&con);
+//! assert_ne!(country.id, id);
+//! # }
+//! ```
+//!
+//! ### Field attributes
+//!
+//! | Name | Description | Example | Default |
+//! |---|---|---|---|
+//! | `map` | Function the field's value is passed through right before it's inserted | `"normalize_email"` | None |
+//! | `default` | Expression used for the field in the derived `Default` implementation | `"\"Denmark\".into()"` | None |
+//! | `default_with` | Function called for the field in the derived `Default` implementation | `"default_name"` | None |
+//! | `fake` | Fake data provider used for the field in the derived `Default` implementation. Requires the `faker` feature, see the [`fake`] module | `"Internet.email"` | None |
+//! | `sql_type` | SQL type the field's value is converted to, by value, when inserted. For custom types such as Postgres enums that only implement `AsExpression` for owned values | `"crate::schema::sql_types::Role"` | None |
+//! | `polymorphic` | Column prefix of a [`PolymorphicAssociation`] field. The `<prefix>_id` and `<prefix>_type` columns are set from the association | `"commentable"` | None |
+//! | `types` | Model types a [`PolymorphicAssociation`] field can point to. Required with `polymorphic` | `types("Post", "Photo")` | None |
+//! | `primary_key` | The key field of the models a [`PolymorphicAssociation`] field points to, used by the per type builder methods. Only with `polymorphic` | `"slug"` | `"id"` |
+//! | `foreign_key` | Column an [`Association`] field's foreign key is in, for when it isn't named after the field | `"owner_id"` | `<field>_id` |
+//! | `anonymize` | Function that replaces the field's value in `from_model_anonymized`. It receives a reference to the model's value | `"fake_email"` | None |
+//! | `rename` | Name of the field's builder method, for when the field's name collides with a method of your own. Association builder methods such as `<name>_with` use it too | `"set_name"` | The field's name |
+//! | `no_builder` | Don't generate builder methods for the field. It's still inserted | `no_builder` | Builder methods are generated |
+//!
+//! `map` is useful for running the same normalization your production code does, such as hashing
+//! passwords or trimming strings. The function receives the field's value by value and must
+//! return something that can be inserted into the column:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "City",
+//!     table = "crate::schema::cities",
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(map = "titlecase")]
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! fn titlecase(name: String) -> String {
+//!     let mut chars = name.chars();
+//!     match chars.next() {
+//!         Some(first) => first.to_uppercase().chain(chars).collect(),
+//!         None => name,
+//!     }
+//! }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         unimplemented!()
+//! #     }
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! Builder methods still set the raw value, `map` is applied when the factory is inserted.
+//!
+//! If any field uses `default`, `default_with` or `fake`, `Default` is derived for the factory,
+//! which requires every non-association field to have one of them. Association fields default to
+//! `Association::default()`, or `None` for optional associations:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "City",
+//!     table = "crate::schema::cities",
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(default = "\"Copenhagen\".into()")]
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! # fn main() {
+//! let city = CityFactory::default();
+//! assert_eq!("Copenhagen", city.name);
+//! # }
+//! ```
+//!
+//! If any field uses `anonymize`, a `from_model_anonymized` constructor is generated. It creates
+//! a factory from an existing model, such as a row loaded from a copy of production data, with the
+//! `anonymize` functions replacing sensitive values. Other fields are copied and associations
+//! point to the same records as the model's foreign keys:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "City",
+//!     table = "crate::schema::cities",
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(anonymize = "fake_city_name")]
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! fn fake_city_name(name: &str) -> String {
+//!     "x".repeat(name.len())
+//! }
+//!
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! let country = CountryFactory::default().insert(&con);
+//! let row = City { id: 1, name: "Copenhagen".into(), country_id: country.id };
+//!
+//! let city = CityFactory::from_model_anonymized(&row).insert(&con);
+//! assert_eq!("xxxxxxxxxx", city.name);
+//! assert_eq!(country.id, city.country_id);
+//! # }
+//! ```
+//!
+//! The `from_model` attribute generates a `from_model` constructor that copies every field as is,
+//! for inserting another record like an existing one. Builder methods then change what should
+//! differ, such as columns with unique constraints. The model's values of `map` fields were mapped
+//! when it was inserted, so as long as they aren't changed they're inserted without mapping them
+//! again:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = "City", table = "crate::schema::cities", from_model)]
+//! struct CityFactory<'a> {
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! let country = CountryFactory::default().insert(&con);
+//! let copenhagen = CityFactory {
+//!     name: "Copenhagen".into(),
+//!     country: Association::default(),
+//! }
+//! .country(&country)
+//! .insert(&con);
+//!
+//! let aarhus = CityFactory::from_model(&copenhagen).name("Aarhus").insert(&con);
+//! assert_eq!(copenhagen.country_id, aarhus.country_id);
+//! # }
+//! ```
 //!
 //! ### Builder methods
 //!
@@ -220,14 +519,36 @@
 //! CountryFactory::default().name("Amsterdam");
 //! ```
 //!
+//! Builder methods of `Vec` and `Option<Vec>` fields, such as Postgres arrays, take a vector,
+//! array or slice of anything that converts into the element type. So an `Option<Vec<String>>`
+//! field can be set with `.tags(["rust", "diesel"])`, or cleared with `.tags(None)`.
+//!
+//! Fields for columns with a database default can be a [`FieldValue`], which leaves the column
+//! out of the insert unless the factory is given a value or `None` for `NULL`.
+//!
+//! [`FieldValue`]: enum.FieldValue.html
 //! [`Factory`]: trait.Factory.html
+//! [`Versioned`]: enum.Versioned.html
+//! [`Shared`]: struct.Shared.html
+//! [`HasMany`]: trait.HasMany.html
+//! [`Joins`]: trait.Joins.html
+//! [`BuildFactory`]: trait.BuildFactory.html
+//! [`InsertWith`]: trait.InsertWith.html
+//! [`latest_inserted_id`]: fn.latest_inserted_id.html
+//! [`random`]: random/index.html
+//! [`fake`]: fake/index.html
+//! [`Profile`]: profile/trait.Profile.html
+//! [`Association`]: enum.Association.html
+//! [`PolymorphicAssociation`]: struct.PolymorphicAssociation.html
+//! [`Strategy`]: enum.Strategy.html
+//! [`with_scope`]: fn.with_scope.html
 //!
 //! ### Builder methods for associations
 //!
 //! The builder methods generated for `Association` fields are a bit different. If you have a factory like:
 //!
 //! ```
-//! # include!("../tests/docs_setup.rs");
+//! # include!("../tests/setup/docs_setup.rs");
 //! #
 //! #[derive(Clone, Factory)]
 //! #[factory(
@@ -251,7 +572,7 @@
 //! You'll be able to call `country` either with an owned `CountryFactory`:
 //!
 //! ```
-//! # include!("../tests/docs_setup.rs");
+//! # include!("../tests/setup/docs_setup.rs");
 //! #
 //! # #[derive(Clone, Factory)]
 //! # #[factory(
@@ -280,7 +601,7 @@
 //! Or a borrowed `Country`:
 //!
 //! ```
-//! # include!("../tests/docs_setup.rs");
+//! # include!("../tests/setup/docs_setup.rs");
 //! #
 //! # #[derive(Clone, Factory)]
 //! # #[factory(
@@ -308,12 +629,83 @@
 //!
 //! This should prevent bugs where you have multiple factory instances sharing some association that you mutate halfway through a test.
 //!
+//! If several associations should point to the same record that hasn't been inserted yet, you
+//! can also call `country` with a [`Shared`] factory which is only inserted once.
+//!
+//! If you only want to tweak the association's default factory you can use the `_with` builder
+//! that is generated for each association field. It receives the current factory (or a default
+//! one, if the association is set to a model or `None`):
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Factory)]
+//! # #[factory(
+//! #     model = "City",
+//! #     table = "crate::schema::cities",
+//! # )]
+//! # struct CityFactory<'a> {
+//! #     pub name: String,
+//! #     pub country: Association<'a, Country, CountryFactory>,
+//! # }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: String::new(), country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # fn main() {
+//! CityFactory::default().country_with(|country| country.name("Norway"));
+//! # }
+//! ```
+//!
+//! The `_latest` builder, also generated for each association field, associates with whatever
+//! record of that type was most recently inserted on the connection. See
+//! [`latest_inserted_id`].
+//!
+//! The `_sampled_from` and `_sampled_from_weighted` builders pick the associated model at random
+//! from a slice, using the generator in the [`random`] module. This is useful for bulk generating
+//! realistic data where parents have varying numbers of children.
+//!
+//! The `_strategy` builder picks whether the association is inserted, faked, or looked up among
+//! existing rows when the factory is inserted. See [`Strategy`].
+//!
+//! Factories with associations also get an `insert_with_associations` method, which returns the
+//! associated models along with the inserted one, in a struct named after the factory. Optional
+//! associations are `Option`s in it, and polymorphic associations are left out. Associations
+//! inserted from factories have the models of their own associations in
+//! `<association>_associations` fields, so the whole graph that was inserted is returned:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
+//! #
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! let (city, associations): (City, CityFactoryAssociations) =
+//!     CityFactory::default().insert_with_associations(&con);
+//!
+//! assert_eq!(city.country_id, associations.country.id);
+//! assert_eq!("Denmark", associations.country.name);
+//! # }
+//! ```
+//!
+//! Associations set to existing rows, such as with the `_latest` and `_id` builders, are loaded
+//! by their id, and their `<association>_associations` fields are `None`. Errors are returned
+//! rather than panicking by [`InsertWithAssociations::insert_returning_associations`], which is
+//! implemented for every factory.
+//!
+//! [`InsertWithAssociations::insert_returning_associations`]: trait.InsertWithAssociations.html#tymethod.insert_returning_associations
+//!
 //! ### Optional associations
 //!
 //! If your model has a nullable association you can do this:
 //!
 //! ```
-//! # include!("../tests/docs_setup_with_city_factory.rs");
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
 //! #
 //! #[derive(Clone, Factory)]
 //! #[factory(
@@ -354,6 +746,168 @@
 //! UserFactory::default().country(Option::<&Country>::None);
 //! # }
 //! ```
+//!
+//! ### Associations to the same type
+//!
+//! A factory can have an association to its own type, such as a category's parent, as long as
+//! it's optional. An `_depth` builder is generated for it, which sets the association to a chain
+//! of that many new factories, each the parent of the one before it. Each factory in the chain is
+//! built with the given function, so the factory doesn't have to implement `Default`:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! # mod category_schema {
+//! #     table! {
+//! #         categories (id) {
+//! #             id -> Integer,
+//! #             name -> Text,
+//! #             parent_id -> Nullable<Integer>,
+//! #         }
+//! #     }
+//! # }
+//! #
+//! #[derive(Clone, Queryable)]
+//! struct Category {
+//!     pub id: i32,
+//!     pub name: String,
+//!     pub parent_id: Option<i32>,
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(model = "Category", table = "crate::category_schema::categories")]
+//! struct CategoryFactory<'a> {
+//!     #[factory(default = "\"Phones\".into()")]
+//!     pub name: String,
+//!     pub parent: Option<Association<'a, Category, CategoryFactory<'a>>>,
+//! }
+//!
+//! # fn main() {
+//! # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//! # con.begin_test_transaction().unwrap();
+//! // Phones, inside two more categories
+//! let phones = CategoryFactory::default()
+//!     .parent_depth(2, CategoryFactory::default)
+//!     .insert(&con);
+//! assert!(phones.parent_id.is_some());
+//! # }
+//! ```
+//!
+//! ## Backend tests
+//!
+//! `#[factory_backend_tests]` generates tests that insert a factory's `Default` value, once with
+//! each insert API and three at a time with `InsertBatch`. It takes a function returning a
+//! connection for each backend to test, with the keys `pg`, `mysql` and `sqlite`:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! use diesel_factories::factory_backend_tests;
+//!
+//! #[factory_backend_tests(pg = "pg_connection")]
+//! #[derive(Clone, Factory)]
+//! #[factory(model = "Country", table = "crate::schema::countries")]
+//! struct TestedCountryFactory {
+//! #   #[factory(default = "String::new()")]
+//!     pub name: String,
+//! }
+//!
+//! fn pg_connection() -> PgConnection {
+//!     let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+//!     con.begin_test_transaction().unwrap();
+//!     con
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! The tests are put in a module named after the factory, such as
+//! `tested_country_factory_backend_tests`. Factories insert using `RETURNING` on Postgres, and
+//! select the inserted row again on MySQL and SQLite.
+//!
+//! ## Connection pools
+//!
+//! `insert` takes `&Self::Connection`, so connections checked out from a pool can be passed
+//! directly and are dereferenced to the connection. The APIs that are generic over the connection,
+//! [`InsertWith`] and [`factory_v2::Insert`], also accept r2d2 pooled connections when the `r2d2`
+//! feature is enabled along with the backend's feature, such as `postgres`:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! diesel-factories = { version = "0.1", features = ["r2d2", "postgres"] }
+//! ```
+//!
+//! [`insert_list_concurrent`] inserts many factories on several threads, each with a connection
+//! of its own from an r2d2 pool, returning the models in order.
+//!
+//! [`factory_v2::Insert`]: factory_v2/trait.Insert.html
+//! [`insert_list_concurrent`]: fn.insert_list_concurrent.html
+//!
+//! ## Backend features
+//!
+//! Enabling any of the `postgres`, `mysql` and `sqlite` features makes `#[derive(Factory)]` only
+//! generate code for the `connection` types of the enabled backends, so factories listing several
+//! connections compile with just one of Diesel's backends. The first enabled connection is the
+//! one `insert` uses. Without any of the features every connection is used, as before.
+//!
+//! Factories without any enabled connection don't compile, unless they're gated with `cfg`, either
+//! on the struct or as a factory attribute. The attribute keeps the struct and only gates the
+//! generated code:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "Country",
+//!     table = "crate::schema::countries",
+//!     connection = "diesel::mysql::MysqlConnection",
+//!     cfg = "feature = \"mysql-tests\"",
+//! )]
+//! struct MysqlCountryFactory {
+//!     pub name: String,
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! ## Doc tests
+//!
+//! With the `sqlite` feature, [`doctest_connection!`] creates an in-memory SQLite database, so
+//! examples in your own crate's docs can insert factories without a Postgres database. See the
+//! [`doctest`] module.
+//!
+//! [`doctest_connection!`]: macro.doctest_connection.html
+//! [`doctest`]: doctest/index.html
+//!
+//! ## Test contexts
+//!
+//! Sequences and the [`random`] generator are otherwise shared by the tests running on a thread
+//! or, for sequences, by all tests. A [`TestContext`] gives a test its own sequences starting at 1,
+//! a fixed seed, and named reference records that are inserted once per test.
+//!
+//! [`random`]: random/index.html
+//! [`TestContext`]: struct.TestContext.html
+//!
+//! ## Fixtures
+//!
+//! The rows factories insert can be recorded and dumped as SQL `INSERT` statements or JSON, to
+//! turn a test setup into a seed file for another environment. Wrap the inserts in
+//! [`fixtures::record`], or call [`TestContext::record_fixtures`] and later
+//! [`TestContext::dump_fixtures`]. Recording is only supported on Postgres.
+//!
+//! [`fixtures::record`]: fixtures/fn.record.html
+//! [`TestContext::record_fixtures`]: struct.TestContext.html#method.record_fixtures
+//! [`TestContext::dump_fixtures`]: struct.TestContext.html#method.dump_fixtures
+//!
+//! ## Tracing
+//!
+//! With the `tracing` feature, each factory insert runs in a `factory_insert` span at the `DEBUG`
+//! level, with the factory's name and table as fields. Associations are inserted inside the span
+//! of the factory they belong to, so the spans follow the factory graph. An event is emitted for
+//! each inserted row, with its `id` and the time `elapsed` since the insert started, including
+//! its associations. The ids are formatted with `Debug`, which the `id` types of factories must
+//! implement with the feature enabled.
 
 #![doc(html_root_url = "https://docs.rs/diesel-factories/0.1.1")]
 #![deny(
@@ -368,15 +922,101 @@
     unused_qualifications
 )]
 
+#[macro_use]
+extern crate diesel;
+
+use diesel::backend::Backend;
+use diesel::connection::{Connection, TransactionManager};
+use diesel::expression::{AppearsOnTable, Expression};
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::result::QueryResult;
+use diesel::serialize::ToSql;
+use diesel::sql_types::HasSqlType;
 use lazy_static::lazy_static;
+use plan::{InsertPlan, Plan, PlannedKey};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub use diesel_factories_code_gen::{factory_backend_tests, ChangesetFactory, Factory};
+#[cfg(feature = "faker")]
+pub use fake::{
+    date::past_datetime as fake_past_datetime, internet::email as fake_email,
+    name::name as fake_name,
+};
 
-pub use diesel_factories_code_gen::Factory;
+pub use context::TestContext;
+#[cfg(feature = "r2d2")]
+pub use pool::insert_list_concurrent;
+pub use scope::{with_scope, ScopedFactory};
+
+mod context;
+#[cfg(feature = "sqlite")]
+pub mod doctest;
+pub mod dry_run;
+pub mod factory_v2;
+#[cfg(feature = "faker")]
+pub mod fake;
+pub mod fixtures;
+pub mod plan;
+#[cfg(feature = "r2d2")]
+mod pool;
+pub mod profile;
+pub mod random;
+#[cfg(feature = "inventory")]
+pub mod registry;
+mod scope;
 
 /// A "belongs to" association that may or may not have been inserted yet.
 ///
 /// You will normally be using this when setting up "belongs to" associations between models in
 /// factories.
+///
+/// The `Model` variant borrows the model, which ties the factory to the model's lifetime. Use the
+/// `Owned` or `Id` variants for factories that must be `'static`, such as factories kept in test
+/// helper structs or moved into spawned threads. Factories whose associations are all `Factory`,
+/// `Shared`, `Owned`, `Id` or `Latest` can use `Association<'static, ...>` and are `Send` when
+/// their fields are:
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # use std::sync::Arc;
+/// #
+/// #[derive(Clone, Factory)]
+/// #[factory(model = "City", table = "crate::schema::cities")]
+/// struct OwnedCityFactory {
+///     pub name: String,
+///     pub country: Association<'static, Country, CountryFactory>,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let country = Arc::new(CountryFactory::default().insert(&con));
+///
+/// let factory = OwnedCityFactory {
+///     name: "Copenhagen".into(),
+///     country: Association::Owned(Arc::clone(&country)),
+/// };
+/// let factory = std::thread::spawn(move || factory).join().unwrap();
+/// let copenhagen = factory.insert(&con);
+///
+/// let aarhus = OwnedCityFactory {
+///     name: "Aarhus".into(),
+///     country: Association::default(),
+/// }
+/// .country_id(country.id)
+/// .insert(&con);
+///
+/// assert_eq!(country.id, copenhagen.country_id);
+/// assert_eq!(country.id, aarhus.country_id);
+/// # }
+/// ```
 #[derive(Debug, Clone)]
 pub enum Association<'a, Model, Factory> {
     /// An associated model that has been inserted into the database.
@@ -386,13 +1026,92 @@ pub enum Association<'a, Model, Factory> {
 
     /// A factory for a model that hasn't been inserted yet into the database.
     ///
-    /// You shouldn't have to use this direclty but instead just `Association::default()`.
-    Factory(Factory),
+    /// You shouldn't have to use this direclty but instead just `Association::default()`. The
+    /// factory is boxed so factories can have associations to their own type.
+    Factory(Box<Factory>),
+
+    /// A factory that is shared between several associations and only inserted once.
+    ///
+    /// See [`Shared`] for more info.
+    ///
+    /// [`Shared`]: struct.Shared.html
+    Shared(Shared<Model, Factory>),
+
+    /// The most recently inserted model of the association's type, on the connection used for
+    /// the insert.
+    ///
+    /// Set it with the `<association>_latest` builder methods. See [`latest_inserted_id`] for
+    /// more info.
+    ///
+    /// [`latest_inserted_id`]: fn.latest_inserted_id.html
+    Latest,
+
+    /// An associated model that has been inserted into the database and is owned by the
+    /// association.
+    Owned(Arc<Model>),
+
+    /// The id of an associated model that has been inserted into the database.
+    ///
+    /// Create it with [`Association::id`] or the `<association>_id` builder methods.
+    ///
+    /// [`Association::id`]: enum.Association.html#method.id
+    Id(AssociationId),
+
+    /// An existing row that is looked up when inserting.
+    ///
+    /// Set it with [`FindBy`] and the `<association>_strategy` builder methods.
+    ///
+    /// [`FindBy`]: struct.FindBy.html
+    Find(AssociationFinder),
+
+    /// A factory for a model that is only built in memory, never inserted.
+    ///
+    /// Set it with [`Strategy::Build`] and the `<association>_strategy` builder methods. Building
+    /// the factory it belongs to with [`BuildFactory`] builds this one as well, while inserting
+    /// it returns an error, since there's no row for the foreign key to reference.
+    ///
+    /// [`Strategy::Build`]: enum.Strategy.html#variant.Build
+    /// [`BuildFactory`]: trait.BuildFactory.html
+    Built(Box<Factory>),
+}
+
+/// The id stored in [`Association::Id`].
+///
+/// [`Association::Id`]: enum.Association.html#variant.Id
+#[derive(Clone)]
+pub struct AssociationId(Arc<dyn Any + Send + Sync>);
+
+impl std::fmt::Debug for AssociationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AssociationId").field(&"..").finish()
+    }
+}
+
+impl AssociationId {
+    fn get<Id: 'static>(&self) -> &Id {
+        self.0
+            .downcast_ref::<Id>()
+            .expect("Association::Id created with another id type")
+    }
+}
+
+/// The lookup stored in [`Association::Find`].
+///
+/// [`Association::Find`]: enum.Association.html#variant.Find
+#[derive(Clone)]
+pub struct AssociationFinder(Arc<FindAssociationId>);
+
+type FindAssociationId = dyn Fn(&dyn Any) -> QueryResult<AssociationId> + Send + Sync;
+
+impl std::fmt::Debug for AssociationFinder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AssociationFinder").field(&"..").finish()
+    }
 }
 
 impl<Model, Factory: Default> Default for Association<'_, Model, Factory> {
     fn default() -> Self {
-        Association::Factory(Factory::default())
+        Association::Factory(Box::default())
     }
 }
 
@@ -404,80 +1123,2520 @@ impl<'a, Model, Factory> Association<'a, Model, Factory> {
 
     #[doc(hidden)]
     pub fn new_factory(inner: Factory) -> Self {
-        Association::Factory(inner)
+        Association::Factory(Box::new(inner))
+    }
+
+    #[doc(hidden)]
+    pub fn new_shared(inner: Shared<Model, Factory>) -> Self {
+        Association::Shared(inner)
+    }
+
+    #[doc(hidden)]
+    pub fn new_owned(inner: Arc<Model>) -> Self {
+        Association::Owned(inner)
+    }
+
+    /// Take the association out of a factory that's being inserted, leaving `Latest`.
+    #[doc(hidden)]
+    pub fn take(&mut self) -> Self {
+        std::mem::replace(self, Association::Latest)
+    }
+
+    /// An association to the model with the given id.
+    pub fn id(id: Factory::Id) -> Self
+    where
+        Factory: factory_v2::Factory,
+        Factory::Id: Send + Sync + 'static,
+    {
+        Association::Id(AssociationId(Arc::new(id)))
+    }
+}
+
+impl<Model, Factory> Association<'_, Model, Factory> {
+    /// Apply `f` to the association's factory, or to `default()` if it doesn't have one.
+    #[doc(hidden)]
+    pub fn map_factory<D, F>(self, default: D, f: F) -> Self
+    where
+        D: FnOnce() -> Factory,
+        F: FnOnce(Factory) -> Factory,
+    {
+        match self {
+            Association::Factory(factory) => Association::Factory(Box::new(f(*factory))),
+            Association::Built(factory) => Association::Built(Box::new(f(*factory))),
+            Association::Model(_)
+            | Association::Shared(_)
+            | Association::Latest
+            | Association::Owned(_)
+            | Association::Id(_)
+            | Association::Find(_) => Association::Factory(Box::new(f(default()))),
+        }
+    }
+}
+
+/// The default value of `T`, for a generic parameter of a generated method to be bounded by
+/// rather than bounding `T: Default` directly. A bound on a concrete `T` that doesn't hold fails
+/// to compile, so this keeps factories whose associated factories aren't `Default` compiling,
+/// only requiring `Default` where such a method is called.
+#[doc(hidden)]
+pub trait DefaultOf<T> {
+    /// `T::default()`.
+    fn default_of() -> T;
+}
+
+impl<S, T: Default> DefaultOf<T> for S {
+    fn default_of() -> T {
+        T::default()
+    }
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: factory_v2::Factory<Model = M>,
+    F::Id: 'static,
+{
+    #[doc(hidden)]
+    pub fn insert_returning_id<Con>(self, con: Con) -> QueryResult<F::Id>
+    where
+        F: factory_v2::Insert<Con>,
+        Con: Deref,
+        Con::Target: Sized + 'static,
+    {
+        match self {
+            Association::Model(model) => Ok(F::id_for_model(model).clone()),
+            Association::Factory(factory) => {
+                let model = (*factory).insert(con)?;
+                Ok(F::id_for_model(&model).clone())
+            }
+            Association::Shared(shared) => shared.insert_returning_id(con),
+            Association::Latest => match latest_inserted_id::<M, _, _>(&*con) {
+                Some(id) => Ok(id),
+                None => panic!(
+                    "No {} has been inserted on this connection to associate with{}",
+                    std::any::type_name::<M>(),
+                    current_insert()
+                        .map(|trace| format!(", in {}", trace))
+                        .unwrap_or_default()
+                ),
+            },
+            Association::Owned(model) => Ok(F::id_for_model(&model).clone()),
+            Association::Id(id) => Ok(id.get::<F::Id>().clone()),
+            Association::Find(finder) => Ok((finder.0)(&*con)?.get::<F::Id>().clone()),
+            Association::Built(_) => Err(diesel::result::Error::QueryBuilderError(
+                format!(
+                    "{} is only built, so there's no row to associate with",
+                    std::any::type_name::<M>()
+                )
+                .into(),
+            )),
+        }
     }
 }
 
 impl<M, F> Association<'_, M, F>
 where
-    F: Factory<Model = M> + Clone,
+    F: Factory<Model = M> + factory_v2::Factory<Model = M, Id = <F as Factory>::Id>,
+    <F as Factory>::Id: 'static,
+    F::Connection: 'static,
 {
+    /// The associated model, inserting it if it's a factory. Rows that already exist are loaded
+    /// by their id, so the model doesn't have to be `Clone`.
+    #[doc(hidden)]
+    pub fn insert_model<'c>(self, con: &'c F::Connection) -> QueryResult<M>
+    where
+        F: factory_v2::Insert<&'c F::Connection> + FindById,
+    {
+        match self {
+            Association::Factory(factory) => factory_v2::Insert::insert(*factory, con),
+            Association::Owned(model) => match Arc::try_unwrap(model) {
+                Ok(model) => Ok(model),
+                Err(model) => F::find_by_id(<F as Factory>::id_for_model(&model), con),
+            },
+            association => {
+                let id = association.insert_returning_id(con)?;
+                F::find_by_id(&id, con)
+            }
+        }
+    }
+
+    /// Like `insert_model`, along with the models of the associated model's own associations if
+    /// it's inserted from a factory. They aren't known for rows that already exist.
     #[doc(hidden)]
-    pub fn insert_returning_id(&self, con: &F::Connection) -> F::Id {
+    pub fn insert_model_with_associations<'c>(
+        self,
+        con: &'c F::Connection,
+    ) -> QueryResult<(M, Option<Box<F::Associations>>)>
+    where
+        F: factory_v2::Insert<&'c F::Connection> + FindById + InsertWithAssociations,
+    {
         match self {
-            Association::Model(model) => F::id_for_model(&model).clone(),
             Association::Factory(factory) => {
-                let model = factory.clone().insert(con);
+                let (model, associations) = factory.insert_returning_associations(con)?;
+                Ok((model, Some(Box::new(associations))))
+            }
+            association => Ok((association.insert_model(con)?, None)),
+        }
+    }
+
+    /// An association to the model's row, by its id.
+    #[doc(hidden)]
+    pub fn to_inserted(model: &M) -> Self
+    where
+        <F as Factory>::Id: Send + Sync,
+    {
+        Association::id(<F as Factory>::id_for_model(model).clone())
+    }
+}
+
+/// Insert associations of the same type, merging the ones with factories into one statement.
+/// [`Shared`] factories that haven't been inserted are merged too, once each however many
+/// associations refer to them.
+///
+/// `None` is for optional associations that aren't set. Other associations are resolved first, so
+/// [`Association::Latest`] doesn't pick up rows inserted by the merged factories.
+#[doc(hidden)]
+pub fn insert_associations<M, F, Con>(
+    associations: Vec<Option<Association<'_, M, F>>>,
+    con: Con,
+) -> QueryResult<Vec<Option<F::Id>>>
+where
+    F: factory_v2::InsertBatch<Con, Model = M>,
+    F::Id: 'static,
+    Con: Deref + Copy,
+    Con::Target: Sized + 'static,
+{
+    let mut ids = Vec::with_capacity(associations.len());
+    let mut shared: Vec<(Shared<M, F>, Vec<usize>)> = Vec::new();
+    // Positions each merged factory's id goes to, and the lock of its `Shared` if it has one
+    let mut targets = Vec::new();
+    let mut factories = Vec::new();
+    for (position, association) in associations.into_iter().enumerate() {
+        match association {
+            Some(Association::Factory(factory)) => {
+                targets.push((vec![position], None));
+                factories.push(*factory);
+                ids.push(None);
+            }
+            Some(Association::Shared(inner)) => {
+                match shared
+                    .iter_mut()
+                    .find(|(other, _)| other.key() == inner.key())
+                {
+                    Some((_, positions)) => positions.push(position),
+                    None => shared.push((inner, vec![position])),
+                }
+                ids.push(None);
+            }
+            Some(association) => ids.push(Some(association.insert_returning_id(con)?)),
+            None => ids.push(None),
+        }
+    }
+
+    // Locked in a fixed order, so batches sharing factories can't deadlock
+    shared.sort_by_key(|(inner, _)| inner.key());
+    for (inner, positions) in &shared {
+        let mut state = inner.state();
+        match &*state {
+            SharedState::Model(model) => {
+                for position in positions {
+                    ids[*position] = Some(F::id_for_model(model).clone());
+                }
+            }
+            SharedState::Factory(_) | SharedState::Built(..) => {
+                factories.push(state.take_factory(inner.clone_factory));
+                targets.push((positions.clone(), Some(state)));
+            }
+            SharedState::Failed => shared_failed::<F>(),
+        }
+    }
+
+    let models = F::insert_batch(factories, con)?;
+    for ((positions, state), model) in targets.into_iter().zip(models) {
+        let id = F::id_for_model(&model).clone();
+        for position in positions {
+            ids[position] = Some(id.clone());
+        }
+        if let Some(mut state) = state {
+            *state = SharedState::Model(model);
+        }
+    }
+    Ok(ids)
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: factory_v2::Factory<Model = M> + Plan,
+    F::Id: std::fmt::Debug + 'static,
+{
+    #[doc(hidden)]
+    pub fn plan_key(&self, plan: &mut InsertPlan) -> PlannedKey {
+        match self {
+            Association::Model(model) => PlannedKey::Id(format!("{:?}", F::id_for_model(model))),
+            Association::Factory(factory) => PlannedKey::Insert(factory.plan_into(plan)),
+            Association::Shared(shared) => shared.plan_key(plan),
+            Association::Latest => PlannedKey::Latest,
+            Association::Owned(model) => PlannedKey::Id(format!("{:?}", F::id_for_model(model))),
+            Association::Id(id) => PlannedKey::Id(format!("{:?}", id.get::<F::Id>())),
+            Association::Find(_) => PlannedKey::Find,
+            Association::Built(_) => PlannedKey::Built,
+        }
+    }
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: factory_v2::Factory<Model = M>,
+    F::Id: 'static,
+{
+    #[doc(hidden)]
+    pub fn dry_run_key<ST>(&self) -> dry_run::Key<F::Id, ST> {
+        let new = || dry_run::Key::pending(format!("id of new {}", short_type_name::<M>()));
+        match self {
+            Association::Model(model) => dry_run::Key::bound(F::id_for_model(model).clone()),
+            Association::Factory(_) => new(),
+            Association::Shared(shared) => match &*shared.state() {
+                SharedState::Model(model) => dry_run::Key::bound(F::id_for_model(model).clone()),
+                SharedState::Factory(_) | SharedState::Built(..) => new(),
+                SharedState::Failed => shared_failed::<F>(),
+            },
+            Association::Latest => {
+                dry_run::Key::pending(format!("id of latest {}", short_type_name::<M>()))
+            }
+            Association::Owned(model) => dry_run::Key::bound(F::id_for_model(model).clone()),
+            Association::Id(id) => dry_run::Key::bound(id.get::<F::Id>().clone()),
+            Association::Find(_) => {
+                dry_run::Key::pending(format!("id of found {}", short_type_name::<M>()))
+            }
+            Association::Built(_) => {
+                dry_run::Key::pending(format!("id of built {}", short_type_name::<M>()))
+            }
+        }
+    }
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: BuildFactory<Model = M> + Clone,
+    F::Id: FakeId + 'static,
+{
+    #[doc(hidden)]
+    pub fn build_returning_id(&self) -> F::Id {
+        match self {
+            Association::Model(model) => F::id_for_model(model).clone(),
+            Association::Factory(factory) | Association::Built(factory) => {
+                let model = F::clone(factory).build();
                 F::id_for_model(&model).clone()
             }
+            Association::Shared(shared) => shared.build_returning_id(),
+            Association::Latest => panic!(
+                "Latest {} can't be used when building since it requires a connection",
+                std::any::type_name::<M>()
+            ),
+            Association::Owned(model) => F::id_for_model(model).clone(),
+            Association::Id(id) => id.get::<F::Id>().clone(),
+            Association::Find(_) => panic!(
+                "Finding {} can't be used when building since it requires a connection",
+                std::any::type_name::<M>()
+            ),
         }
     }
 }
 
-/// A generic factory trait.
+/// How an association is set up when its factory is inserted.
 ///
-/// You shouldn't ever have to implement this trait yourself. It can be derived using
-/// `#[derive(Factory)]`
+/// Passed to the `<association>_strategy` builder methods, along with [`FindBy`], to pick a
+/// strategy at the call site. That lets one factory serve tests that need the whole graph
+/// inserted, tests against reference data, and tests that don't care about the association:
 ///
-/// See the [root module docs](/) for info on how to use `#[derive(Factory)]`.
-pub trait Factory: Clone {
-    /// The model type the factory inserts.
-    ///
-    /// For a factory named `UserFactory` this would probably be `Use`.
-    type Model;
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// use diesel_factories::{FindBy, Strategy};
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let denmark = CountryFactory::default().insert(&con);
+///
+/// let city = CityFactory::default()
+///     .country_strategy(FindBy(schema::countries::name.eq("Denmark")))
+///     .insert(&con);
+/// assert_eq!(denmark.id, city.country_id);
+///
+/// let city = CityFactory::default()
+///     .country(&denmark)
+///     .country_strategy(Strategy::Create)
+///     .insert(&con);
+/// assert_ne!(denmark.id, city.country_id);
+/// # }
+/// ```
+///
+/// [`FindBy`]: struct.FindBy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Insert the association's factory, or a default factory if the association is set to
+    /// something else. This is what associations normally do.
+    Create,
 
-    /// The primary key type your model uses.
+    /// Only build the association's factory, or a default factory if the association is set to
+    /// something else, with [`BuildFactory`] when the factory it belongs to is built.
     ///
-    /// This will normally be i32 or i64 but can be whatever you need.
-    type Id: Clone;
+    /// Inserting the factory it belongs to returns an error rather than making up a foreign key
+    /// that doesn't reference a row. See [`Association::Built`].
+    ///
+    /// [`BuildFactory`]: trait.BuildFactory.html
+    /// [`Association::Built`]: enum.Association.html#variant.Built
+    Build,
+}
 
-    /// The database connection type you use such as `diesel::pg::PgConnection`.
-    type Connection;
+/// A strategy that looks up an existing row with a filter when inserting, instead of inserting a
+/// new one.
+///
+/// The filter is a Diesel expression on the association's table, such as
+/// `countries::name.eq("Denmark")`. The first matching row is used, and the insert fails with
+/// `NotFound` if there aren't any. The lookup uses the association factory's
+/// [`Factory::Connection`]. See [`Strategy`] for an example.
+///
+/// [`Factory::Connection`]: trait.Factory.html#associatedtype.Connection
+/// [`Strategy`]: enum.Strategy.html
+#[derive(Debug, Clone, Copy)]
+pub struct FindBy<Filter>(pub Filter);
 
-    /// Insert the factory into the database.
-    ///
-    /// # Panics
-    /// This will panic if the insert fails. Should be fine since you want panics early in tests.
-    fn insert(self, con: &Self::Connection) -> Self::Model;
+/// Strategies that can be given to the `<association>_strategy` builder methods.
+///
+/// Implemented by [`Strategy`] and [`FindBy`].
+///
+/// [`Strategy`]: enum.Strategy.html
+/// [`FindBy`]: struct.FindBy.html
+pub trait AssociationStrategy<'a, M, F> {
+    /// Change the association to use the strategy. `None` is for optional associations that
+    /// aren't set.
+    fn apply(self, association: Option<Association<'a, M, F>>) -> Association<'a, M, F>;
+}
 
-    /// Get the primary id value for a model type.
+impl<'a, M, F: Default> AssociationStrategy<'a, M, F> for Strategy {
+    fn apply(self, association: Option<Association<'a, M, F>>) -> Association<'a, M, F> {
+        let factory = match association {
+            Some(Association::Factory(factory)) | Some(Association::Built(factory)) => factory,
+            _ => Box::default(),
+        };
+        match self {
+            Strategy::Create => Association::Factory(factory),
+            Strategy::Build => Association::Built(factory),
+        }
+    }
+}
+
+impl<'a, M, F, Filter> AssociationStrategy<'a, M, F> for FindBy<Filter>
+where
+    F: Factory + for<'c> FindModel<Filter, &'c <F as Factory>::Connection>,
+    <F as Factory>::Connection: 'static,
+    <F as factory_v2::Factory>::Id: Send + Sync + 'static,
+    Filter: Clone + Send + Sync + 'static,
+{
+    fn apply(self, _: Option<Association<'a, M, F>>) -> Association<'a, M, F> {
+        let filter = self.0;
+        let find = move |con: &dyn Any| {
+            let con = con
+                .downcast_ref::<<F as Factory>::Connection>()
+                .ok_or_else(|| {
+                    diesel::result::Error::QueryBuilderError(
+                        format!(
+                            "{} can only be found with {}",
+                            std::any::type_name::<M>(),
+                            std::any::type_name::<<F as Factory>::Connection>()
+                        )
+                        .into(),
+                    )
+                })?;
+            let model = F::find_model(filter.clone(), con)?;
+            let id = <F as factory_v2::Factory>::id_for_model(&model).clone();
+            Ok(AssociationId(Arc::new(id)))
+        };
+        Association::Find(AssociationFinder(Arc::new(find)))
+    }
+}
+
+/// Load a model by its primary key. Implemented by `#[derive(Factory)]`, and used to load
+/// associated rows that already exist for `insert_with_associations`.
+#[doc(hidden)]
+pub trait FindById: Factory {
+    fn find_by_id(id: &Self::Id, con: &Self::Connection) -> QueryResult<Self::Model>;
+}
+
+/// Insert a factory along with its associations, returning their models as well.
+///
+/// Implemented by `#[derive(Factory)]` for every factory. Factories with associations also get an
+/// `insert_with_associations` method, which panics if inserting fails. `Associations` is a
+/// struct named after the factory holding the associated models, or `()` for factories without
+/// associations. See the [root module docs](/) for more info.
+pub trait InsertWithAssociations: Factory {
+    /// The models of the factory's associations.
+    type Associations;
+
+    /// Insert the factory along with its associations, returning the associated models as well.
+    fn insert_returning_associations(
+        self,
+        con: &Self::Connection,
+    ) -> QueryResult<(Self::Model, Self::Associations)>;
+}
+
+/// Load the first row of a factory's table matching a filter.
+///
+/// `#[derive(Factory)]` implements this for each connection type, and every filter Diesel can
+/// run on the factory's table. Used by [`FindBy`].
+///
+/// [`FindBy`]: struct.FindBy.html
+pub trait FindModel<Filter, Con>: factory_v2::Factory {
+    /// Load the first row matching `filter`.
+    fn find_model(filter: Filter, con: Con) -> QueryResult<Self::Model>;
+}
+
+/// A "belongs to" association that can point to one of several tables.
+///
+/// Used for tables with both an id column and a column saying which table the id belongs to, such
+/// as `commentable_id` and `commentable_type`. The field needs the `polymorphic` attribute naming
+/// the column prefix, and `types` listing the model types it can point to. The type column is
+/// set to the name of the model's type.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # mod comments_schema {
+/// #     table! {
+/// #         comments (id) {
+/// #             id -> Integer,
+/// #             body -> Text,
+/// #             commentable_id -> Integer,
+/// #             commentable_type -> Text,
+/// #         }
+/// #     }
+/// # }
+/// #
+/// # #[derive(Clone, Queryable)]
+/// # struct Comment {
+/// #     pub id: i32,
+/// #     pub body: String,
+/// #     pub commentable_id: i32,
+/// #     pub commentable_type: String,
+/// # }
+/// #
+/// use diesel_factories::PolymorphicAssociation;
+///
+/// #[derive(Clone, Factory)]
+/// #[factory(model = "Comment", table = "crate::comments_schema::comments")]
+/// struct CommentFactory<'a> {
+///     pub body: String,
+///     #[factory(polymorphic = "commentable", types("Country", "City"))]
+///     pub commentable: PolymorphicAssociation<'a>,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let country = CountryFactory::default().insert(&con);
+///
+/// let comment = CommentFactory {
+///     body: "Nice".into(),
+///     commentable: PolymorphicAssociation::factory(CountryFactory::default()),
+/// }
+/// .commentable_country(&country)
+/// .insert(&con);
+///
+/// assert_eq!(country.id, comment.commentable_id);
+/// assert_eq!("Country", comment.commentable_type);
+/// # }
+/// ```
+///
+/// Besides the usual builder method taking a `PolymorphicAssociation`, a builder method is
+/// generated for each type, named like the field followed by the type in snake case. It takes the
+/// model's `id`, or the field named by the field's `primary_key` attribute for models keyed by
+/// something else, such as a slug with `PolymorphicAssociation<'a, String>`.
+pub struct PolymorphicAssociation<'a, Id = i32> {
+    type_name: &'static str,
+    target: PolymorphicTarget<'a, Id>,
+}
+
+enum PolymorphicTarget<'a, Id> {
+    Id(Id),
+    Factory(Arc<InsertReturningId<'a, Id>>, Arc<PlanInsert<'a>>),
+}
+
+type InsertReturningId<'a, Id> = dyn Fn(&dyn Any) -> QueryResult<Id> + Send + Sync + 'a;
+
+type PlanInsert<'a> = dyn Fn(&mut InsertPlan) -> usize + Send + Sync + 'a;
+
+impl<Id: Clone> Clone for PolymorphicAssociation<'_, Id> {
+    fn clone(&self) -> Self {
+        let target = match &self.target {
+            PolymorphicTarget::Id(id) => PolymorphicTarget::Id(id.clone()),
+            PolymorphicTarget::Factory(insert, plan) => {
+                PolymorphicTarget::Factory(Arc::clone(insert), Arc::clone(plan))
+            }
+        };
+        PolymorphicAssociation {
+            type_name: self.type_name,
+            target,
+        }
+    }
+}
+
+impl<'a, Id: Clone> PolymorphicAssociation<'a, Id> {
+    /// Point to an existing row.
+    pub fn model(type_name: &'static str, id: Id) -> Self {
+        PolymorphicAssociation {
+            type_name,
+            target: PolymorphicTarget::Id(id),
+        }
+    }
+
+    /// Point to a row that will be inserted using the factory.
     ///
-    /// Just a generic wrapper around `model.id`.
-    fn id_for_model(model: &Self::Model) -> &Self::Id;
+    /// The factory is inserted using its [`Factory::Connection`] and the type is the name of its
+    /// model type.
+    ///
+    /// [`Factory::Connection`]: trait.Factory.html#associatedtype.Connection
+    pub fn factory<F>(factory: F) -> Self
+    where
+        F: Factory
+            + factory_v2::Factory<Id = Id>
+            + for<'c> factory_v2::Insert<&'c <F as Factory>::Connection>
+            + Plan
+            + Clone
+            + Send
+            + Sync
+            + 'a,
+        F::Connection: 'static,
+    {
+        let type_name = short_type_name::<<F as factory_v2::Factory>::Model>();
+        let plan_factory = factory.clone();
+        let plan = move |plan: &mut InsertPlan| plan_factory.plan_into(plan);
+        let insert = move |con: &dyn Any| {
+            let con = con.downcast_ref::<F::Connection>().unwrap_or_else(|| {
+                panic!(
+                    "{} can only be inserted with {}",
+                    std::any::type_name::<F>(),
+                    std::any::type_name::<F::Connection>()
+                )
+            });
+            let model = factory_v2::Insert::insert(factory.clone(), con)?;
+            Ok(<F as factory_v2::Factory>::id_for_model(&model).clone())
+        };
+
+        PolymorphicAssociation {
+            type_name,
+            target: PolymorphicTarget::Factory(Arc::new(insert), Arc::new(plan)),
+        }
+    }
+
+    /// Name of the type the association points to.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    #[doc(hidden)]
+    pub fn insert_returning_id(&self, con: &dyn Any, types: &[&str]) -> QueryResult<Id> {
+        if !types.contains(&self.type_name) {
+            panic!(
+                "{} isn't one of the polymorphic association's types: {}",
+                self.type_name,
+                types.join(", ")
+            );
+        }
+
+        match &self.target {
+            PolymorphicTarget::Id(id) => Ok(id.clone()),
+            PolymorphicTarget::Factory(insert, _) => insert(con),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn plan_key(&self, plan: &mut InsertPlan) -> PlannedKey
+    where
+        Id: std::fmt::Debug,
+    {
+        match &self.target {
+            PolymorphicTarget::Id(id) => PlannedKey::Id(format!("{:?}", id)),
+            PolymorphicTarget::Factory(_, plan_insert) => PlannedKey::Insert(plan_insert(plan)),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn dry_run_key<ST>(&self) -> dry_run::Key<Id, ST> {
+        match &self.target {
+            PolymorphicTarget::Id(id) => dry_run::Key::bound(id.clone()),
+            PolymorphicTarget::Factory(..) => {
+                dry_run::Key::pending(format!("id of new {}", self.type_name))
+            }
+        }
+    }
 }
 
-lazy_static! {
-    static ref SEQUENCE_COUNTER: AtomicUsize = { AtomicUsize::new(0) };
+impl<Id: std::fmt::Debug> std::fmt::Debug for PolymorphicAssociation<'_, Id> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("PolymorphicAssociation");
+        debug.field("type_name", &self.type_name);
+        match &self.target {
+            PolymorphicTarget::Id(id) => debug.field("id", id),
+            PolymorphicTarget::Factory(..) => debug.field("factory", &".."),
+        };
+        debug.finish()
+    }
 }
 
-/// Utility function for generating unique ids or strings in factories.
-/// Each time `sequence` gets called, the closure will receive a different number.
+fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// The value of a factory field for a column with a database default.
+///
+/// An `Option` field can only insert a value or `NULL`. With `FieldValue`, the column can also be
+/// left out of the insert, so the database's default applies, such as a `DEFAULT now()` or a
+/// generated column:
 ///
 /// ```
-/// use diesel_factories::sequence;
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// use diesel_factories::FieldValue;
 ///
-/// assert_ne!(
-///     sequence(|i| format!("unique-string-{}", i)),
-///     sequence(|i| format!("unique-string-{}", i)),
-/// );
+/// #[derive(Clone, Factory)]
+/// #[factory(model = "Country", table = "crate::schema::countries")]
+/// struct DefaultedCountryFactory {
+///     #[factory(default = "FieldValue::Default")]
+///     pub name: FieldValue<String>,
+/// }
+///
+/// # fn main() {
+/// // Inserts the column's default
+/// let factory = DefaultedCountryFactory::default();
+/// assert_eq!(FieldValue::Default, factory.name);
+///
+/// // Inserts a value
+/// let factory = factory.name("Denmark");
+/// assert_eq!(FieldValue::Value("Denmark".to_string()), factory.name);
+///
+/// // `None` inserts `NULL`
+/// let factory = factory.name(None);
+/// assert_eq!(FieldValue::Null, factory.name);
+/// # }
 /// ```
-pub fn sequence<T, F>(f: F) -> T
-where
-    F: Fn(usize) -> T,
-{
-    SEQUENCE_COUNTER.fetch_add(1, Ordering::SeqCst);
-    let count = SEQUENCE_COUNTER.load(Ordering::Relaxed);
-    f(count)
+///
+/// Factories that also `build` models build `None` for `Default` and `Null`, so the model's field
+/// has to be an `Option`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FieldValue<T> {
+    /// Leave the column out of the insert, so the database's default is used.
+    #[default]
+    Default,
+    /// Insert `NULL`.
+    Null,
+    /// Insert a value.
+    Value(T),
+}
+
+impl<T> From<T> for FieldValue<T> {
+    fn from(value: T) -> Self {
+        FieldValue::Value(value)
+    }
+}
+
+impl From<&str> for FieldValue<String> {
+    fn from(value: &str) -> Self {
+        FieldValue::Value(value.to_string())
+    }
+}
+
+impl<T> From<Option<T>> for FieldValue<T> {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => FieldValue::Value(value),
+            None => FieldValue::Null,
+        }
+    }
+}
+
+impl<T> From<FieldValue<T>> for Option<T> {
+    fn from(value: FieldValue<T>) -> Self {
+        match value {
+            FieldValue::Value(value) => Some(value),
+            FieldValue::Default | FieldValue::Null => None,
+        }
+    }
+}
+
+impl<T> FieldValue<T> {
+    /// The expression to insert, or `None` to leave the column out. Used by `#[derive(Factory)]`.
+    #[doc(hidden)]
+    pub fn insert_value<ST>(&self) -> Option<ColumnValue<'_, T, ST>> {
+        match self {
+            FieldValue::Default => None,
+            FieldValue::Null => Some(ColumnValue::Null(PhantomData)),
+            FieldValue::Value(value) => Some(ColumnValue::Value(value)),
+        }
+    }
+}
+
+/// A `FieldValue` being inserted. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+#[derive(Debug)]
+pub enum ColumnValue<'a, T, ST> {
+    Null(PhantomData<ST>),
+    Value(&'a T),
+}
+
+impl<T, ST> Expression for ColumnValue<'_, T, ST> {
+    type SqlType = ST;
+}
+
+impl<T, ST, QS> AppearsOnTable<QS> for ColumnValue<'_, T, ST> {}
+
+impl<T, ST> QueryId for ColumnValue<'_, T, ST> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T, ST, DB> QueryFragment<DB> for ColumnValue<'_, T, ST>
+where
+    DB: Backend + HasSqlType<ST>,
+    T: ToSql<ST, DB>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, DB>) -> QueryResult<()> {
+        match self {
+            ColumnValue::Null(_) => {
+                out.push_sql("NULL");
+                Ok(())
+            }
+            ColumnValue::Value(value) => out.push_bind_param::<ST, T>(value),
+        }
+    }
+}
+
+/// A factory that can be shared between several associations, and is only inserted once.
+///
+/// Normally each `Association` holding a factory inserts its own record. If two associations
+/// should point to the same record that hasn't been inserted yet, wrap the factory in `Shared`
+/// and give each association a clone of it. The first insert memoizes the created model and the
+/// other associations reuse its id.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// # #[derive(Clone, Factory)]
+/// # #[factory(
+/// #     model = "City",
+/// #     table = "crate::schema::cities",
+/// # )]
+/// # struct CityFactory<'a> {
+/// #     pub name: String,
+/// #     pub country: Association<'a, Country, CountryFactory>,
+/// # }
+/// #
+/// # impl<'a> Default for CityFactory<'a> {
+/// #     fn default() -> Self {
+/// #         Self {
+/// #             name: "Copenhagen".into(), country: Association::default(),
+/// #         }
+/// #     }
+/// # }
+/// #
+/// use diesel_factories::Shared;
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let norway = Shared::new(CountryFactory::default().name("Norway"));
+///
+/// let oslo = CityFactory::default().country(norway.clone()).insert(&con);
+/// let bergen = CityFactory::default().country(norway.clone()).insert(&con);
+///
+/// assert_eq!(oslo.country_id, bergen.country_id);
+/// assert_eq!(Some(oslo.country_id), norway.model().map(|country| country.id));
+/// # }
+/// ```
+///
+/// Clones of a `Shared` all refer to the same factory.
+///
+/// If inserting the factory fails it's kept, so it can be inserted again, such as after rolling
+/// back [`insert_in_savepoint`]. That requires cloning it before each insert, so factories that
+/// aren't `Clone` are shared with [`Shared::without_retry`] instead.
+///
+/// [`insert_in_savepoint`]: trait.Factory.html#method.insert_in_savepoint
+/// [`Shared::without_retry`]: struct.Shared.html#method.without_retry
+#[derive(Debug)]
+pub struct Shared<Model, Factory> {
+    inner: Arc<Mutex<SharedState<Model, Factory>>>,
+    clone_factory: Option<fn(&Factory) -> Factory>,
+}
+
+#[derive(Debug)]
+enum SharedState<Model, Factory> {
+    Factory(Factory),
+    /// The factory was built in memory, which is reused by later builds. It's inserted as if it
+    /// weren't built.
+    Built(Factory, Model),
+    Model(Model),
+    /// The factory was taken to be inserted, but the insert failed and it couldn't be cloned.
+    Failed,
+}
+
+impl<Model, Factory> SharedState<Model, Factory> {
+    /// Take the factory out to insert it, leaving a clone until the model is stored so a failed
+    /// insert keeps the factory, or `Failed` if it can't be cloned.
+    fn take_factory(&mut self, clone_factory: Option<fn(&Factory) -> Factory>) -> Factory {
+        let kept = match (&*self, clone_factory) {
+            (SharedState::Factory(factory), Some(clone_factory))
+            | (SharedState::Built(factory, _), Some(clone_factory)) => {
+                SharedState::Factory(clone_factory(factory))
+            }
+            _ => SharedState::Failed,
+        };
+        match std::mem::replace(self, kept) {
+            SharedState::Factory(factory) | SharedState::Built(factory, _) => factory,
+            _ => panic!("Shared factory was already taken"),
+        }
+    }
+}
+
+fn shared_failed<F>() -> ! {
+    panic!(
+        "Shared {} can't be used since inserting it failed",
+        std::any::type_name::<F>()
+    )
+}
+
+impl<Model, Factory> Clone for Shared<Model, Factory> {
+    fn clone(&self) -> Self {
+        Shared {
+            inner: Arc::clone(&self.inner),
+            clone_factory: self.clone_factory,
+        }
+    }
+}
+
+impl<Model, Factory> Shared<Model, Factory> {
+    /// Create a new shared factory.
+    pub fn new(factory: Factory) -> Self
+    where
+        Factory: Clone,
+    {
+        Shared {
+            inner: Arc::new(Mutex::new(SharedState::Factory(factory))),
+            clone_factory: Some(Factory::clone),
+        }
+    }
+
+    /// Create a new shared factory that doesn't have to be `Clone`.
+    ///
+    /// If inserting it fails, the factory is lost and using the `Shared` again panics.
+    pub fn without_retry(factory: Factory) -> Self {
+        Shared {
+            inner: Arc::new(Mutex::new(SharedState::Factory(factory))),
+            clone_factory: None,
+        }
+    }
+
+    /// The inserted model, if the factory has been inserted.
+    pub fn model(&self) -> Option<Model>
+    where
+        Model: Clone,
+    {
+        match &*self.state() {
+            SharedState::Model(model) => Some(model.clone()),
+            SharedState::Factory(_) | SharedState::Built(..) | SharedState::Failed => None,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SharedState<Model, Factory>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Identifies the factory, which all clones share.
+    fn key(&self) -> usize {
+        Arc::as_ptr(&self.inner) as *const () as usize
+    }
+}
+
+impl<M, F> Shared<M, F>
+where
+    F: factory_v2::Factory<Model = M>,
+{
+    fn insert_returning_id<Con>(&self, con: Con) -> QueryResult<F::Id>
+    where
+        F: factory_v2::Insert<Con>,
+    {
+        let mut state = self.state();
+
+        let model = match &*state {
+            SharedState::Model(model) => return Ok(F::id_for_model(model).clone()),
+            SharedState::Factory(_) | SharedState::Built(..) => {
+                state.take_factory(self.clone_factory).insert(con)?
+            }
+            SharedState::Failed => shared_failed::<F>(),
+        };
+
+        let id = F::id_for_model(&model).clone();
+        *state = SharedState::Model(model);
+        Ok(id)
+    }
+}
+
+impl<M, F> Shared<M, F>
+where
+    F: factory_v2::Factory<Model = M> + Plan,
+    F::Id: std::fmt::Debug,
+{
+    fn plan_key(&self, plan: &mut InsertPlan) -> PlannedKey {
+        match &*self.state() {
+            SharedState::Model(model) => PlannedKey::Id(format!("{:?}", F::id_for_model(model))),
+            SharedState::Factory(factory) | SharedState::Built(factory, _) => {
+                PlannedKey::Insert(plan.push_shared(self.key(), |plan| factory.plan_into(plan)))
+            }
+            SharedState::Failed => shared_failed::<F>(),
+        }
+    }
+}
+
+impl<M, F> Shared<M, F>
+where
+    F: BuildFactory<Model = M> + Clone,
+    F::Id: FakeId + 'static,
+{
+    /// The id of the model built from the factory, building it the first time, or of the
+    /// inserted model.
+    fn build_returning_id(&self) -> F::Id {
+        let mut state = self.state();
+        if let SharedState::Factory(factory) = &*state {
+            let model = factory.clone().build();
+            if let SharedState::Factory(factory) =
+                std::mem::replace(&mut *state, SharedState::Failed)
+            {
+                *state = SharedState::Built(factory, model);
+            }
+        }
+
+        match &*state {
+            SharedState::Model(model) | SharedState::Built(_, model) => {
+                F::id_for_model(model).clone()
+            }
+            SharedState::Factory(_) => unreachable!("Shared factory wasn't built"),
+            SharedState::Failed => shared_failed::<F>(),
+        }
+    }
+}
+
+/// A generic factory trait.
+///
+/// You shouldn't ever have to implement this trait yourself. It can be derived using
+/// `#[derive(Factory)]`
+///
+/// See the [root module docs](/) for info on how to use `#[derive(Factory)]`.
+pub trait Factory: Sized {
+    /// The model type the factory inserts.
+    ///
+    /// For a factory named `UserFactory` this would probably be `Use`.
+    type Model;
+
+    /// The primary key type your model uses.
+    ///
+    /// This will normally be i32 or i64 but can be whatever you need.
+    type Id: Clone;
+
+    /// The database connection type you use such as `diesel::pg::PgConnection`.
+    type Connection;
+
+    /// Insert the factory into the database.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails. Should be fine since you want panics early in tests.
+    fn insert(self, con: &Self::Connection) -> Self::Model;
+
+    /// Get the primary id value for a model type.
+    ///
+    /// Just a generic wrapper around `model.id`.
+    fn id_for_model(model: &Self::Model) -> &Self::Id;
+
+    /// Insert a number of children along with the factory.
+    ///
+    /// Requires a "has many" relationship declared with `#[factory(has_many(...))]`. See
+    /// [`HasMany`] for more info.
+    ///
+    /// [`HasMany`]: trait.HasMany.html
+    fn with_children<C>(self, count: usize) -> WithChildren<Self, C>
+    where
+        Self: HasMany<C>,
+        C: Factory,
+    {
+        WithChildren {
+            parent: self,
+            count,
+            child: PhantomData,
+        }
+    }
+
+    /// Wrap the factory in a [`Shared`], so associations set to it insert it only once.
+    ///
+    /// [`Shared`]: struct.Shared.html
+    fn shared(self) -> Shared<Self::Model, Self>
+    where
+        Self: Clone,
+    {
+        Shared::new(self)
+    }
+
+    /// Insert the factory inside a savepoint, returning errors rather than panicking.
+    ///
+    /// If the insert, or the insert of an association, fails the savepoint is rolled back so the
+    /// connection's outer transaction can still be used. Useful for testing constraints. See
+    /// [`with_savepoint`] for more info.
+    ///
+    /// [`with_savepoint`]: fn.with_savepoint.html
+    fn insert_in_savepoint(self, con: &Self::Connection) -> QueryResult<<Self as Factory>::Model>
+    where
+        Self: for<'c> factory_v2::Insert<&'c Self::Connection>
+            + factory_v2::Factory<Model = <Self as Factory>::Model>,
+        Self::Connection: Connection,
+    {
+        with_savepoint(con, || factory_v2::Insert::insert(self, con))
+    }
+
+    /// Insert the factory, returning only its primary key.
+    ///
+    /// Only the primary key is returned by the insert, which is useful when the model can't be
+    /// loaded from the inserted row, or when the caller doesn't need it. See
+    /// [`factory_v2::InsertReturningId`].
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    ///
+    /// [`factory_v2::InsertReturningId`]: factory_v2/trait.InsertReturningId.html
+    fn insert_returning_id(self, con: &Self::Connection) -> <Self as Factory>::Id
+    where
+        Self: for<'c> factory_v2::InsertReturningId<&'c Self::Connection>
+            + factory_v2::Factory<Id = <Self as Factory>::Id>,
+    {
+        factory_v2::InsertReturningId::insert_returning_id(self, con).unwrap_or_else(|err| {
+            match take_failed_insert() {
+                Some(trace) => panic!("{} failed: {}", trace, err),
+                None => panic!("Insert failed: {}", err),
+            }
+        })
+    }
+}
+
+/// Insert a factory using one of several connection types.
+///
+/// `#[derive(Factory)]` implements this for each `connection` attribute given, which lets the
+/// same factories be used with several connection types, such as a plain connection and a pooled
+/// one. The first connection is used as [`Factory::Connection`].
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// use diesel_factories::InsertWith;
+///
+/// fn insert_country<Con>(con: &Con) -> Country
+/// where
+///     CountryFactory: InsertWith<Con>,
+/// {
+///     CountryFactory::default().name("Denmark").insert_with(con)
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let country = insert_country(&con);
+/// assert_eq!("Denmark", country.name);
+/// # }
+/// ```
+///
+/// Associations are inserted using the same connection, so the associated factories must also
+/// list the connection.
+///
+/// This is implemented for every factory implementing [`factory_v2::Insert`] for `&Con`.
+///
+/// [`Factory::Connection`]: trait.Factory.html#associatedtype.Connection
+/// [`factory_v2::Insert`]: factory_v2/trait.Insert.html
+pub trait InsertWith<Con>: Factory {
+    /// Insert the factory into the database using the given connection.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_with(self, con: &Con) -> Self::Model;
+}
+
+impl<F, Con> InsertWith<Con> for F
+where
+    F: Factory + for<'a> factory_v2::Insert<&'a Con, Model = <F as Factory>::Model>,
+{
+    fn insert_with(self, con: &Con) -> Self::Model {
+        factory_v2::Insert::insert(self, con).unwrap_or_else(|err| match take_failed_insert() {
+            Some(trace) => panic!("{} failed: {}", trace, err),
+            None => panic!("Insert failed: {}", err),
+        })
+    }
+}
+
+/// Insert several factories at once. Implemented for `Vec`s of factories.
+///
+/// Rows for the same table are inserted with one statement, including the rows of associations.
+/// [`Shared`] associations are inserted once, however many factories refer to them:
+///
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// use diesel_factories::InsertAll;
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let norway = CountryFactory::default().name("Norway").shared();
+///
+/// let cities = vec!["Oslo", "Bergen", "Trondheim"]
+///     .into_iter()
+///     .map(|name| CityFactory::default().name(name).country(norway.clone()))
+///     .collect::<Vec<_>>()
+///     .insert_all(&con);
+///
+/// assert!(cities.iter().all(|city| city.country_id == cities[0].country_id));
+/// # }
+/// ```
+///
+/// [`Shared`]: struct.Shared.html
+pub trait InsertAll<Con> {
+    /// The model type of the factories.
+    type Model;
+
+    /// Insert the factories, returning their models in order.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_all(self, con: &Con) -> Vec<Self::Model>;
+}
+
+impl<F, Con> InsertAll<Con> for Vec<F>
+where
+    F: Factory + for<'a> factory_v2::InsertBatch<&'a Con, Model = <F as Factory>::Model>,
+{
+    type Model = <F as Factory>::Model;
+
+    fn insert_all(self, con: &Con) -> Vec<Self::Model> {
+        factory_v2::InsertBatch::insert_batch(self, con).unwrap_or_else(|err| {
+            match take_failed_insert() {
+                Some(trace) => panic!("{} failed: {}", trace, err),
+                None => panic!("Insert failed: {}", err),
+            }
+        })
+    }
+}
+
+/// An object safe version of [`Factory`], for storing different factories together.
+///
+/// Implemented for every factory that can be inserted with `Con`. The inserted model is returned
+/// as a [`DynModel`].
+///
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// use diesel_factories::DynFactory;
+///
+/// let factories: Vec<Box<dyn DynFactory<PgConnection>>> = vec![
+///     Box::new(CountryFactory::default()),
+///     Box::new(CityFactory::default()),
+/// ];
+///
+/// for factory in factories {
+///     let name = factory.factory_name();
+///     let inserted = factory.insert_dyn(&con);
+///     println!("{} inserted row {}", name, inserted.id);
+/// }
+/// # }
+/// ```
+///
+/// [`Factory`]: trait.Factory.html
+/// [`DynModel`]: struct.DynModel.html
+pub trait DynFactory<Con> {
+    /// Insert the factory into the database.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_dyn(self: Box<Self>, con: &Con) -> DynModel;
+
+    /// Name of the factory type.
+    fn factory_name(&self) -> &'static str;
+}
+
+impl<F, Con> DynFactory<Con> for F
+where
+    F: InsertWith<Con>,
+    F::Model: 'static,
+    F::Id: ToString,
+{
+    fn insert_dyn(self: Box<Self>, con: &Con) -> DynModel {
+        let model = (*self).insert_with(con);
+        DynModel {
+            id: F::id_for_model(&model).to_string(),
+            model: Box::new(model),
+        }
+    }
+
+    fn factory_name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+}
+
+/// A model inserted through [`DynFactory`].
+///
+/// [`DynFactory`]: trait.DynFactory.html
+#[derive(Debug)]
+pub struct DynModel {
+    /// The model's primary key, formatted as a string.
+    pub id: String,
+
+    /// The model itself. Use `downcast_ref` to get it back.
+    pub model: Box<dyn Any>,
+}
+
+/// A factory for changeset structs, used for testing updates.
+///
+/// Derive it with `#[derive(ChangesetFactory)]` and the `changeset` attribute naming a type that
+/// derives Diesel's `AsChangeset`. The factory must have the same fields as the changeset. It
+/// gets builder methods like `#[derive(Factory)]` does. Builders for `Option` fields take the
+/// inner value, and a `clear_<field>` builder sets the field back to `None`. The field attributes
+/// `map`, `default`, `default_with` and `fake` are supported.
+///
+/// `Default` is derived where possible, with `Option` fields defaulting to `None` which leaves
+/// their columns unchanged.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// use diesel_factories::ChangesetFactory;
+/// use schema::countries;
+///
+/// #[derive(AsChangeset)]
+/// #[table_name = "countries"]
+/// struct CountryChangeset {
+///     name: Option<String>,
+/// }
+///
+/// #[derive(Clone, ChangesetFactory)]
+/// #[factory(changeset = "CountryChangeset")]
+/// struct CountryChangesetFactory {
+///     name: Option<String>,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let country = CountryFactory::default().insert(&con);
+///
+/// let changeset = CountryChangesetFactory::default().name("Norway").build();
+/// let country = diesel::update(countries::table.find(country.id))
+///     .set(&changeset)
+///     .get_result::<Country>(&con)
+///     .unwrap();
+///
+/// assert_eq!("Norway", country.name);
+/// # }
+/// ```
+pub trait ChangesetFactory: Clone {
+    /// The changeset type the factory builds.
+    type Changeset;
+
+    /// Build the changeset.
+    fn build(self) -> Self::Changeset;
+}
+
+/// A factory that can build its model in memory, without touching the database.
+///
+/// Implemented by `#[derive(Factory)]` when the `build` attribute is given. The model is
+/// constructed with a struct literal, so it must have an `id` field and a field for each of the
+/// factory's fields, where `Association` fields are named like the association followed by
+/// `_id`.
+///
+/// Associations are resolved to the id of the referenced model. Associations that hold a factory
+/// are built as well and get a fake id, so no records are inserted.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// #[derive(Clone, Factory)]
+/// #[factory(model = "Country", table = "crate::schema::countries", build)]
+/// struct BuildableCountryFactory {
+///     pub name: String,
+/// }
+///
+/// # fn main() {
+/// use diesel_factories::BuildFactory;
+///
+/// let country = BuildableCountryFactory { name: "Denmark".into() }.build_with_id(42);
+/// assert_eq!(42, country.id);
+/// assert_eq!("Denmark", country.name);
+///
+/// let one = BuildableCountryFactory { name: "Norway".into() }.build();
+/// let two = BuildableCountryFactory { name: "Sweden".into() }.build();
+/// assert_ne!(one.id, two.id);
+/// # }
+/// ```
+pub trait BuildFactory: Factory {
+    /// Build the model in memory with the given primary key.
+    fn build_with_id(self, id: Self::Id) -> Self::Model;
+
+    /// Build the model in memory with a fake primary key generated using [`sequence`].
+    ///
+    /// [`sequence`]: fn.sequence.html
+    fn build(self) -> Self::Model
+    where
+        Self::Id: FakeId,
+    {
+        let id = sequence(Self::Id::fake_id);
+        self.build_with_id(id)
+    }
+}
+
+/// Primary key types that can be faked when building models without the database.
+///
+/// See [`BuildFactory`] for more info.
+///
+/// [`BuildFactory`]: trait.BuildFactory.html
+pub trait FakeId {
+    /// Create a fake id from a sequence number.
+    fn fake_id(n: usize) -> Self;
+}
+
+macro_rules! impl_fake_id_for_numbers {
+    ( $($ty:ty),* ) => {
+        $(
+            impl FakeId for $ty {
+                fn fake_id(n: usize) -> Self {
+                    n as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_fake_id_for_numbers!(i16, i32, i64, u16, u32, u64);
+
+impl FakeId for String {
+    fn fake_id(n: usize) -> Self {
+        n.to_string()
+    }
+}
+
+/// A "has many" relationship between two factories.
+///
+/// Implemented by `#[derive(Factory)]` for each `has_many` attribute. `factory` is the child
+/// factory and `association` is the child's `Association` field that points back to the parent.
+/// The child factory must implement `Default` and the association field must be visible from
+/// where the parent factory is declared.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// #[derive(Clone, Factory)]
+/// #[factory(
+///     model = "Country",
+///     table = "crate::schema::countries",
+///     has_many(factory = "CityFactory<'a>", association = "country"),
+/// )]
+/// struct CountryWithCitiesFactory {
+///     pub name: String,
+/// }
+///
+/// #[derive(Clone, Factory)]
+/// #[factory(
+///     model = "City",
+///     table = "crate::schema::cities",
+/// )]
+/// struct CityFactory<'a> {
+///     pub name: String,
+///     pub country: Association<'a, Country, CountryFactory>,
+/// }
+/// #
+/// # impl<'a> Default for CityFactory<'a> {
+/// #     fn default() -> Self {
+/// #         Self {
+/// #             name: "Copenhagen".into(), country: Association::default(),
+/// #         }
+/// #     }
+/// # }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let (country, cities) = CountryWithCitiesFactory { name: "Denmark".into() }
+///     .with_children::<CityFactory>(5)
+///     .insert(&con);
+///
+/// assert_eq!(5, cities.len());
+/// assert!(cities.iter().all(|city| city.country_id == country.id));
+/// # }
+/// ```
+pub trait HasMany<C: Factory>: Factory {
+    /// Insert `count` default children belonging to `parent`.
+    fn insert_children(parent: &Self::Model, count: usize, con: &Self::Connection)
+        -> Vec<C::Model>;
+}
+
+/// A factory along with a number of children to insert with it.
+///
+/// Created with [`Factory::with_children`].
+///
+/// [`Factory::with_children`]: trait.Factory.html#method.with_children
+#[derive(Debug)]
+pub struct WithChildren<P, C> {
+    parent: P,
+    count: usize,
+    child: PhantomData<fn() -> C>,
+}
+
+impl<P, C> WithChildren<P, C>
+where
+    P: HasMany<C>,
+    C: Factory,
+{
+    /// Insert the parent followed by its children.
+    pub fn insert(self, con: &P::Connection) -> (P::Model, Vec<C::Model>) {
+        let parent = self.parent.insert(con);
+        let children = P::insert_children(&parent, self.count, con);
+        (parent, children)
+    }
+}
+
+/// A "many to many" relationship through a join table.
+///
+/// Declared with `#[factory(joins(...))]`, which generates a builder method that takes the
+/// factories on the other side. Inserting then inserts the factory, the other factories, and a
+/// row in the join table for each of them.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # mod join_schema {
+/// #     table! {
+/// #         languages (id) {
+/// #             id -> Integer,
+/// #             code -> Text,
+/// #             name -> Text,
+/// #         }
+/// #     }
+/// #
+/// #     table! {
+/// #         countries_languages (country_id, language_id) {
+/// #             country_id -> Integer,
+/// #             language_id -> Integer,
+/// #         }
+/// #     }
+/// # }
+/// #
+/// # #[derive(Clone, Queryable)]
+/// # struct Language {
+/// #     pub id: i32,
+/// #     pub code: String,
+/// #     pub name: String,
+/// # }
+/// #
+/// #[derive(Clone, Factory)]
+/// #[factory(
+///     model = "Country",
+///     table = "crate::schema::countries",
+///     joins(
+///         table = "crate::join_schema::countries_languages",
+///         left = "country_id",
+///         right = "language_id",
+///         factory = "LanguageFactory",
+///         name = "languages",
+///     ),
+/// )]
+/// struct MultilingualCountryFactory {
+///     pub name: String,
+/// }
+///
+/// #[derive(Clone, Factory)]
+/// #[factory(model = "Language", table = "crate::join_schema::languages")]
+/// struct LanguageFactory {
+///     pub code: String,
+///     pub name: String,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let (country, languages) = MultilingualCountryFactory { name: "Switzerland".into() }
+///     .languages(vec![
+///         LanguageFactory { code: "de".into(), name: "German".into() },
+///         LanguageFactory { code: "fr".into(), name: "French".into() },
+///     ])
+///     .insert(&con);
+///
+/// assert_eq!(2, languages.len());
+/// # }
+/// ```
+///
+/// | Name | Description |
+/// |---|---|
+/// | `table` | Path to the join table |
+/// | `left` | Join table column pointing to this factory's table |
+/// | `right` | Join table column pointing to the other factory's table |
+/// | `factory` | The other factory |
+/// | `name` | Name of the generated builder method |
+pub trait Joins<C: Factory>: Factory {
+    /// Insert a join table row between `parent` and `child`.
+    fn insert_join_row(parent: &Self::Model, child: &C::Model, con: &Self::Connection);
+}
+
+/// A factory along with the factories it's joined to through a join table.
+///
+/// Created with the builder method generated by `#[factory(joins(...))]`. See [`Joins`].
+///
+/// [`Joins`]: trait.Joins.html
+#[derive(Debug)]
+pub struct WithJoins<P, C> {
+    parent: P,
+    children: Vec<C>,
+}
+
+impl<P, C> WithJoins<P, C>
+where
+    P: Joins<C>,
+    C: Factory<Connection = P::Connection>,
+{
+    #[doc(hidden)]
+    pub fn new(parent: P, children: Vec<C>) -> Self {
+        WithJoins { parent, children }
+    }
+
+    /// Insert the parent, the joined records, and the join table rows.
+    pub fn insert(self, con: &P::Connection) -> (P::Model, Vec<C::Model>) {
+        let parent = self.parent.insert(con);
+        let children = self
+            .children
+            .into_iter()
+            .map(|child| {
+                let child = child.insert(con);
+                P::insert_join_row(&parent, &child, con);
+                child
+            })
+            .collect();
+        (parent, children)
+    }
+}
+
+#[doc(hidden)]
+pub trait IntoAssociation<T> {
+    fn into_association(self) -> T;
+}
+
+impl<'a, M, F> IntoAssociation<Association<'a, M, F>> for &'a M {
+    fn into_association(self) -> Association<'a, M, F> {
+        Association::Model(self)
+    }
+}
+
+impl<'a, M, F> IntoAssociation<Option<Association<'a, M, F>>> for &'a M {
+    fn into_association(self) -> Option<Association<'a, M, F>> {
+        Some(Association::Model(self))
+    }
+}
+
+/// Values the builder method of a `Vec` or `Option<Vec>` field accepts, so `.tags(["a", "b"])`
+/// works for an `Option<Vec<String>>` field.
+#[doc(hidden)]
+pub trait IntoContainer<T> {
+    fn into_container(self) -> T;
+}
+
+impl<T, U: Into<T>> IntoContainer<Vec<T>> for Vec<U> {
+    fn into_container(self) -> Vec<T> {
+        self.into_iter().map(Into::into).collect()
+    }
+}
+
+impl<T, U: Into<T>, const N: usize> IntoContainer<Vec<T>> for [U; N] {
+    fn into_container(self) -> Vec<T> {
+        IntoIterator::into_iter(self).map(Into::into).collect()
+    }
+}
+
+impl<T, U: Clone + Into<T>> IntoContainer<Vec<T>> for &[U] {
+    fn into_container(self) -> Vec<T> {
+        self.iter().cloned().map(Into::into).collect()
+    }
+}
+
+impl<T, U: Into<T>> IntoContainer<Option<Vec<T>>> for Vec<U> {
+    fn into_container(self) -> Option<Vec<T>> {
+        Some(self.into_container())
+    }
+}
+
+impl<T, U: Into<T>, const N: usize> IntoContainer<Option<Vec<T>>> for [U; N] {
+    fn into_container(self) -> Option<Vec<T>> {
+        Some(self.into_container())
+    }
+}
+
+impl<T, U: Clone + Into<T>> IntoContainer<Option<Vec<T>>> for &[U] {
+    fn into_container(self) -> Option<Vec<T>> {
+        Some(self.into_container())
+    }
+}
+
+impl<T> IntoContainer<Option<Vec<T>>> for Option<Vec<T>> {
+    fn into_container(self) -> Option<Vec<T>> {
+        self
+    }
+}
+
+/// A factory that targets the schema as it looks after some migration has been applied.
+///
+/// Implemented by `#[derive(Factory)]` when the `since_migration` attribute is given. See
+/// [`Versioned`] for how to pick between versions at runtime.
+///
+/// [`Versioned`]: enum.Versioned.html
+pub trait VersionedFactory: Factory {
+    /// The name of this version, as given with `#[factory(version = "...")]`.
+    ///
+    /// Defaults to the migration if no version is given.
+    fn version() -> &'static str;
+
+    /// The migration this factory requires, as given with `#[factory(since_migration = "...")]`.
+    fn since_migration() -> &'static str;
+
+    /// Whether the migration this factory requires has been applied.
+    fn is_current(con: &Self::Connection) -> bool;
+}
+
+/// One of two versions of a factory, or of a model, chosen based on which migrations have been
+/// applied.
+///
+/// This lets a single test suite run both before and after a long lived migration is applied:
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// #
+/// use diesel_factories::Versioned;
+///
+/// #[derive(Clone, Factory)]
+/// #[factory(
+///     model = "Country",
+///     table = "crate::schema::countries",
+///     version = "v2",
+///     since_migration = "2019-05-07-092232_add_countries",
+/// )]
+/// struct CountryFactoryV2 {
+///     pub name: String,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let factory = Versioned::select(
+///     CountryFactory::default(),
+///     CountryFactoryV2 { name: "Norway".into() },
+///     &con,
+/// );
+///
+/// match factory.insert(&con) {
+///     Versioned::Old(country) => assert_eq!("Denmark", country.name),
+///     Versioned::New(country) => assert_eq!("Norway", country.name),
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub enum Versioned<Old, New> {
+    /// The version used before the migration is applied.
+    Old(Old),
+
+    /// The version used after the migration is applied.
+    New(New),
+}
+
+impl<Old, New> Versioned<Old, New>
+where
+    Old: Factory,
+    New: VersionedFactory<Connection = Old::Connection>,
+{
+    /// Pick `new` if its migration has been applied, otherwise `old`.
+    pub fn select(old: Old, new: New, con: &Old::Connection) -> Self {
+        if New::is_current(con) {
+            Versioned::New(new)
+        } else {
+            Versioned::Old(old)
+        }
+    }
+
+    /// Insert whichever factory was selected.
+    pub fn insert(self, con: &Old::Connection) -> Versioned<Old::Model, New::Model> {
+        match self {
+            Versioned::Old(factory) => Versioned::Old(factory.insert(con)),
+            Versioned::New(factory) => Versioned::New(factory.insert(con)),
+        }
+    }
+}
+
+#[allow(non_local_definitions)]
+mod schema_migrations {
+    table! {
+        __diesel_schema_migrations (version) {
+            version -> VarChar,
+        }
+    }
+}
+
+type MigrationQuery = diesel::dsl::Filter<
+    diesel::dsl::Select<
+        schema_migrations::__diesel_schema_migrations::table,
+        schema_migrations::__diesel_schema_migrations::version,
+    >,
+    diesel::dsl::Eq<schema_migrations::__diesel_schema_migrations::version, String>,
+>;
+
+/// Check if a migration has been applied by looking in the table Diesel uses to track migrations.
+///
+/// `migration` can either be the name of the migration's directory, such as
+/// `"2019-05-10-072830_cities"`, or the version Diesel stores, such as `"20190510072830"`.
+///
+/// Returns `false` if Diesel's migrations table doesn't exist. The query is run in a savepoint so
+/// that doesn't abort any surrounding test transaction.
+pub fn migration_applied<Con>(migration: &str, con: &Con) -> bool
+where
+    Con: Connection,
+    MigrationQuery: diesel::query_dsl::LoadQuery<Con, String>,
+{
+    use diesel::prelude::*;
+    use schema_migrations::__diesel_schema_migrations::dsl::*;
+
+    let migration_version = migration
+        .split('_')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>();
+
+    let applied = con.transaction(|| {
+        __diesel_schema_migrations
+            .select(version)
+            .filter(version.eq(migration_version))
+            .load::<String>(con)
+    });
+
+    match applied {
+        Ok(versions) => !versions.is_empty(),
+        Err(_) => false,
+    }
+}
+
+lazy_static! {
+    static ref SEQUENCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    static ref NAMED_SEQUENCES: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+/// Utility function for generating unique ids or strings in factories.
+/// Each time `sequence` gets called, the closure will receive a different number.
+///
+/// ```
+/// use diesel_factories::sequence;
+///
+/// assert_ne!(
+///     sequence(|i| format!("unique-string-{}", i)),
+///     sequence(|i| format!("unique-string-{}", i)),
+/// );
+/// ```
+///
+/// Inside a [`TestContext`] the context's own counter is used instead.
+///
+/// [`TestContext`]: struct.TestContext.html
+pub fn sequence<T, F>(f: F) -> T
+where
+    F: Fn(usize) -> T,
+{
+    if let Some(count) = context::next_sequence(None) {
+        return f(count);
+    }
+    SEQUENCE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let count = SEQUENCE_COUNTER.load(Ordering::Relaxed);
+    f(count)
+}
+
+/// Like [`sequence`] but uses a counter of its own for each name.
+///
+/// Each named sequence starts at 1 and is only advanced by calls using the same name, so values
+/// don't depend on what else has used a sequence. Combined with [`reset_sequence`] this gives
+/// deterministic values that can be used in snapshot assertions.
+///
+/// ```
+/// use diesel_factories::{reset_sequence, sequence_in};
+///
+/// let email = |i| format!("user-{}@example.com", i);
+///
+/// assert_eq!("user-1@example.com", sequence_in("docs.email", email));
+/// assert_eq!("user-2@example.com", sequence_in("docs.email", email));
+///
+/// reset_sequence("docs.email");
+/// assert_eq!("user-1@example.com", sequence_in("docs.email", email));
+/// ```
+///
+/// Named sequences are shared between threads, so tests running in parallel should use names of
+/// their own, or a [`TestContext`] each.
+///
+/// [`sequence`]: fn.sequence.html
+/// [`reset_sequence`]: fn.reset_sequence.html
+/// [`TestContext`]: struct.TestContext.html
+pub fn sequence_in<T, F>(name: &str, f: F) -> T
+where
+    F: Fn(usize) -> T,
+{
+    if let Some(count) = context::next_sequence(Some(name)) {
+        return f(count);
+    }
+    let count = {
+        let mut sequences = named_sequences();
+        let count = sequences.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    };
+    f(count)
+}
+
+/// Reset the named sequence so it starts over from 1.
+///
+/// See [`sequence_in`] for more info.
+///
+/// [`sequence_in`]: fn.sequence_in.html
+pub fn reset_sequence(name: &str) {
+    if context::reset_sequences(Some(name)) {
+        return;
+    }
+    named_sequences().remove(name);
+}
+
+/// Reset all named sequences along with the counter used by [`sequence`].
+///
+/// Since this affects every sequence, it's best called somewhere that doesn't run in parallel
+/// with other tests. Inside a [`TestContext`] only the context's sequences are reset.
+///
+/// [`sequence`]: fn.sequence.html
+/// [`TestContext`]: struct.TestContext.html
+pub fn reset_sequences() {
+    if context::reset_sequences(None) {
+        return;
+    }
+    named_sequences().clear();
+    SEQUENCE_COUNTER.store(0, Ordering::SeqCst);
+}
+
+fn named_sequences() -> MutexGuard<'static, HashMap<String, usize>> {
+    NAMED_SEQUENCES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Factory type name, field name and value.
+type CopiedValue = (&'static str, &'static str, Box<dyn Any>);
+
+thread_local! {
+    static COPIED_VALUES: RefCell<Vec<CopiedValue>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Remember a value of a `map` field that `from_model` copied from a model. The model's value was
+/// mapped when it was inserted, so it's inserted as is rather than mapped again.
+#[doc(hidden)]
+pub fn copy_model_value<F: ?Sized, T: Clone + 'static>(field: &'static str, value: &T) -> T {
+    let factory = std::any::type_name::<F>();
+    COPIED_VALUES.with(|copied| {
+        copied
+            .borrow_mut()
+            .push((factory, field, Box::new(value.clone())))
+    });
+    value.clone()
+}
+
+/// Whether the value of a `map` field was copied from a model with [`copy_model_value`].
+///
+/// [`copy_model_value`]: fn.copy_model_value.html
+#[doc(hidden)]
+pub fn is_copied_model_value<F: ?Sized, T: PartialEq + 'static>(
+    field: &'static str,
+    value: &T,
+) -> bool {
+    let factory = std::any::type_name::<F>();
+    COPIED_VALUES.with(|copied| {
+        copied
+            .borrow()
+            .iter()
+            .any(|(copied_factory, copied_field, copied)| {
+                *copied_factory == factory
+                    && *copied_field == field
+                    && copied.downcast_ref::<T>() == Some(value)
+            })
+    })
+}
+
+/// Connection address and model type name.
+type InsertKey = (usize, &'static str);
+
+pub(crate) type LatestInserts = HashMap<InsertKey, Box<dyn Any>>;
+
+thread_local! {
+    static LATEST_INSERTS: RefCell<LatestInserts> = RefCell::new(HashMap::new());
+}
+
+/// Take the thread's latest inserts, leaving none, for restoring them later with
+/// [`restore_latest_inserts`].
+///
+/// [`restore_latest_inserts`]: fn.restore_latest_inserts.html
+pub(crate) fn take_latest_inserts() -> LatestInserts {
+    LATEST_INSERTS.with(|inserts| std::mem::take(&mut *inserts.borrow_mut()))
+}
+
+/// Replace the thread's latest inserts with what [`take_latest_inserts`] returned.
+///
+/// [`take_latest_inserts`]: fn.take_latest_inserts.html
+pub(crate) fn restore_latest_inserts(saved: LatestInserts) {
+    LATEST_INSERTS.with(|inserts| *inserts.borrow_mut() = saved);
+}
+
+/// The id of the most recently inserted `Model` on the given connection.
+///
+/// Every factory insert is recorded, including inserts of associations, so tests can attach new
+/// records to whatever they just made without passing references around. The `<association>_latest`
+/// builder methods generated by `#[derive(Factory)]` use this.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// use diesel_factories::latest_inserted_id;
+///
+/// let country = CountryFactory::default().insert(&con);
+/// assert_eq!(Some(country.id), latest_inserted_id::<Country, i32, _>(&con));
+///
+/// let city = CityFactory::default().country_latest().insert(&con);
+/// assert_eq!(country.id, city.country_id);
+/// # }
+/// ```
+///
+/// Inserts are recorded per thread, so they're isolated between tests running in parallel. The
+/// connection is identified by its address, and an r2d2 pooled connection by the connection it
+/// dereferences to, which is what factories insert with.
+///
+/// A [`TestContext`] starts without any latest inserts, and forgets the ones made while it was
+/// alive when it's dropped. Otherwise they're kept for as long as the thread runs, so a test
+/// that doesn't use a context could see the inserts of an earlier test on the thread, if its
+/// connection happens to get the same address.
+///
+/// [`TestContext`]: struct.TestContext.html
+pub fn latest_inserted_id<Model, Id, Con>(con: &Con) -> Option<Id>
+where
+    Id: Clone + 'static,
+    Con: Any,
+{
+    let key = (
+        underlying_connection_address(con),
+        std::any::type_name::<Model>(),
+    );
+    LATEST_INSERTS.with(|inserts| {
+        inserts
+            .borrow()
+            .get(&key)
+            .and_then(|id| id.downcast_ref::<Id>())
+            .cloned()
+    })
+}
+
+#[doc(hidden)]
+pub fn record_insert<Model, Id, Con>(con: &Con, id: &Id)
+where
+    Id: Clone + 'static,
+    Con: ?Sized,
+{
+    let key = (connection_address(con), std::any::type_name::<Model>());
+    LATEST_INSERTS.with(|inserts| {
+        inserts.borrow_mut().insert(key, Box::new(id.clone()));
+    });
+}
+
+fn connection_address<Con: ?Sized>(con: &Con) -> usize {
+    let ptr: *const Con = con;
+    ptr as *const () as usize
+}
+
+/// The address of the connection inserts on `con` go through, for state kept per connection
+/// that's looked up or set with the connection a test holds, such as the latest inserts. r2d2
+/// pooled connections insert with the connection they dereference to, so they're identified by
+/// it.
+fn underlying_connection_address<Con: Any>(con: &Con) -> usize {
+    #[cfg(feature = "r2d2")]
+    {
+        if let Some(address) = pool::pooled_connection_address(con) {
+            return address;
+        }
+    }
+    connection_address(con)
+}
+
+thread_local! {
+    static DENY_INSERTS_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Run a closure while making any factory insert panic.
+///
+/// Useful for pure unit tests that shouldn't hit the database, even if they accidentally call a
+/// helper that uses a factory. Only affects the current thread.
+///
+/// ```should_panic
+/// # include!("../tests/setup/docs_setup.rs");
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// use diesel_factories::deny_factory_inserts;
+///
+/// deny_factory_inserts(|| {
+///     // panics with "CountryFactory attempted to insert inside `deny_factory_inserts`"
+///     CountryFactory::default().insert(&con);
+/// });
+/// # }
+/// ```
+pub fn deny_factory_inserts<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            DENY_INSERTS_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    DENY_INSERTS_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let _guard = Guard;
+    f()
+}
+
+thread_local! {
+    static FIXTURE_BUDGETS: RefCell<Vec<Vec<(&'static str, Duration)>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run a closure and panic if the factories inserted in it took longer than `budget` in total.
+///
+/// Gives a test a fixed budget for setting up its data, so slow fixtures are caught before they
+/// add up. The panic lists the time spent inserting into each table, slowest first:
+///
+/// ```text
+/// Factory inserts took 2.41s, over the budget of 2s:
+///     cities: 1.9s
+///     countries: 510ms
+/// ```
+///
+/// Only the time spent in inserts is counted, and time spent inserting an association counts
+/// towards the association's table. Only affects the current thread.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// use diesel_factories::with_fixture_budget;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let country = with_fixture_budget(Duration::from_secs(2), || {
+///     CountryFactory::default().insert(&con)
+/// });
+/// # }
+/// ```
+pub fn with_fixture_budget<T, F>(budget: Duration, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            FIXTURE_BUDGETS.with(|budgets| budgets.borrow_mut().pop());
+        }
+    }
+
+    FIXTURE_BUDGETS.with(|budgets| budgets.borrow_mut().push(Vec::new()));
+    let guard = Guard;
+    let value = f();
+
+    let mut timings = FIXTURE_BUDGETS
+        .with(|budgets| budgets.borrow_mut().last_mut().map(std::mem::take))
+        .unwrap_or_default();
+    drop(guard);
+
+    let total = timings.iter().map(|(_, time)| *time).sum::<Duration>();
+    if total > budget {
+        timings.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+        let breakdown = timings
+            .iter()
+            .map(|(table, time)| format!("    {}: {:?}", table, time))
+            .collect::<Vec<_>>();
+        panic!(
+            "Factory inserts took {:?}, over the budget of {:?}:\n{}",
+            total,
+            budget,
+            breakdown.join("\n")
+        );
+    }
+    value
+}
+
+/// Add time spent inserting into `table` to every running `with_fixture_budget`.
+fn record_insert_time(table: &'static str, time: Duration) {
+    FIXTURE_BUDGETS.with(|budgets| {
+        for timings in budgets.borrow_mut().iter_mut() {
+            match timings.iter_mut().find(|(other, _)| *other == table) {
+                Some((_, total)) => *total += time,
+                None => timings.push((table, time)),
+            }
+        }
+    });
+}
+
+/// Run a closure inside a savepoint, rolling it back if the closure returns an error or panics.
+///
+/// Postgres aborts a transaction when a statement fails, so a failed insert inside
+/// `begin_test_transaction` makes every later query in the test fail as well. Running the insert
+/// inside a savepoint keeps the outer transaction usable. The savepoint is released if the closure
+/// returns `Ok`:
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// use diesel::result::Error;
+/// use diesel_factories::with_savepoint;
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let result = with_savepoint(&con, || {
+///     CountryFactory::default().name("Narnia").insert(&con);
+///     Err::<(), _>(Error::RollbackTransaction)
+/// });
+/// assert!(result.is_err());
+///
+/// // The outer transaction still works and Narnia was rolled back
+/// let count = schema::countries::table.count().get_result::<i64>(&con).unwrap();
+/// assert_eq!(0, count);
+/// # }
+/// ```
+///
+/// Panics, such as from [`Factory::insert`], roll back the savepoint before the panic continues,
+/// so a test catching them with `std::panic::catch_unwind` can keep using the connection.
+///
+/// [`Factory::insert`]: trait.Factory.html#tymethod.insert
+pub fn with_savepoint<Con, T, E, F>(con: &Con, f: F) -> Result<T, E>
+where
+    Con: Connection,
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    let transactions = con.transaction_manager();
+    transactions.begin_transaction(con)?;
+
+    // The panic is resumed below, after rolling back, so nothing observes broken state
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => {
+            transactions.commit_transaction(con)?;
+            Ok(value)
+        }
+        Ok(Err(err)) => {
+            transactions.rollback_transaction(con)?;
+            Err(err)
+        }
+        Err(panic) => {
+            let _ = transactions.rollback_transaction(con);
+            std::panic::resume_unwind(panic)
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn assert_inserts_allowed(factory: &str) {
+    let denied = DENY_INSERTS_DEPTH.with(|depth| depth.get() > 0);
+    if denied {
+        panic!(
+            "{} attempted to insert inside `deny_factory_inserts`",
+            factory
+        );
+    }
+}
+
+lazy_static! {
+    static ref INSERT_CORRELATION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+}
+
+thread_local! {
+    static CURRENT_INSERT: RefCell<Option<InsertTrace>> = const { RefCell::new(None) };
+    static FAILED_INSERT: RefCell<Option<InsertTrace>> = const { RefCell::new(None) };
+    // Time spent inserting associations of each running insert, to leave out of its own time
+    static ASSOCIATION_INSERT_TIMES: RefCell<Vec<Duration>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The chain of factories being inserted when the trace was taken.
+///
+/// Each top-level insert gets a new correlation id, which is shared by the inserts of all its
+/// associations. Panics from failed inserts include the trace of the factory that failed, so it's
+/// clear which parent triggered the insert:
+///
+/// ```text
+/// UserFactory -> CityFactory -> CountryFactory (insert #12) failed: ...
+/// ```
+///
+/// Use [`current_insert`] to get the trace from within an insert, such as in an `after_insert`
+/// hook.
+///
+/// [`current_insert`]: fn.current_insert.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertTrace {
+    correlation_id: usize,
+    factories: Vec<&'static str>,
+}
+
+impl InsertTrace {
+    /// The id shared by a top-level insert and the inserts of its associations.
+    pub fn correlation_id(&self) -> usize {
+        self.correlation_id
+    }
+
+    /// Names of the factories being inserted, starting with the top-level one.
+    pub fn factories(&self) -> &[&'static str] {
+        &self.factories
+    }
+}
+
+impl std::fmt::Display for InsertTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (insert #{})",
+            self.factories.join(" -> "),
+            self.correlation_id
+        )
+    }
+}
+
+/// The trace of the insert currently running on this thread, if any.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # fn main() {
+/// use diesel_factories::current_insert;
+///
+/// assert_eq!(None, current_insert());
+/// # }
+/// ```
+///
+/// See [`InsertTrace`] for more info.
+///
+/// [`InsertTrace`]: struct.InsertTrace.html
+pub fn current_insert() -> Option<InsertTrace> {
+    CURRENT_INSERT.with(|current| current.borrow().clone())
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct InsertScope {
+    table: &'static str,
+    started: Instant,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+impl InsertScope {
+    #[doc(hidden)]
+    pub fn enter(factory: &'static str, table: &'static str) -> Self {
+        CURRENT_INSERT.with(|current| {
+            let mut current = current.borrow_mut();
+            match &mut *current {
+                Some(trace) => trace.factories.push(factory),
+                None => {
+                    *current = Some(InsertTrace {
+                        correlation_id: INSERT_CORRELATION_COUNTER.fetch_add(1, Ordering::SeqCst)
+                            + 1,
+                        factories: vec![factory],
+                    })
+                }
+            }
+        });
+        ASSOCIATION_INSERT_TIMES.with(|times| times.borrow_mut().push(Duration::default()));
+        InsertScope {
+            table,
+            started: Instant::now(),
+            #[cfg(feature = "tracing")]
+            _span: tracing::debug_span!("factory_insert", factory, table).entered(),
+        }
+    }
+
+    /// Emit an event for a row the factory inserted. Only called with the `tracing` feature.
+    #[cfg(feature = "tracing")]
+    #[doc(hidden)]
+    pub fn inserted<Id: std::fmt::Debug>(&self, id: &Id) {
+        tracing::debug!(
+            id = ?id,
+            elapsed = ?self.started.elapsed(),
+            "inserted row into {}",
+            self.table,
+        );
+    }
+
+    /// Remember the trace of the failing insert, unless one of its associations already failed.
+    #[doc(hidden)]
+    pub fn fail<E>(&self, err: E) -> E {
+        let trace = current_insert();
+        FAILED_INSERT.with(|failed| {
+            let mut failed = failed.borrow_mut();
+            let already_recorded = match (&*failed, &trace) {
+                (Some(failed), Some(trace)) => failed.correlation_id == trace.correlation_id,
+                _ => false,
+            };
+            if !already_recorded {
+                *failed = trace;
+            }
+        });
+        err
+    }
+}
+
+impl Drop for InsertScope {
+    fn drop(&mut self) {
+        CURRENT_INSERT.with(|current| {
+            let mut current = current.borrow_mut();
+            let finished = match &mut *current {
+                Some(trace) => {
+                    trace.factories.pop();
+                    trace.factories.is_empty()
+                }
+                None => false,
+            };
+            if finished {
+                *current = None;
+            }
+        });
+
+        let elapsed = self.started.elapsed();
+        let associations = ASSOCIATION_INSERT_TIMES.with(|times| {
+            let mut times = times.borrow_mut();
+            let associations = times.pop().unwrap_or_default();
+            if let Some(parent) = times.last_mut() {
+                *parent += elapsed;
+            }
+            associations
+        });
+        record_insert_time(
+            self.table,
+            elapsed.checked_sub(associations).unwrap_or_default(),
+        );
+    }
+}
+
+fn take_failed_insert() -> Option<InsertTrace> {
+    FAILED_INSERT.with(|failed| failed.borrow_mut().take())
+}
+
+/// Insert a graph of records, returning a struct with a field for each record.
+///
+/// Takes a connection followed by `name: factory` pairs that are inserted in order, so later
+/// factories can refer to records inserted before them by name. A pair can also insert a list of
+/// factories with `name: [factory, factory]`, or one factory several times with
+/// `name: [factory; count]`, giving a `Vec` of records. The factory expression is evaluated again
+/// for each of the `count` records, so the factory doesn't have to be `Clone` and each record gets
+/// its own [`sequence`] values.
+///
+/// Records that depend on another record can be nested under it with
+/// `name: factory => { ... }`. The nested pairs are inserted right after the record they're nested
+/// under, so a tree is inserted parents first. Nested records are fields of the returned struct
+/// like any other, so names have to be unique across the whole graph.
+///
+/// ```
+/// # include!("../tests/setup/docs_setup_with_city_factory.rs");
+/// use diesel_factories::create_graph;
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let graph = create_graph!(&con, {
+///     denmark: CountryFactory::default() => {
+///         capital: CityFactory::default().name("Copenhagen").country(&denmark),
+///         cities: [CityFactory::default().country(&denmark); 3],
+///     },
+///     sweden: CountryFactory::default().name("Sweden") => {
+///         swedish_cities: [
+///             CityFactory::default().name("Stockholm").country(&sweden),
+///             CityFactory::default().name("Malmö").country(&sweden),
+///         ],
+///     },
+/// });
+///
+/// assert_eq!(graph.denmark.id, graph.capital.country_id);
+/// assert_eq!(3, graph.cities.len());
+/// assert_eq!("Malmö", graph.swedish_cities[1].name);
+/// # }
+/// ```
+///
+/// Inserts use [`Factory::insert`] and panic if they fail.
+///
+/// [`Factory::insert`]: trait.Factory.html#tymethod.insert
+/// [`sequence`]: fn.sequence.html
+#[macro_export]
+macro_rules! create_graph {
+    ( $con:expr, { $($body:tt)* } ) => {{
+        let con = $con;
+        $crate::create_graph!(@insert con, [] $($body)*)
+    }};
+
+    ( @insert $con:ident, [$($done:ident)*] ) => {{
+        // The records' types can't be named here, so they are type parameters named like the
+        // fields
+        #[allow(non_camel_case_types)]
+        struct Graph<$($done),*> {
+            $( $done: $done ),*
+        }
+
+        Graph { $($done),* }
+    }};
+
+    // Left behind after splicing nested pairs in front of the rest
+    ( @insert $con:ident, [$($done:ident)*] , $($rest:tt)* ) => {
+        $crate::create_graph!(@insert $con, [$($done)*] $($rest)*)
+    };
+
+    ( @insert $con:ident, [$($done:ident)*]
+        $name:ident : $factory:expr => { $($nested:tt)* } $(, $($rest:tt)*)?
+    ) => {{
+        let $name = $crate::Factory::insert($factory, $con);
+        $crate::create_graph!(@insert $con, [$($done)* $name] $($nested)* , $($($rest)*)?)
+    }};
+
+    ( @insert $con:ident, [$($done:ident)*]
+        $name:ident : [ $factory:expr ; $count:expr ] $(, $($rest:tt)*)?
+    ) => {{
+        let $name = (0..$count)
+            .map(|_| $crate::Factory::insert($factory, $con))
+            .collect::<Vec<_>>();
+        $crate::create_graph!(@insert $con, [$($done)* $name] $($($rest)*)?)
+    }};
+
+    ( @insert $con:ident, [$($done:ident)*]
+        $name:ident : [ $($factory:expr),* $(,)? ] $(, $($rest:tt)*)?
+    ) => {{
+        let $name = vec![$( $crate::Factory::insert($factory, $con) ),*];
+        $crate::create_graph!(@insert $con, [$($done)* $name] $($($rest)*)?)
+    }};
+
+    ( @insert $con:ident, [$($done:ident)*]
+        $name:ident : $factory:expr $(, $($rest:tt)*)?
+    ) => {{
+        let $name = $crate::Factory::insert($factory, $con);
+        $crate::create_graph!(@insert $con, [$($done)* $name] $($($rest)*)?)
+    }};
 }
 
 #[cfg(test)]
@@ -0,0 +1,164 @@
+//! Plans of the inserts a factory would run, for reviewing association graphs without a
+//! database.
+//!
+//! [`Plan`] is implemented by `#[derive(Factory)]`. Calling [`plan`] walks the factory's
+//! associations the same way inserting does and lists each row that would be inserted, in order,
+//! along with where its foreign keys come from:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
+//! use diesel_factories::plan::{Plan, PlannedKey};
+//!
+//! # fn main() {
+//! let plan = CityFactory::default().plan();
+//!
+//! assert_eq!(vec!["countries", "cities"], plan.tables());
+//! assert_eq!(
+//!     vec![("country_id", PlannedKey::Insert(0))],
+//!     plan.inserts()[1].foreign_keys,
+//! );
+//!
+//! let country = Country { id: 1, name: "Denmark".into() };
+//! let plan = CityFactory::default().country(&country).plan();
+//! assert_eq!(vec!["cities"], plan.tables());
+//!
+//! print!("{}", plan);
+//! # }
+//! ```
+//!
+//! [`Plan`]: trait.Plan.html
+//! [`plan`]: trait.Plan.html#method.plan
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A factory whose inserts can be planned without a database.
+///
+/// You shouldn't have to implement this trait yourself. It is implemented by
+/// `#[derive(Factory)]`.
+pub trait Plan {
+    /// The inserts inserting this factory would run, including its associations.
+    fn plan(&self) -> InsertPlan {
+        let mut plan = InsertPlan::default();
+        self.plan_into(&mut plan);
+        plan
+    }
+
+    #[doc(hidden)]
+    fn plan_into(&self, plan: &mut InsertPlan) -> usize;
+}
+
+/// Ordered list of the inserts a factory would run. See the [module docs] for an example.
+///
+/// Prints one line per insert:
+///
+/// ```text
+/// [0] CountryFactory into countries
+/// [1] CityFactory into cities (country_id = [0])
+/// ```
+///
+/// [module docs]: index.html
+#[derive(Debug, Clone, Default)]
+pub struct InsertPlan {
+    inserts: Vec<PlannedInsert>,
+    shared: HashMap<usize, usize>,
+}
+
+impl InsertPlan {
+    /// The planned inserts, in the order they would run.
+    pub fn inserts(&self) -> &[PlannedInsert] {
+        &self.inserts
+    }
+
+    /// The table of each planned insert, in the order they would run.
+    pub fn tables(&self) -> Vec<&'static str> {
+        self.inserts.iter().map(|insert| insert.table).collect()
+    }
+
+    #[doc(hidden)]
+    pub fn push(
+        &mut self,
+        factory: &'static str,
+        table: &'static str,
+        foreign_keys: Vec<(&'static str, PlannedKey)>,
+    ) -> usize {
+        self.inserts.push(PlannedInsert {
+            factory,
+            table,
+            foreign_keys,
+        });
+        self.inserts.len() - 1
+    }
+
+    /// Plan a shared factory only the first time it's seen, since it's only inserted once.
+    pub(crate) fn push_shared<F>(&mut self, key: usize, plan: F) -> usize
+    where
+        F: FnOnce(&mut Self) -> usize,
+    {
+        if let Some(index) = self.shared.get(&key) {
+            return *index;
+        }
+        let index = plan(self);
+        self.shared.insert(key, index);
+        index
+    }
+}
+
+impl fmt::Display for InsertPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, insert) in self.inserts.iter().enumerate() {
+            write!(f, "[{}] {} into {}", index, insert.factory, insert.table)?;
+            if !insert.foreign_keys.is_empty() {
+                let keys = insert
+                    .foreign_keys
+                    .iter()
+                    .map(|(column, key)| format!("{} = {}", column, key))
+                    .collect::<Vec<_>>();
+                write!(f, " ({})", keys.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A row that would be inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedInsert {
+    /// Name of the factory inserting the row.
+    pub factory: &'static str,
+    /// Name of the table the row is inserted into.
+    pub table: &'static str,
+    /// Foreign key columns and where their values come from.
+    pub foreign_keys: Vec<(&'static str, PlannedKey)>,
+}
+
+/// Where a foreign key's value comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedKey {
+    /// An existing row's id, formatted with `Debug`.
+    Id(String),
+    /// The row inserted by the planned insert at this index.
+    Insert(usize),
+    /// The latest row inserted on the connection, from an `Association::Latest`.
+    Latest,
+    /// A row found when inserting, from an `Association::Find`.
+    Find,
+    /// A row that's only built and fails to insert, from an `Association::Built`.
+    Built,
+    /// An optional association that isn't set.
+    Null,
+}
+
+impl fmt::Display for PlannedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedKey::Id(id) => write!(f, "{}", id),
+            PlannedKey::Insert(index) => write!(f, "[{}]", index),
+            PlannedKey::Latest => write!(f, "latest"),
+            PlannedKey::Find => write!(f, "found"),
+            PlannedKey::Built => write!(f, "built"),
+            PlannedKey::Null => write!(f, "null"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Inserting with connections checked out from an r2d2 pool.
+//!
+//! Enabled with the `r2d2` feature along with the feature for each backend, such as `postgres`.
+//! Every factory that can be inserted with `&PgConnection` can then also be inserted with
+//! `&PooledConnection<ConnectionManager<PgConnection>>`, using any of the `factory_v2` traits, so
+//! pooled connections shouldn't be listed with the `connection` attribute.
+
+use crate::connection_address;
+use crate::factory_v2::{Insert, InsertBatch, InsertReturningId};
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::result::QueryResult;
+use diesel::Connection;
+use std::any::Any;
+use std::panic;
+use std::thread;
+
+// Implemented for each connection type rather than generically, since a generic implementation
+// would make the compiler recurse through pools of pools when inferring connection types.
+macro_rules! impl_insert_for_pooled_connection {
+    ( $( $feature:literal => $connection:path ),* ) => {
+        $(
+            #[cfg(feature = $feature)]
+            impl<'a, F> Insert<&'a PooledConnection<ConnectionManager<$connection>>> for F
+            where
+                F: Insert<&'a $connection>,
+            {
+                fn insert(
+                    self,
+                    con: &'a PooledConnection<ConnectionManager<$connection>>,
+                ) -> QueryResult<Self::Model> {
+                    <F as Insert<&'a $connection>>::insert(self, &**con)
+                }
+            }
+
+            #[cfg(feature = $feature)]
+            impl<'a, F> InsertBatch<&'a PooledConnection<ConnectionManager<$connection>>> for F
+            where
+                F: InsertBatch<&'a $connection>,
+            {
+                fn insert_batch(
+                    factories: Vec<Self>,
+                    con: &'a PooledConnection<ConnectionManager<$connection>>,
+                ) -> QueryResult<Vec<Self::Model>> {
+                    <F as InsertBatch<&'a $connection>>::insert_batch(factories, &**con)
+                }
+            }
+
+            #[cfg(feature = $feature)]
+            impl<'a, F> InsertReturningId<&'a PooledConnection<ConnectionManager<$connection>>> for F
+            where
+                F: InsertReturningId<&'a $connection>,
+            {
+                fn insert_returning_id(
+                    self,
+                    con: &'a PooledConnection<ConnectionManager<$connection>>,
+                ) -> QueryResult<Self::Id> {
+                    <F as InsertReturningId<&'a $connection>>::insert_returning_id(self, &**con)
+                }
+            }
+        )*
+    };
+}
+
+impl_insert_for_pooled_connection!(
+    "postgres" => diesel::pg::PgConnection,
+    "mysql" => diesel::mysql::MysqlConnection,
+    "sqlite" => diesel::sqlite::SqliteConnection
+);
+
+/// The address of the connection `con` dereferences to, if it's a pooled connection of an enabled
+/// backend. That's the connection the implementations above insert with.
+#[cfg_attr(
+    not(any(feature = "postgres", feature = "mysql", feature = "sqlite")),
+    allow(unused_variables)
+)]
+pub(crate) fn pooled_connection_address(con: &dyn Any) -> Option<usize> {
+    #[cfg(feature = "postgres")]
+    {
+        type Pooled = PooledConnection<ConnectionManager<diesel::pg::PgConnection>>;
+        if let Some(con) = con.downcast_ref::<Pooled>() {
+            return Some(connection_address::<diesel::pg::PgConnection>(con));
+        }
+    }
+    #[cfg(feature = "mysql")]
+    {
+        type Pooled = PooledConnection<ConnectionManager<diesel::mysql::MysqlConnection>>;
+        if let Some(con) = con.downcast_ref::<Pooled>() {
+            return Some(connection_address::<diesel::mysql::MysqlConnection>(con));
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        type Pooled = PooledConnection<ConnectionManager<diesel::sqlite::SqliteConnection>>;
+        if let Some(con) = con.downcast_ref::<Pooled>() {
+            return Some(connection_address::<diesel::sqlite::SqliteConnection>(con));
+        }
+    }
+    None
+}
+
+/// Insert `n` factories on up to `concurrency` threads, each inserting on a connection of its own
+/// checked out from the pool. `factory` builds the factory for each index, from `0` to `n - 1`.
+///
+/// The indices are split into `concurrency` contiguous ranges, one per thread, and the models are
+/// returned in the order of their indices however the threads interleave. Inserting concurrently
+/// on one connection would either be serialized or unsound, since connections aren't `Sync`.
+///
+/// ```no_run
+/// # include!("../tests/setup/docs_setup.rs");
+/// use diesel::r2d2::{ConnectionManager, Pool};
+/// use diesel_factories::insert_list_concurrent;
+///
+/// # fn main() {
+/// let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/diesel_factories_test");
+/// let pool = Pool::builder().max_size(4).build(manager).unwrap();
+///
+/// let countries = insert_list_concurrent(&pool, 100, 4, |n| {
+///     CountryFactory::default().name(format!("Country {}", n))
+/// })
+/// .unwrap();
+/// assert_eq!("Country 99", countries[99].name);
+/// # }
+/// ```
+///
+/// Each thread commits its rows on its own connection, so tests running inside a test
+/// transaction should begin one on every connection of the pool, such as with a
+/// `CustomizeConnection`. Requires the `r2d2` feature.
+///
+/// # Panics
+/// Panics if `concurrency` is `0`, if a connection can't be checked out, or if a thread panics.
+pub fn insert_list_concurrent<Con, F, B>(
+    pool: &Pool<ConnectionManager<Con>>,
+    n: usize,
+    concurrency: usize,
+    factory: B,
+) -> QueryResult<Vec<F::Model>>
+where
+    Con: Connection + Send + 'static,
+    F: for<'a> Insert<&'a Con>,
+    F::Model: Send,
+    B: Fn(usize) -> F + Sync,
+{
+    assert!(concurrency > 0, "`concurrency` must be at least 1");
+
+    let indices = (0..n).collect::<Vec<_>>();
+    let chunk_size = n.div_ceil(concurrency).max(1);
+    let factory = &factory;
+    thread::scope(|scope| {
+        let workers = indices
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let con = pool.get().unwrap_or_else(|err| {
+                        panic!("Failed to check out a connection from the pool: {}", err)
+                    });
+                    chunk
+                        .iter()
+                        .map(|index| factory(*index).insert(&*con))
+                        .collect::<QueryResult<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut models = Vec::with_capacity(n);
+        for worker in workers {
+            let chunk = worker
+                .join()
+                .unwrap_or_else(|payload| panic::resume_unwind(payload))?;
+            models.extend(chunk);
+        }
+        Ok(models)
+    })
+}
@@ -0,0 +1,274 @@
+//! Reusable profiles of default values, matched to fields by name.
+//!
+//! A profile bundles providers for common columns, such as emails, SKUs or prices. Factories
+//! with `#[factory(profile = "EcommerceProfile")]` get a derived `Default` where every field
+//! without `default`, `default_with` or `fake` takes its value from the first provider whose
+//! pattern matches the field's name:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup.rs");
+//! #
+//! use diesel_factories::profile::{Profile, Providers};
+//! use diesel_factories::sequence;
+//!
+//! struct EcommerceProfile;
+//!
+//! impl Profile for EcommerceProfile {
+//!     fn providers(providers: &mut Providers) {
+//!         providers
+//!             .add("*email", || sequence(|n| format!("customer-{}@example.com", n)))
+//!             .add("sku", || sequence(|n| format!("SKU-{:05}", n)))
+//!             .add("*price*", || 1999)
+//!             .add("name", || "Denmark");
+//!     }
+//! }
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = "Country",
+//!     table = "crate::schema::countries",
+//!     profile = "EcommerceProfile",
+//! )]
+//! struct ShopCountryFactory {
+//!     pub name: String,
+//! }
+//!
+//! # fn main() {
+//! assert_eq!("Denmark", ShopCountryFactory::default().name);
+//! # }
+//! ```
+//!
+//! Patterns match the whole field name, with `*` matching any number of characters. Fields no
+//! provider matches panic when `default` is called, naming the field, so it can be given an
+//! attribute of its own or the profile a new provider.
+
+use std::fmt;
+use std::time::SystemTime;
+
+/// A set of providers that factories can use for their defaults.
+///
+/// See the [module docs] for an example.
+///
+/// [module docs]: index.html
+pub trait Profile {
+    /// Add the profile's providers. The first one matching a field is used.
+    fn providers(providers: &mut Providers);
+}
+
+/// Providers of a [`Profile`], each with the pattern of the field names it's used for.
+///
+/// [`Profile`]: trait.Profile.html
+#[derive(Default)]
+pub struct Providers {
+    providers: Vec<(String, Box<dyn Fn() -> Value>)>,
+}
+
+impl Providers {
+    /// Use `provider` for fields matching `pattern`, unless an earlier provider matches them.
+    pub fn add<V, F>(&mut self, pattern: &str, provider: F) -> &mut Self
+    where
+        V: Into<Value>,
+        F: Fn() -> V + 'static,
+    {
+        self.providers
+            .push((pattern.to_string(), Box::new(move || provider().into())));
+        self
+    }
+
+    /// A value from the first provider matching `field`.
+    pub fn value(&self, field: &str) -> Option<Value> {
+        self.providers
+            .iter()
+            .find(|(pattern, _)| matches(pattern, field))
+            .map(|(_, provider)| provider())
+    }
+}
+
+impl fmt::Debug for Providers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.providers.iter().map(|(pattern, _)| pattern))
+            .finish()
+    }
+}
+
+/// A value returned by a provider.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A string.
+    Text(String),
+    /// An integer.
+    Integer(i64),
+    /// A floating point number.
+    Float(f64),
+    /// A boolean.
+    Bool(bool),
+    /// A point in time.
+    Time(SystemTime),
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Integer(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<SystemTime> for Value {
+    fn from(value: SystemTime) -> Self {
+        Value::Time(value)
+    }
+}
+
+/// Field types a [`Value`] can be converted to.
+///
+/// [`Value`]: enum.Value.html
+pub trait FromValue: Sized {
+    /// Convert the value, or `None` if it's of another kind or out of range.
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_value_for_integer {
+    ( $( $ty:ty ),* ) => {
+        $(
+            impl FromValue for $ty {
+                fn from_value(value: Value) -> Option<Self> {
+                    match value {
+                        Value::Integer(value) => std::convert::TryFrom::try_from(value).ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value_for_integer!(i16, i32, i64);
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Option<Self> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Float(value) => Some(value),
+            Value::Integer(value) => Some(value as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for SystemTime {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Time(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Option<Self> {
+        T::from_value(value).map(Some)
+    }
+}
+
+/// The providers of a profile. Used by `#[derive(Factory)]`, which builds them once for each
+/// factory it creates.
+#[doc(hidden)]
+pub fn providers<P: Profile>() -> Providers {
+    let mut providers = Providers::default();
+    P::providers(&mut providers);
+    providers
+}
+
+/// The default value of a factory field from a profile's providers. Used by `#[derive(Factory)]`.
+#[doc(hidden)]
+pub fn value<P: Profile, T: FromValue>(providers: &Providers, factory: &str, field: &str) -> T {
+    let value = providers.value(field).unwrap_or_else(|| {
+        panic!(
+            "No provider in {} matches {}.{}",
+            std::any::type_name::<P>(),
+            factory,
+            field
+        )
+    });
+    let description = format!("{:?}", value);
+    T::from_value(value).unwrap_or_else(|| {
+        panic!(
+            "{} provided {} for {}.{}, which can't be converted to {}",
+            std::any::type_name::<P>(),
+            description,
+            factory,
+            field,
+            std::any::type_name::<T>()
+        )
+    })
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any number of characters.
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let name = &name[prefix.len()..];
+            (0..=name.len())
+                .filter(|index| name.is_char_boundary(*index))
+                .any(|index| matches(rest, &name[index..]))
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Seeded random numbers for generating test data.
+//!
+//! Each thread has its own generator. It's seeded from the `DIESEL_FACTORIES_SEED` environment
+//! variable if that is set, or from the current time otherwise. Use [`seed`] to print the seed
+//! when a test fails, and set the environment variable, or call [`set_seed`], to reproduce it.
+//!
+//! ```
+//! use diesel_factories::random;
+//!
+//! random::set_seed(42);
+//! let first = random::choose(&["a", "b", "c"]);
+//!
+//! random::set_seed(42);
+//! assert_eq!(first, random::choose(&["a", "b", "c"]));
+//! ```
+//!
+//! [`seed`]: fn.seed.html
+//! [`set_seed`]: fn.set_seed.html
+
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable the generators are seeded from.
+pub const SEED_ENV_VAR: &str = "DIESEL_FACTORIES_SEED";
+
+thread_local! {
+    static SEED: Cell<u64> = Cell::new(initial_seed());
+    static STATE: Cell<u64> = Cell::new(SEED.with(Cell::get));
+}
+
+fn initial_seed() -> u64 {
+    if let Some(seed) = env_seed() {
+        return seed;
+    }
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// The seed from the environment variable, if it's set.
+pub(crate) fn env_seed() -> Option<u64> {
+    std::env::var(SEED_ENV_VAR).ok().map(|seed| {
+        seed.parse()
+            .unwrap_or_else(|_| panic!("{} must be a number, got {:?}", SEED_ENV_VAR, seed))
+    })
+}
+
+/// The seed and current state of the thread's generator, for restoring it later.
+pub(crate) fn save() -> (u64, u64) {
+    (SEED.with(Cell::get), STATE.with(Cell::get))
+}
+
+/// Restore the generator to what [`save`] returned.
+///
+/// [`save`]: fn.save.html
+pub(crate) fn restore((seed, state): (u64, u64)) {
+    SEED.with(|cell| cell.set(seed));
+    STATE.with(|cell| cell.set(state));
+}
+
+/// The seed the current thread's generator started from.
+pub fn seed() -> u64 {
+    SEED.with(Cell::get)
+}
+
+/// Restart the current thread's generator from the given seed.
+pub fn set_seed(seed: u64) {
+    SEED.with(|cell| cell.set(seed));
+    STATE.with(|cell| cell.set(seed));
+}
+
+/// The next random number from the current thread's generator.
+///
+/// Uses SplitMix64, which is fast and good enough for test data but not for anything security
+/// related.
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state.set(next);
+
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    })
+}
+
+/// A random number in `0..upper`.
+///
+/// # Panics
+/// Panics if `upper` is zero.
+pub fn below(upper: u64) -> u64 {
+    assert!(upper > 0, "Can't pick a number below zero");
+    next_u64() % upper
+}
+
+/// A random item from the slice.
+///
+/// # Panics
+/// Panics if the slice is empty.
+pub fn choose<T>(items: &[T]) -> &T {
+    assert!(!items.is_empty(), "Can't choose from an empty slice");
+    &items[below(items.len() as u64) as usize]
+}
+
+/// A random item from the slice, where each item is picked in proportion to its weight.
+///
+/// # Panics
+/// Panics if the weights add up to zero.
+pub fn choose_weighted<T>(items: &[(T, u32)]) -> &T {
+    let total = items.iter().map(|(_, weight)| u64::from(*weight)).sum();
+    assert!(total > 0, "Can't choose when the weights add up to zero");
+
+    let mut target = below(total);
+    for (item, weight) in items {
+        let weight = u64::from(*weight);
+        if target < weight {
+            return item;
+        }
+        target -= weight;
+    }
+    unreachable!()
+}
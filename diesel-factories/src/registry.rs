@@ -0,0 +1,63 @@
+//! A registry of every derived factory in the binary. Requires the `inventory` feature.
+//!
+//! With the feature enabled, `#[derive(Factory)]` registers each factory, so tooling such as
+//! database cleaners, seeders or graph visualizers can find all factories at runtime, including
+//! ones from other crates, without keeping a list by hand:
+//!
+//! ```
+//! # include!("../tests/setup/docs_setup_with_city_factory.rs");
+//! use diesel_factories::registry::RegisteredFactory;
+//!
+//! # fn main() {
+//! let city = RegisteredFactory::find("CityFactory").unwrap();
+//! assert_eq!("cities", city.table);
+//! assert_eq!(&[("country_id", "CountryFactory")], city.associations);
+//!
+//! let tables = RegisteredFactory::all().map(|factory| factory.table).collect::<Vec<_>>();
+//! assert!(tables.contains(&"countries"));
+//! # }
+//! ```
+//!
+//! Abstract factories aren't registered since they can't be inserted.
+
+#[doc(hidden)]
+pub use inventory;
+
+/// A factory registered by `#[derive(Factory)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisteredFactory {
+    /// Name of the factory type.
+    pub name: &'static str,
+    /// Path of the module the factory is defined in.
+    pub module_path: &'static str,
+    /// Name of the model type the factory inserts.
+    pub model: &'static str,
+    /// Name of the table the factory inserts into.
+    pub table: &'static str,
+    /// Foreign key columns of the factory's associations, with the name of the associated
+    /// factory type.
+    pub associations: &'static [(&'static str, &'static str)],
+    /// Id and type columns of the factory's polymorphic associations, with the names of the model
+    /// types each can point to.
+    pub polymorphic_associations:
+        &'static [(&'static str, &'static str, &'static [&'static str])],
+}
+
+inventory::collect!(RegisteredFactory);
+
+impl RegisteredFactory {
+    /// Every registered factory, in no particular order.
+    pub fn all() -> impl Iterator<Item = &'static RegisteredFactory> {
+        inventory::iter::<RegisteredFactory>.into_iter()
+    }
+
+    /// The registered factory with the given type name.
+    ///
+    /// If factories in different modules share the name, any of them may be returned. Use
+    /// [`all`] and check `module_path` to tell them apart.
+    ///
+    /// [`all`]: #method.all
+    pub fn find(name: &str) -> Option<&'static RegisteredFactory> {
+        Self::all().find(|factory| factory.name == name)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{connection_address, dry_run, factory_v2, underlying_connection_address};
+use std::any::Any;
+use std::cell::RefCell;
+
+thread_local! {
+    static SCOPES: RefCell<Vec<(usize, Box<dyn Any>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run a closure with a scope, such as a tenant id, that factories fill their scoped column from
+/// when inserting on the connection.
+///
+/// Factories opt in with the `scoped_column` attribute, naming the column, and `scope_type` if the
+/// scope isn't an `i32`. Associations inserted along with a factory are filled from the scope as
+/// well, so rows of every table end up in the same tenant:
+///
+/// ```
+/// # include!("../tests/setup/docs_setup.rs");
+/// # mod scoped_schema {
+/// #     table! {
+/// #         projects (id) {
+/// #             id -> Integer,
+/// #             name -> Text,
+/// #             tenant_id -> Integer,
+/// #         }
+/// #     }
+/// # }
+/// use diesel_factories::with_scope;
+///
+/// #[derive(Clone, Queryable)]
+/// struct Project {
+///     pub id: i32,
+///     pub name: String,
+///     pub tenant_id: i32,
+/// }
+///
+/// #[derive(Clone, Factory)]
+/// #[factory(
+///     model = "Project",
+///     table = "crate::scoped_schema::projects",
+///     scoped_column = "tenant_id",
+/// )]
+/// struct ProjectFactory {
+///     #[factory(default = "\"Roadmap\".into()")]
+///     pub name: String,
+/// }
+///
+/// # fn main() {
+/// # let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+/// # con.begin_test_transaction().unwrap();
+/// let project = with_scope(&con, 7, || ProjectFactory::default().insert(&con));
+/// assert_eq!(7, project.tenant_id);
+/// # }
+/// ```
+///
+/// Inserting a scoped factory without a scope panics. A field named like the scoped column is
+/// inserted instead of the scope, for the odd row that belongs elsewhere. Scopes are per thread
+/// and connection, the connection being identified by its address, and the innermost one of the
+/// factory's scope type is used when they're nested. A scope set on an r2d2 pooled connection applies to the connection it
+/// dereferences to, which is what factories insert with.
+pub fn with_scope<Con, S, T, F>(con: &Con, scope: S, f: F) -> T
+where
+    Con: Any,
+    S: 'static,
+    F: FnOnce() -> T,
+{
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            SCOPES.with(|scopes| scopes.borrow_mut().pop());
+        }
+    }
+
+    SCOPES.with(|scopes| {
+        scopes
+            .borrow_mut()
+            .push((underlying_connection_address(con), Box::new(scope)))
+    });
+    let _guard = Guard;
+    f()
+}
+
+/// A factory with a column filled from the scope set with [`with_scope`].
+///
+/// You shouldn't have to implement this trait yourself. It is implemented by
+/// `#[derive(Factory)]` for factories with the `scoped_column` attribute.
+///
+/// [`with_scope`]: fn.with_scope.html
+pub trait ScopedFactory: factory_v2::Factory {
+    /// The type of the scope, such as a tenant id.
+    type Scope: Clone + 'static;
+
+    /// The column filled from the scope.
+    fn scoped_column() -> &'static str;
+
+    /// The innermost scope set on the connection.
+    ///
+    /// # Panics
+    /// Panics if no scope is set on the connection, or if every scope is of another type.
+    fn scope<Con: ?Sized>(con: &Con) -> Self::Scope {
+        let address = connection_address(con);
+        innermost_scope::<Self>(Some(address)).unwrap_or_else(|| {
+            panic!(
+                "{} fills `{}` from the scope, but no scope is set on the connection. Insert it inside `with_scope`",
+                std::any::type_name::<Self>(),
+                Self::scoped_column()
+            )
+        })
+    }
+
+    /// The innermost scope set on any connection, for uses without a connection such as
+    /// building models.
+    #[doc(hidden)]
+    fn any_scope() -> Option<Self::Scope> {
+        innermost_scope::<Self>(None)
+    }
+
+    #[doc(hidden)]
+    fn dry_run_scope<ST>() -> dry_run::Key<Self::Scope, ST> {
+        match Self::any_scope() {
+            Some(scope) => dry_run::Key::bound(scope),
+            None => dry_run::Key::pending("scope".to_string()),
+        }
+    }
+}
+
+/// Scopes of other types are skipped, so scopes for factories with different scope types can be
+/// nested.
+fn innermost_scope<F: ScopedFactory>(address: Option<usize>) -> Option<F::Scope> {
+    SCOPES.with(|scopes| {
+        let scopes = scopes.borrow();
+        let mut scopes = scopes
+            .iter()
+            .rev()
+            .filter(|(scope_address, _)| address.is_none() || address == Some(*scope_address))
+            .peekable();
+        scopes.peek()?;
+        let scope = scopes
+            .find_map(|(_, scope)| scope.downcast_ref::<F::Scope>())
+            .unwrap_or_else(|| {
+                panic!(
+                    "{} needs a scope of type {}, but every scope is of another type",
+                    std::any::type_name::<F>(),
+                    std::any::type_name::<F::Scope>()
+                )
+            });
+        Some(scope.clone())
+    })
+}
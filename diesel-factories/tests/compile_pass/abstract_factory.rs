@@ -0,0 +1,78 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel_factories::{Association, Factory};
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct CountryFactory {
+    pub name: String,
+}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities", abstract)]
+struct BaseCityFactory<'a> {
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+impl<'a> Default for BaseCityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+fn main() {
+    let country = Country {
+        id: 1,
+        name: "Denmark".into(),
+    };
+
+    BaseCityFactory::default()
+        .name("Aarhus")
+        .country(&country)
+        .country_with(|country| country.name("Norway"));
+}
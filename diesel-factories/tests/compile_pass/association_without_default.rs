@@ -0,0 +1,102 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel_factories::{Association, Factory};
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        users (id) {
+            id -> Integer,
+            country_id -> Nullable<Integer>,
+        }
+    }
+
+    table! {
+        categories (id) {
+            id -> Integer,
+            parent_id -> Nullable<Integer>,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+// Not `Default`, so `city.country_with(...)` and `city.country_strategy(Strategy::Create)` can't
+// be called, but `CityFactory` and `UserFactory` still compile.
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct CountryFactory {
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct CityFactory {
+    pub name: String,
+    pub country: Association<'static, Country, CountryFactory>,
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub country_id: Option<i32>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "User", table = "crate::schema::users")]
+struct UserFactory {
+    pub country: Option<Association<'static, Country, CountryFactory>>,
+}
+
+#[derive(Queryable, Clone)]
+struct Category {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+}
+
+// Not `Default` either, with `parent_depth` building the parents with a given function.
+#[derive(Clone, Factory)]
+#[factory(model = "Category", table = "crate::schema::categories")]
+struct CategoryFactory {
+    pub parent: Option<Association<'static, Category, CategoryFactory>>,
+}
+
+fn main() {
+    let city = CityFactory {
+        name: "Copenhagen".into(),
+        country: Association::new_factory(CountryFactory {
+            name: "Denmark".into(),
+        }),
+    };
+    let _ = city.country_id(1);
+    let _ = UserFactory { country: None }.country_id(1);
+    let _ = CategoryFactory { parent: None }.parent_depth(2, || CategoryFactory { parent: None });
+}
@@ -0,0 +1,52 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel_factories::Factory;
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+// `any()` is never true, so the code using the missing connection type isn't compiled
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    connection = "crate::MissingConnection",
+    cfg = "any()"
+)]
+struct GatedCountryFactory {
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries", cfg = "all()")]
+struct CountryFactory {
+    pub name: String,
+}
+
+fn main() {
+    GatedCountryFactory {
+        name: "Denmark".into(),
+    };
+
+    let factory = CountryFactory {
+        name: "Denmark".into(),
+    }
+    .name("Norway");
+    let _: fn(CountryFactory, &diesel::pg::PgConnection) -> Country = Factory::insert;
+    assert_eq!("Norway", factory.name);
+}
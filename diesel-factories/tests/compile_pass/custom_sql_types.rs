@@ -0,0 +1,87 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::bound::Bound;
+use diesel::expression::AsExpression;
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel_factories::Factory;
+use std::io::Write;
+
+mod schema {
+    pub mod sql_types {
+        #[derive(SqlType, QueryId)]
+        #[postgres(type_name = "role")]
+        pub struct Role;
+    }
+
+    table! {
+        use diesel::sql_types::*;
+        use crate::schema::sql_types::Role;
+
+        members (id) {
+            id -> Integer,
+            role -> Role,
+            settings -> Jsonb,
+            tags -> Array<Text>,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, FromSqlRow)]
+pub enum Role {
+    Admin,
+    Member,
+}
+
+// Only implemented by value, so the field needs `sql_type`
+impl AsExpression<schema::sql_types::Role> for Role {
+    type Expression = Bound<schema::sql_types::Role, Self>;
+
+    fn as_expression(self) -> Self::Expression {
+        Bound::new(self)
+    }
+}
+
+impl ToSql<schema::sql_types::Role, Pg> for Role {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        match self {
+            Role::Admin => out.write_all(b"admin")?,
+            Role::Member => out.write_all(b"member")?,
+        }
+        Ok(serialize::IsNull::No)
+    }
+}
+
+impl FromSql<schema::sql_types::Role, Pg> for Role {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"admin" => Ok(Role::Admin),
+            b"member" => Ok(Role::Member),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Member {
+    pub id: i32,
+    pub role: Role,
+    pub settings: serde_json::Value,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Member", table = "crate::schema::members")]
+struct MemberFactory {
+    #[factory(sql_type = "crate::schema::sql_types::Role")]
+    pub role: Role,
+    #[factory(sql_type = "diesel::sql_types::Jsonb")]
+    pub settings: serde_json::Value,
+    pub tags: Vec<String>,
+}
+
+fn main() {}
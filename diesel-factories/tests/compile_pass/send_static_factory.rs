@@ -0,0 +1,99 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel_factories::{Association, Factory, PolymorphicAssociation, Shared};
+use std::sync::Arc;
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        comments (id) {
+            id -> Integer,
+            body -> Text,
+            commentable_id -> Integer,
+            commentable_type -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct CountryFactory {
+    #[factory(default = "\"Denmark\".into()")]
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct CityFactory {
+    pub name: String,
+    pub country: Association<'static, Country, CountryFactory>,
+}
+
+#[derive(Queryable, Clone)]
+struct Comment {
+    pub id: i32,
+    pub body: String,
+    pub commentable_id: i32,
+    pub commentable_type: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Comment", table = "crate::schema::comments")]
+struct CommentFactory {
+    pub body: String,
+    #[factory(polymorphic = "commentable", types("Country", "City"))]
+    pub commentable: PolymorphicAssociation<'static>,
+}
+
+fn assert_send_static<T: Send + Sync + 'static>(_: T) {}
+
+fn main() {
+    let country = Arc::new(Country {
+        id: 1,
+        name: "Denmark".into(),
+    });
+
+    let city = CityFactory {
+        name: "Copenhagen".into(),
+        country: Association::default(),
+    };
+    assert_send_static(city.clone().country(Arc::clone(&country)));
+    assert_send_static(city.clone().country_id(1));
+    assert_send_static(city.clone().country(Shared::new(CountryFactory::default())));
+    assert_send_static(city.country_latest());
+
+    assert_send_static(CommentFactory {
+        body: "Nice".into(),
+        commentable: PolymorphicAssociation::factory(CountryFactory::default()),
+    });
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "faker")]
+#![allow(
+    proc_macro_derive_resolution_fallback,
+    non_local_definitions,
+    dead_code
+)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{random, ChangesetFactory, Factory};
+use std::time::SystemTime;
+
+mod schema {
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+            age -> Integer,
+            country_id -> Nullable<Integer>,
+            home_city_id -> Nullable<Integer>,
+            current_city_id -> Nullable<Integer>,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+    pub country_id: Option<i32>,
+    pub home_city_id: Option<i32>,
+    pub current_city_id: Option<i32>,
+}
+
+#[derive(AsChangeset)]
+#[table_name = "users"]
+struct UserChangeset {
+    pub name: Option<String>,
+}
+
+use schema::users;
+
+#[derive(Clone, Factory)]
+#[factory(model = "User", table = "crate::schema::users")]
+struct UserFactory {
+    #[factory(fake = "Name.name")]
+    pub name: String,
+    #[factory(default = "30")]
+    pub age: i32,
+}
+
+#[derive(Clone, ChangesetFactory)]
+#[factory(changeset = "UserChangeset")]
+struct UserChangesetFactory {
+    #[factory(fake = "Name.first_name")]
+    pub name: Option<String>,
+}
+
+#[test]
+fn fake_defaults_follow_the_seed() {
+    random::set_seed(1234);
+    let first = UserFactory::default();
+
+    random::set_seed(1234);
+    let second = UserFactory::default();
+
+    assert_eq!(first.name, second.name);
+    assert!(first.name.contains(' '));
+}
+
+#[test]
+fn inserting_with_fake_defaults() {
+    let con = setup();
+
+    let user = UserFactory::default().insert(&con);
+
+    assert!(!user.name.is_empty());
+    assert!(UserChangesetFactory::default().name.is_some());
+}
+
+#[test]
+fn fake_helpers() {
+    assert!(diesel_factories::fake_email().contains('@'));
+    assert!(!diesel_factories::fake_name().is_empty());
+    assert!(diesel_factories::fake_past_datetime() < SystemTime::now());
+}
+
+fn setup() -> PgConnection {
+    let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+    con
+}
@@ -4,8 +4,19 @@
 extern crate diesel;
 
 use diesel::{pg::PgConnection, prelude::*};
-use diesel_factories::{Association, Factory};
+use diesel_factories::{
+    fixtures, random, reset_sequence, reset_sequences, sequence, sequence_in, with_scope,
+    Association, BuildFactory, ChangesetFactory, Factory, FieldValue, FindBy, FindModel, InsertAll,
+    InsertWith, PolymorphicAssociation, ScopedFactory, Strategy, TestContext,
+};
+use std::time::{Duration, SystemTime};
 
+#[path = "setup/counting_connection.rs"]
+mod counting_connection;
+
+use counting_connection::CountingConnection;
+
+#[allow(non_local_definitions)]
 mod schema {
     table! {
         users (id) {
@@ -34,45 +45,328 @@ mod schema {
             country_id -> Integer,
         }
     }
+
+    table! {
+        languages (id) {
+            id -> Integer,
+            code -> Text,
+            name -> Text,
+        }
+    }
+
+    table! {
+        comments (id) {
+            id -> Integer,
+            body -> Text,
+            commentable_id -> Integer,
+            commentable_type -> Text,
+        }
+    }
+
+    table! {
+        countries_languages (country_id, language_id) {
+            country_id -> Integer,
+            language_id -> Integer,
+        }
+    }
+
+    table! {
+        bookmarks (id) {
+            id -> Integer,
+            bookmarkable_id -> Text,
+            bookmarkable_type -> Text,
+        }
+    }
+
+    table! {
+        tags (slug) {
+            slug -> Text,
+            name -> Text,
+        }
+    }
+
+    table! {
+        articles (id) {
+            id -> Integer,
+            title -> Text,
+            tags -> Nullable<Array<Text>>,
+            ratings -> Array<Integer>,
+            notes -> Array<Nullable<Text>>,
+        }
+    }
+
+    table! {
+        events (id) {
+            id -> Integer,
+            name -> Text,
+            created_at -> Timestamp,
+            updated_at -> Timestamp,
+        }
+    }
+
+    table! {
+        newsletters (id) {
+            id -> Integer,
+            title -> Text,
+            status -> Text,
+            subtitle -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        projects (id) {
+            id -> Integer,
+            name -> Text,
+            tenant_id -> Integer,
+        }
+    }
+
+    table! {
+        tasks (id) {
+            id -> Integer,
+            title -> Text,
+            project_id -> Integer,
+            tenant_id -> Integer,
+        }
+    }
+
+    table! {
+        categories (id) {
+            id -> Integer,
+            name -> Text,
+            parent_id -> Nullable<Integer>,
+        }
+    }
+}
+
+#[allow(non_local_definitions)]
+mod models {
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct User {
+        pub id: i32,
+        pub name: String,
+        pub age: i32,
+        pub country_id: Option<i32>,
+        pub home_city_id: Option<i32>,
+        pub current_city_id: Option<i32>,
+    }
+
+    #[derive(Queryable, Clone)]
+    pub struct Country {
+        pub id: i32,
+        pub name: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct City {
+        pub id: i32,
+        pub name: String,
+        pub team_association: String,
+        pub association_label: String,
+        pub country_id: i32,
+    }
+
+    #[derive(AsChangeset)]
+    #[table_name = "users"]
+    pub struct UserChangeset {
+        pub name: Option<String>,
+        pub age: i32,
+    }
+
+    #[derive(AsChangeset)]
+    #[table_name = "articles"]
+    pub struct ArticleChangeset {
+        pub tags: Option<Option<Vec<String>>>,
+        pub ratings: Vec<i32>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Language {
+        pub id: i32,
+        pub code: String,
+        pub name: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Comment {
+        pub id: i32,
+        pub body: String,
+        pub commentable_id: i32,
+        pub commentable_type: String,
+    }
+
+    #[derive(Queryable, Clone)]
+    pub struct Bookmark {
+        pub id: i32,
+        pub bookmarkable_id: String,
+        pub bookmarkable_type: String,
+    }
+
+    #[derive(Queryable, Clone)]
+    pub struct Tag {
+        pub slug: String,
+        pub name: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Article {
+        pub id: i32,
+        pub title: String,
+        pub tags: Option<Vec<String>>,
+        pub ratings: Vec<i32>,
+        pub notes: Vec<Option<String>>,
+    }
+
+    #[derive(Queryable, Clone)]
+    pub struct Event {
+        pub id: i32,
+        pub name: String,
+        pub created_at: SystemTime,
+        pub updated_at: SystemTime,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Newsletter {
+        pub id: i32,
+        pub title: String,
+        pub status: String,
+        pub subtitle: Option<String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Category {
+        pub id: i32,
+        pub name: String,
+        pub parent_id: Option<i32>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Project {
+        pub id: i32,
+        pub name: String,
+        pub tenant_id: i32,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Queryable, Clone)]
+    pub struct Task {
+        pub id: i32,
+        pub title: String,
+        pub project_id: i32,
+        pub tenant_id: i32,
+    }
+
+    #[derive(Queryable, Clone)]
+    pub struct CityName {
+        pub id: i32,
+        pub name: String,
+        pub country_id: i32,
+    }
+
+    /// Neither the model nor the factories are `Clone`.
+    #[derive(Queryable)]
+    pub struct UniqueCountry {
+        pub id: i32,
+        pub name: String,
+    }
 }
 
-#[derive(Queryable, Clone)]
-struct User {
-    pub id: i32,
+use models::*;
+
+use schema::{articles, users};
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "User",
+    table = "crate::schema::users",
+    connection = "diesel::pg::PgConnection",
+    build,
+    from_model,
+    traits("senior", "anonymous")
+)]
+struct UserFactory<'b> {
+    #[factory(anonymize = "fake_name")]
     pub name: String,
     pub age: i32,
-    pub country_id: Option<i32>,
-    pub home_city_id: Option<i32>,
-    pub current_city_id: Option<i32>,
+    pub country: std::option::Option<diesel_factories::Association<'b, Country, CountryFactory>>,
+    pub home_city: Option<diesel_factories::Association<'b, City, CityFactory<'b>>>,
+    pub current_city: Option<Association<'b, City, CityFactory<'b>>>,
 }
 
-#[derive(Queryable, Clone)]
-struct Country {
-    pub id: i32,
-    pub name: String,
+#[derive(Clone, Factory)]
+#[factory(model = "Article", table = "crate::schema::articles", build)]
+struct ArticleFactory {
+    #[factory(default = "\"Containers\".into()")]
+    pub title: String,
+    #[factory(default = "None")]
+    pub tags: Option<Vec<String>>,
+    #[factory(default = "vec![5]")]
+    pub ratings: Vec<i32>,
+    #[factory(default = "Vec::new()")]
+    pub notes: Vec<Option<String>>,
+}
+
+struct PeopleProfile;
+
+thread_local! {
+    static PEOPLE_PROFILE_BUILDS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+impl diesel_factories::profile::Profile for PeopleProfile {
+    fn providers(providers: &mut diesel_factories::profile::Providers) {
+        PEOPLE_PROFILE_BUILDS.with(|builds| builds.set(builds.get() + 1));
+        providers
+            .add("*name", || {
+                sequence_in("people_profile.name", |n| format!("Person {}", n))
+            })
+            .add("age", || 42);
+    }
 }
 
-#[derive(Queryable, Clone)]
-struct City {
-    pub id: i32,
+#[derive(Clone, Factory)]
+#[factory(
+    model = "User",
+    table = "crate::schema::users",
+    profile = "PeopleProfile"
+)]
+struct ProfiledUserFactory<'b> {
     pub name: String,
-    pub team_association: String,
-    pub association_label: String,
-    pub country_id: i32,
+    #[factory(default = "18")]
+    pub age: i32,
+    pub country: Option<Association<'b, Country, CountryFactory>>,
 }
 
 #[derive(Clone, Factory)]
 #[factory(
     model = "User",
     table = "crate::schema::users",
-    connection = "diesel::pg::PgConnection"
+    profile = "PeopleProfile"
 )]
-struct UserFactory<'b> {
+struct FullyProfiledUserFactory<'b> {
     pub name: String,
     pub age: i32,
-    pub country: std::option::Option<diesel_factories::Association<'b, Country, CountryFactory>>,
-    pub home_city: Option<diesel_factories::Association<'b, City, CityFactory<'b>>>,
-    pub current_city: Option<Association<'b, City, CityFactory<'b>>>,
+    pub country: Option<Association<'b, Country, CountryFactory>>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    profile = "PeopleProfile",
+    abstract
+)]
+struct UnmatchedProfileFactory {
+    pub title: String,
 }
 
 impl<'b> Default for UserFactory<'b> {
@@ -87,8 +381,26 @@ impl<'b> Default for UserFactory<'b> {
     }
 }
 
+// Clippy mistakes the two `association = "country"` for duplicated attributes
+#[allow(clippy::duplicated_attributes)]
+#[diesel_factories::factory_backend_tests(pg = "setup")]
 #[derive(Clone, Factory)]
-#[factory(model = "Country", table = "crate::schema::countries")]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    has_many(factory = "CityFactory<'b>", association = "country"),
+    has_many(factory = "UserFactory<'b>", association = "country"),
+    joins(
+        table = "crate::schema::countries_languages",
+        left = "country_id",
+        right = "language_id",
+        factory = "LanguageFactory",
+        name = "languages"
+    ),
+    connection = "diesel::pg::PgConnection",
+    connection = "crate::CountingConnection",
+    build
+)]
 struct CountryFactory {
     pub name: String,
 }
@@ -102,7 +414,13 @@ impl Default for CountryFactory {
 }
 
 #[derive(Clone, Factory)]
-#[factory(model = "City", table = "crate::schema::cities")]
+#[factory(
+    model = "City",
+    table = "crate::schema::cities",
+    connection = "diesel::pg::PgConnection",
+    connection = "crate::CountingConnection",
+    build
+)]
 struct CityFactory<'b> {
     pub name: String,
     pub team_association: String,
@@ -121,69 +439,1965 @@ impl<'b> Default for CityFactory<'b> {
     }
 }
 
-#[test]
-fn insert_one_user() {
-    let con = setup();
+impl<'b> UserFactory<'b> {
+    fn senior(self) -> Self {
+        self.age(70)
+    }
 
-    let user = UserFactory::default().name("Alice").insert(&con);
+    fn anonymous(self) -> Self {
+        self.name("Anonymous")
+    }
+}
 
-    assert_eq!(user.name, "Alice");
-    assert_eq!(user.age, 30);
-    assert_eq!(1, count_users(&con));
-    assert_eq!(0, count_countries(&con));
+fn fake_name(_: &str) -> String {
+    diesel_factories::sequence(|n| format!("User {}", n))
 }
 
-#[test]
-fn overriding_country() {
-    let con = setup();
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct DefaultedCityFactory {
+    #[factory(default = "\"Odense\".into()")]
+    pub name: String,
+    #[factory(default_with = "default_team_association")]
+    pub team_association: String,
+    #[factory(default = "\"thebest\".into()")]
+    pub association_label: String,
+    pub country: Association<'static, Country, CountryFactory>,
+}
 
-    let bob = UserFactory::default()
-        .country(Some(CountryFactory::default().name("USA")))
-        .insert(&con);
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities", build)]
+struct NationCityFactory {
+    #[factory(default = "\"Aarhus\".into()")]
+    pub name: String,
+    #[factory(default = "\"teamfive\".into()")]
+    pub team_association: String,
+    #[factory(default = "\"thebest\".into()")]
+    pub association_label: String,
+    #[factory(foreign_key = "country_id")]
+    pub nation: Association<'static, Country, CountryFactory>,
+}
 
-    let country = find_country_by_id(bob.country_id.unwrap(), &con);
+fn default_team_association() -> String {
+    "teamfive".into()
+}
 
-    assert_eq!("USA", country.name);
-    assert_eq!(1, count_users(&con));
-    assert_eq!(1, count_countries(&con));
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct TrimmedCountryFactory {
+    #[factory(map = "trim")]
+    pub name: String,
 }
 
-#[test]
-fn insert_two_users_sharing_country() {
-    let con = setup();
+impl Default for TrimmedCountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "  Denmark  ".into(),
+        }
+    }
+}
 
-    let country = CountryFactory::default().insert(&con);
-    let bob = UserFactory::default().country(Some(&country)).insert(&con);
-    let alice = UserFactory::default().country(Some(&country)).insert(&con);
+fn trim(name: String) -> String {
+    name.trim().to_string()
+}
 
-    assert_eq!(bob.country_id, alice.country_id);
-    assert_eq!(2, count_users(&con));
-    assert_eq!(1, count_countries(&con));
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    build,
+    from_model
+)]
+struct EmphasizedCountryFactory {
+    #[factory(map = "emphasize", default = "\"Denmark\".into()")]
+    pub name: String,
 }
 
-fn setup() -> PgConnection {
-    let database_url = "postgres://localhost/diesel_factories_test";
-    let con = PgConnection::establish(&database_url).unwrap();
-    con.begin_test_transaction().unwrap();
-    con
+fn emphasize(name: String) -> String {
+    format!("{}!", name)
 }
 
-fn count_users(con: &PgConnection) -> i64 {
-    use crate::schema::users;
-    use diesel::dsl::count_star;
-    users::table.select(count_star()).first(con).unwrap()
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    after_insert = "insert_capital"
+)]
+struct CountryWithCapitalFactory {
+    pub name: String,
 }
 
-fn count_countries(con: &PgConnection) -> i64 {
-    use crate::schema::countries;
-    use diesel::dsl::count_star;
-    countries::table.select(count_star()).first(con).unwrap()
+impl Default for CountryWithCapitalFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
 }
 
-fn find_country_by_id(input: i32, con: &PgConnection) -> Country {
-    use crate::schema::countries::dsl::*;
-    countries
-        .filter(id.eq(&input))
-        .first::<Country>(con)
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    version = "v2",
+    since_migration = "2019-05-10-072830_cities"
+)]
+struct CountryFactoryV2 {
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Country",
+    table = "crate::schema::countries",
+    since_migration = "2999-01-01-000000_not_applied"
+)]
+struct CountryFactoryFromTheFuture {
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Language",
+    table = "crate::schema::languages",
+    on_conflict = "do_nothing",
+    conflict_target = "code"
+)]
+struct LanguageFactory {
+    #[factory(default = "\"da\".into()")]
+    pub code: String,
+    #[factory(default = "\"Danish\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Language",
+    table = "crate::schema::languages",
+    on_conflict = "do_nothing",
+    conflict_target = "code"
+)]
+struct LowercaseLanguageFactory {
+    #[factory(map = "lowercase")]
+    pub code: String,
+    pub name: String,
+}
+
+fn lowercase(code: String) -> String {
+    code.to_lowercase()
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Language",
+    table = "crate::schema::languages",
+    on_conflict = "do_update",
+    conflict_target = "code"
+)]
+struct UpdatingLanguageFactory {
+    #[factory(default = "\"da\".into()")]
+    pub code: String,
+    #[factory(default = "\"Danish\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Event", table = "crate::schema::events", timestamps, build)]
+struct EventFactory {
+    #[factory(default = "\"Launch\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Event",
+    table = "crate::schema::events",
+    timestamps("created_at")
+)]
+struct BackdatedEventFactory {
+    #[factory(default = "\"Launch\".into()")]
+    pub name: String,
+    #[factory(default = "SystemTime::UNIX_EPOCH")]
+    pub updated_at: SystemTime,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Newsletter", table = "crate::schema::newsletters")]
+struct NewsletterFactory {
+    #[factory(default = "\"Issue\".into()")]
+    pub title: String,
+    #[factory(default = "FieldValue::Default")]
+    pub status: FieldValue<String>,
+    #[factory(default = "FieldValue::Default")]
+    pub subtitle: FieldValue<String>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Language", table = "crate::schema::languages")]
+struct CustomBuilderLanguageFactory {
+    #[factory(default = "\"sv\".into()", rename = "set_code")]
+    pub code: String,
+    #[factory(default = "\"Swedish\".into()", no_builder)]
+    pub name: String,
+}
+
+impl CustomBuilderLanguageFactory {
+    fn code(self, code: &str) -> Self {
+        self.set_code(code.to_lowercase())
+    }
+
+    fn name(mut self, name: &str) -> Self {
+        self.name = format!("{} language", name);
+        self
+    }
+}
+
+#[derive(Clone, ChangesetFactory)]
+#[factory(changeset = "UserChangeset")]
+struct UserChangesetFactory {
+    #[factory(map = "trim")]
+    pub name: Option<String>,
+    #[factory(default = "31")]
+    pub age: i32,
+}
+
+#[derive(Clone, ChangesetFactory)]
+#[factory(changeset = "ArticleChangeset")]
+struct ArticleChangesetFactory {
+    pub tags: Option<Option<Vec<String>>>,
+    #[factory(default = "vec![3]")]
+    pub ratings: Vec<i32>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Comment", table = "crate::schema::comments")]
+struct CommentFactory<'b> {
+    pub body: String,
+    #[factory(polymorphic = "commentable", types("Country", "City"))]
+    pub commentable: PolymorphicAssociation<'b>,
+}
+
+impl<'b> Default for CommentFactory<'b> {
+    fn default() -> Self {
+        Self {
+            body: "Nice".into(),
+            commentable: PolymorphicAssociation::factory(CountryFactory::default()),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Tag",
+    table = "crate::schema::tags",
+    id = "String",
+    primary_key = "slug"
+)]
+struct TagFactory {
+    pub slug: String,
+    pub name: String,
+}
+
+#[derive(Factory)]
+#[factory(model = "Bookmark", table = "crate::schema::bookmarks")]
+struct BookmarkFactory<'b> {
+    #[factory(polymorphic = "bookmarkable", types("Tag"), primary_key = "slug")]
+    pub bookmarkable: PolymorphicAssociation<'b, String>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Tag",
+    table = "crate::schema::tags",
+    id = "String",
+    primary_key = "slug",
+    reselect
+)]
+struct ReselectedTagFactory {
+    #[factory(map = "trim")]
+    pub slug: String,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "CityName",
+    table = "crate::schema::cities",
+    returning = "id, name, country_id"
+)]
+struct CityNameFactory<'a> {
+    #[factory(default = "\"Aarhus\".into()")]
+    pub name: String,
+    #[factory(default = "\"fans\".into()")]
+    pub team_association: String,
+    #[factory(default = "\"label\".into()")]
+    pub association_label: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Category", table = "crate::schema::categories")]
+struct CategoryFactory<'a> {
+    #[factory(default = "sequence(|n| format!(\"Category {}\", n))")]
+    pub name: String,
+    pub parent: Option<Association<'a, Category, CategoryFactory<'a>>>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Project",
+    table = "crate::schema::projects",
+    scoped_column = "tenant_id",
+    build
+)]
+struct ProjectFactory {
+    #[factory(default = "\"Roadmap\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Task",
+    table = "crate::schema::tasks",
+    scoped_column = "tenant_id"
+)]
+struct TaskFactory<'a> {
+    #[factory(default = "\"Plan\".into()")]
+    pub title: String,
+    pub project: Association<'a, Project, ProjectFactory>,
+}
+
+#[derive(Factory)]
+#[factory(model = "UniqueCountry", table = "crate::schema::countries")]
+struct UniqueCountryFactory {
+    #[factory(default = "\"Denmark\".into()")]
+    pub name: String,
+}
+
+#[derive(Factory)]
+#[factory(
+    model = "CityName",
+    table = "crate::schema::cities",
+    returning = "id, name, country_id"
+)]
+struct UniqueCityFactory<'a> {
+    #[factory(default = "\"Aarhus\".into()")]
+    pub name: String,
+    #[factory(default = "\"fans\".into()")]
+    pub team_association: String,
+    #[factory(default = "\"label\".into()")]
+    pub association_label: String,
+    pub country: Association<'a, UniqueCountry, UniqueCountryFactory>,
+}
+
+fn insert_capital(country: &Country, con: &PgConnection) {
+    CityFactory::default().country(country).insert(con);
+}
+
+#[test]
+fn insert_one_user() {
+    let con = setup();
+
+    let user = UserFactory::default().name("Alice").insert(&con);
+
+    assert_eq!(user.name, "Alice");
+    assert_eq!(user.age, 30);
+    assert_eq!(1, count_users(&con));
+    assert_eq!(0, count_countries(&con));
+}
+
+#[test]
+fn overriding_country() {
+    let con = setup();
+
+    let bob = UserFactory::default()
+        .country(Some(CountryFactory::default().name("USA")))
+        .insert(&con);
+
+    let country = find_country_by_id(bob.country_id.unwrap(), &con);
+
+    assert_eq!("USA", country.name);
+    assert_eq!(1, count_users(&con));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn insert_two_users_sharing_country() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let bob = UserFactory::default().country(Some(&country)).insert(&con);
+    let alice = UserFactory::default().country(Some(&country)).insert(&con);
+
+    assert_eq!(bob.country_id, alice.country_id);
+    assert_eq!(2, count_users(&con));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn map_is_applied_before_insert() {
+    let con = setup();
+
+    let country = TrimmedCountryFactory::default().insert(&con);
+    assert_eq!("Denmark", country.name);
+
+    let country = TrimmedCountryFactory::default()
+        .name(" Sweden\n")
+        .insert(&con);
+    assert_eq!("Sweden", country.name);
+}
+
+#[test]
+fn after_insert_hook_runs_after_insert() {
+    let con = setup();
+
+    let country = CountryWithCapitalFactory::default().insert(&con);
+
+    let city = find_city_by_country_id(country.id, &con);
+    assert_eq!("Copenhagen", city.name);
+    assert_eq!(1, count_countries(&con));
+    assert_eq!(1, count_cities(&con));
+}
+
+#[test]
+fn after_insert_hook_runs_when_inserting_returning_id() {
+    let con = setup();
+
+    let country_id = CountryWithCapitalFactory::default().insert_returning_id(&con);
+
+    assert_eq!(
+        country_id,
+        find_city_by_country_id(country_id, &con).country_id
+    );
+}
+
+#[test]
+#[should_panic(expected = "UserFactory attempted to insert inside `deny_factory_inserts`")]
+fn deny_factory_inserts_panics_on_insert() {
+    let con = setup();
+
+    diesel_factories::deny_factory_inserts(|| {
+        UserFactory::default().insert(&con);
+    });
+}
+
+#[test]
+fn deny_factory_inserts_allows_building_factories() {
+    let con = setup();
+
+    let factory = diesel_factories::deny_factory_inserts(|| UserFactory::default().name("Alice"));
+    let user = factory.insert(&con);
+
+    assert_eq!("Alice", user.name);
+}
+
+#[test]
+fn tweaking_association_factory_with_closure() {
+    let con = setup();
+
+    let city = CityFactory::default()
+        .country_with(|country| country.name("Norway"))
+        .insert(&con);
+    assert_eq!("Norway", find_country_by_id(city.country_id, &con).name);
+
+    let user = UserFactory::default()
+        .country_with(|country: CountryFactory| country.name("Sweden"))
+        .insert(&con);
+    assert_eq!(
+        "Sweden",
+        find_country_by_id(user.country_id.unwrap(), &con).name
+    );
+
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn versioned_factories_are_selected_by_applied_migrations() {
+    use diesel_factories::{Versioned, VersionedFactory};
+
+    let con = setup();
+
+    assert_eq!("v2", CountryFactoryV2::version());
+    assert!(CountryFactoryV2::is_current(&con));
+    assert!(!CountryFactoryFromTheFuture::is_current(&con));
+
+    let new = Versioned::select(
+        CountryFactory::default(),
+        CountryFactoryV2 {
+            name: "Norway".into(),
+        },
+        &con,
+    );
+    match new.insert(&con) {
+        Versioned::New(country) => assert_eq!("Norway", country.name),
+        Versioned::Old(_) => panic!("expected the new version"),
+    }
+
+    let old = Versioned::select(
+        CountryFactory::default(),
+        CountryFactoryFromTheFuture {
+            name: "Atlantis".into(),
+        },
+        &con,
+    );
+    match old.insert(&con) {
+        Versioned::Old(country) => assert_eq!("Denmark", country.name),
+        Versioned::New(_) => panic!("expected the old version"),
+    }
+
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn shared_association_is_only_inserted_once() {
+    use diesel_factories::Shared;
+
+    let con = setup();
+
+    let country = Shared::new(CountryFactory::default().name("Norway"));
+    let user = UserFactory::default()
+        .country(Some(country.clone()))
+        .home_city(Some(CityFactory::default().country(country.clone())))
+        .insert(&con);
+
+    let norway = country.model().unwrap();
+    assert_eq!("Norway", norway.name);
+    assert_eq!(Some(norway.id), user.country_id);
+    assert_eq!(
+        norway.id,
+        find_city_by_country_id(norway.id, &con).country_id
+    );
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn inserting_with_children() {
+    let con = setup();
+
+    let (country, cities) = CountryFactory::default()
+        .with_children::<CityFactory>(3)
+        .insert(&con);
+    assert_eq!(3, cities.len());
+    assert!(cities.iter().all(|city| city.country_id == country.id));
+
+    let (country, users) = CountryFactory::default()
+        .with_children::<UserFactory>(2)
+        .insert(&con);
+    assert_eq!(2, users.len());
+    assert!(users.iter().all(|user| user.country_id == Some(country.id)));
+
+    assert_eq!(2, count_countries(&con));
+    assert_eq!(3, count_cities(&con));
+    assert_eq!(2, count_users(&con));
+}
+
+#[test]
+fn building_models_without_inserting() {
+    let con = setup();
+
+    let (user, city) = diesel_factories::deny_factory_inserts(|| {
+        let country = CountryFactory::default().build_with_id(7);
+        let city = CityFactory::default().country(&country).build();
+        let user = UserFactory::default()
+            .name("Alice")
+            .home_city(Some(CityFactory::default()))
+            .country(Some(&country))
+            .build();
+        (user, city)
+    });
+
+    assert_eq!("Alice", user.name);
+    assert_eq!(Some(7), user.country_id);
+    assert!(user.home_city_id.is_some());
+    assert_eq!(None, user.current_city_id);
+    assert_eq!(7, city.country_id);
+    assert_ne!(city.id, user.home_city_id.unwrap());
+
+    assert_eq!(0, count_users(&con));
+    assert_eq!(0, count_cities(&con));
+    assert_eq!(0, count_countries(&con));
+}
+
+#[test]
+fn inserting_with_another_connection_type() {
+    let con = CountingConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+    let queries_before = con.queries();
+
+    let city = CityFactory::default().name("Aarhus").insert_with(&con);
+
+    assert_eq!("Aarhus", city.name);
+    assert_eq!(2, con.queries() - queries_before);
+}
+
+#[test]
+fn inserting_all_inserts_shared_parents_once() {
+    let con = CountingConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+    let count_countries = || {
+        schema::countries::table
+            .select(diesel::dsl::count_star())
+            .first::<i64>(&con)
+            .unwrap()
+    };
+    let countries_before = count_countries();
+    let queries_before = con.queries();
+
+    let norway = CountryFactory::default().name("Norway").shared();
+    let sweden = CountryFactory::default().name("Sweden").shared();
+    let cities = vec![
+        CityFactory::default().name("Oslo").country(norway.clone()),
+        CityFactory::default()
+            .name("Stockholm")
+            .country(sweden.clone()),
+        CityFactory::default()
+            .name("Bergen")
+            .country(norway.clone()),
+    ]
+    .insert_all(&con);
+
+    assert_eq!(2, con.queries() - queries_before);
+    assert_eq!(2, count_countries() - countries_before);
+    assert_eq!(cities[0].country_id, cities[2].country_id);
+    assert_ne!(cities[0].country_id, cities[1].country_id);
+    assert_eq!(
+        Some(cities[1].country_id),
+        sweden.model().map(|country| country.id)
+    );
+
+    let trondheim = CityFactory::default()
+        .name("Trondheim")
+        .country(norway)
+        .insert_with(&con);
+    assert_eq!(cities[0].country_id, trondheim.country_id);
+}
+
+#[test]
+fn inserting_batch_merges_rows_for_same_table() {
+    use diesel_factories::factory_v2::InsertBatch;
+
+    let con = CountingConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+    let queries_before = con.queries();
+
+    let cities = InsertBatch::insert_batch(
+        vec![
+            CityFactory::default().name("Aarhus"),
+            CityFactory::default().name("Odense"),
+            CityFactory::default()
+                .name("Oslo")
+                .country(CountryFactory::default().name("Norway")),
+        ],
+        &con,
+    )
+    .unwrap();
+
+    assert_eq!(2, con.queries() - queries_before);
+    assert_eq!(
+        vec!["Aarhus", "Odense", "Oslo"],
+        cities.iter().map(|city| &city.name).collect::<Vec<_>>()
+    );
+    assert_ne!(cities[0].country_id, cities[1].country_id);
+    let norway = schema::countries::table
+        .find(cities[2].country_id)
+        .first::<Country>(&con)
+        .unwrap();
+    assert_eq!("Norway", norway.name);
+}
+
+#[test]
+fn named_sequences_can_be_reset() {
+    let con = setup();
+    let name = |i| format!("User {}", i);
+
+    let first = UserFactory::default()
+        .name(sequence_in("integration.user_name", name))
+        .insert(&con);
+    let second = UserFactory::default()
+        .name(sequence_in("integration.user_name", name))
+        .insert(&con);
+    reset_sequence("integration.user_name");
+    let third = UserFactory::default()
+        .name(sequence_in("integration.user_name", name))
+        .insert(&con);
+
+    assert_eq!("User 1", first.name);
+    assert_eq!("User 2", second.name);
+    assert_eq!("User 1", third.name);
+}
+
+#[test]
+fn associating_with_latest_inserted_model() {
+    let con = setup();
+
+    CountryFactory::default().name("Denmark").insert(&con);
+    let norway = CountryFactory::default().name("Norway").insert(&con);
+    let city = CityFactory::default().country_latest().insert(&con);
+    let user = UserFactory::default()
+        .country_latest()
+        .home_city_latest()
+        .insert(&con);
+
+    assert_eq!(norway.id, city.country_id);
+    assert_eq!(Some(norway.id), user.country_id);
+    assert_eq!(Some(city.id), user.home_city_id);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+#[should_panic(expected = "has been inserted on this connection to associate with")]
+fn associating_with_latest_panics_without_inserts() {
+    let con = setup();
+
+    CityFactory::default().country_latest().insert(&con);
+}
+
+#[test]
+fn association_with_foreign_key_attribute() {
+    use diesel_factories::plan::{Plan, PlannedKey};
+
+    let con = setup();
+
+    let city = NationCityFactory::default()
+        .nation_with(|nation| nation.name("Iceland"))
+        .insert(&con);
+    assert_eq!("Iceland", find_country_by_id(city.country_id, &con).name);
+
+    let plan = NationCityFactory::default().plan();
+    assert_eq!(
+        vec![("country_id", PlannedKey::Insert(0))],
+        plan.inserts()[1].foreign_keys,
+    );
+
+    let built = NationCityFactory::default().build();
+    assert_eq!("Aarhus", built.name);
+    assert_eq!(1, count_cities(&con));
+}
+
+#[test]
+fn default_is_derived_from_field_attributes() {
+    let con = setup();
+
+    let city = DefaultedCityFactory::default().insert(&con);
+
+    assert_eq!("Odense", city.name);
+    assert_eq!("teamfive", city.team_association);
+    assert_eq!("thebest", city.association_label);
+    assert_eq!("Denmark", find_country_by_id(city.country_id, &con).name);
+}
+
+#[test]
+fn v2_insert_returns_errors() {
+    use diesel_factories::factory_v2;
+
+    let con = setup();
+
+    let country = factory_v2::Insert::insert(CountryFactory::default(), &con).unwrap();
+    assert_eq!("Denmark", country.name);
+
+    let city = CityFactory::default().country_with(|country| country.name("Den\0mark"));
+    assert!(factory_v2::Insert::insert(city, &con).is_err());
+}
+
+#[test]
+#[should_panic(expected = "UserFactory -> CityFactory -> CountryFactory (insert #")]
+fn failed_nested_insert_panics_with_insert_trace() {
+    let con = setup();
+
+    let city = CityFactory::default().country_with(|country| country.name("Den\0mark"));
+    UserFactory::default().home_city(Some(city)).insert(&con);
+}
+
+#[test]
+fn on_conflict_do_nothing_returns_existing_row() {
+    let con = setup();
+
+    use crate::schema::languages;
+    use diesel::dsl::sql;
+    use diesel::sql_types::Text;
+
+    // The row's physical location changes if it's updated
+    let location = |id: i32| {
+        languages::table
+            .find(id)
+            .select(sql::<Text>("ctid::text"))
+            .first::<String>(&con)
+            .unwrap()
+    };
+
+    let first = LanguageFactory::default().insert(&con);
+    let before = location(first.id);
+    let second = LanguageFactory::default().name("Dansk").insert(&con);
+    let batch = diesel_factories::factory_v2::InsertBatch::insert_batch(
+        vec![LanguageFactory::default(), LanguageFactory::default()],
+        &con,
+    )
+    .unwrap();
+
+    assert_eq!(first.id, second.id);
+    assert_eq!("Danish", second.name);
+    assert_eq!(vec![first.id, first.id], vec![batch[0].id, batch[1].id]);
+    assert_eq!(before, location(first.id));
+    assert_eq!(1, count_languages(&con));
+}
+
+#[test]
+fn on_conflict_do_nothing_finds_existing_row_by_mapped_value() {
+    let con = setup();
+
+    let first = LowercaseLanguageFactory {
+        code: "DA".into(),
+        name: "Danish".into(),
+    }
+    .insert(&con);
+    let second = LowercaseLanguageFactory {
+        code: "Da".into(),
+        name: "Dansk".into(),
+    }
+    .insert(&con);
+
+    assert_eq!("da", second.code);
+    assert_eq!(first.id, second.id);
+    assert_eq!(1, count_languages(&con));
+}
+
+#[test]
+fn on_conflict_do_update_updates_existing_row() {
+    let con = setup();
+
+    let first = UpdatingLanguageFactory::default().insert(&con);
+    let second = UpdatingLanguageFactory::default()
+        .name("Dansk")
+        .insert(&con);
+
+    assert_eq!(first.id, second.id);
+    assert_eq!("Dansk", second.name);
+    assert_eq!(1, count_languages(&con));
+}
+
+#[test]
+fn updating_with_changeset_factory() {
+    let con = setup();
+    let user = UserFactory::default().name("Alice").insert(&con);
+
+    let changeset = UserChangesetFactory::default().build();
+    let user = diesel::update(users::table.find(user.id))
+        .set(&changeset)
+        .get_result::<User>(&con)
+        .unwrap();
+    assert_eq!("Alice", user.name);
+    assert_eq!(31, user.age);
+
+    let changeset = UserChangesetFactory::default()
+        .name(" Bob ")
+        .age(40)
+        .build();
+    let user = diesel::update(users::table.find(user.id))
+        .set(&changeset)
+        .get_result::<User>(&con)
+        .unwrap();
+    assert_eq!("Bob", user.name);
+    assert_eq!(40, user.age);
+
+    let changeset = UserChangesetFactory::default()
+        .name("Carol")
+        .clear_name()
+        .build();
+    let user = diesel::update(users::table.find(user.id))
+        .set(&changeset)
+        .get_result::<User>(&con)
+        .unwrap();
+    assert_eq!("Bob", user.name);
+    assert_eq!(31, user.age);
+}
+
+#[test]
+fn sampling_associations_from_existing_models() {
+    let con = setup();
+    diesel_factories::random::set_seed(7);
+
+    let countries = vec![
+        CountryFactory::default().name("Denmark").insert(&con),
+        CountryFactory::default().name("Norway").insert(&con),
+    ];
+    let cities = (0..20)
+        .map(|_| {
+            CityFactory::default()
+                .country_sampled_from(&countries)
+                .insert(&con)
+        })
+        .collect::<Vec<_>>();
+    assert!(cities.iter().all(|city| countries
+        .iter()
+        .any(|country| country.id == city.country_id)));
+
+    let weighted = [(&countries[0], 0), (&countries[1], 1)];
+    let users = (0..5)
+        .map(|_| {
+            UserFactory::default()
+                .country_sampled_from_weighted(&weighted)
+                .insert(&con)
+        })
+        .collect::<Vec<_>>();
+    assert!(users
+        .iter()
+        .all(|user| user.country_id == Some(countries[1].id)));
+}
+
+#[test]
+fn inserting_factories_through_trait_objects() {
+    use diesel_factories::DynFactory;
+
+    let con = setup();
+
+    let factories: Vec<Box<dyn DynFactory<PgConnection>>> = vec![
+        Box::new(CountryFactory::default()),
+        Box::new(CityFactory::default()),
+        Box::new(UserFactory::default().name("Alice")),
+    ];
+    let inserted = factories
+        .into_iter()
+        .map(|factory| factory.insert_dyn(&con))
+        .collect::<Vec<_>>();
+
+    let user = inserted[2].model.downcast_ref::<User>().unwrap();
+    assert_eq!("Alice", user.name);
+    assert_eq!(user.id.to_string(), inserted[2].id);
+    assert_eq!(2, count_countries(&con));
+    assert_eq!(1, count_cities(&con));
+}
+
+#[test]
+fn polymorphic_association_sets_id_and_type() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let comment = CommentFactory::default()
+        .commentable_country(&country)
+        .insert(&con);
+    assert_eq!(country.id, comment.commentable_id);
+    assert_eq!("Country", comment.commentable_type);
+
+    let city = CityFactory::default().insert(&con);
+    let comment = CommentFactory::default()
+        .commentable_city(&city)
+        .insert(&con);
+    assert_eq!(city.id, comment.commentable_id);
+    assert_eq!("City", comment.commentable_type);
+
+    let comment = CommentFactory::default()
+        .commentable(PolymorphicAssociation::factory(CityFactory::default()))
+        .insert(&con);
+    assert_eq!("City", comment.commentable_type);
+    assert_eq!(2, count_cities(&con));
+}
+
+#[test]
+fn polymorphic_association_to_models_with_string_keys() {
+    let con = setup();
+    let tag = TagFactory {
+        slug: "rust".into(),
+        name: "Rust".into(),
+    }
+    .insert(&con);
+
+    let bookmark = BookmarkFactory {
+        bookmarkable: PolymorphicAssociation::factory(TagFactory {
+            slug: "diesel".into(),
+            name: "Diesel".into(),
+        }),
+    }
+    .insert(&con);
+    assert_eq!("diesel", bookmark.bookmarkable_id);
+
+    let bookmark = BookmarkFactory {
+        bookmarkable: PolymorphicAssociation::model("Tag", String::new()),
+    }
+    .bookmarkable_tag(&tag)
+    .insert(&con);
+    assert_eq!("rust", bookmark.bookmarkable_id);
+    assert_eq!("Tag", bookmark.bookmarkable_type);
+}
+
+#[test]
+#[should_panic(expected = "User isn't one of the polymorphic association's types: Country, City")]
+fn polymorphic_association_rejects_unlisted_types() {
+    let con = setup();
+    CommentFactory::default()
+        .commentable(PolymorphicAssociation::factory(UserFactory::default()))
+        .insert(&con);
+}
+
+#[test]
+fn inserting_with_join_table_rows() {
+    let con = setup();
+
+    let (country, languages) = CountryFactory::default()
+        .name("Switzerland")
+        .languages(vec![
+            LanguageFactory::default().code("de").name("German"),
+            LanguageFactory::default().code("fr").name("French"),
+        ])
+        .insert(&con);
+
+    assert_eq!(2, languages.len());
+    assert_eq!(2, count_languages(&con));
+
+    let mut language_ids = {
+        use schema::countries_languages::dsl::*;
+        countries_languages
+            .filter(country_id.eq(country.id))
+            .select(language_id)
+            .load::<i32>(&con)
+            .unwrap()
+    };
+    language_ids.sort();
+    let mut expected = languages
+        .iter()
+        .map(|language| language.id)
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(expected, language_ids);
+}
+
+#[test]
+fn cloning_models_with_anonymized_fields() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let original = UserFactory::default()
+        .name("Alice Smith")
+        .age(42)
+        .country(Some(&country))
+        .insert(&con);
+
+    let clone = UserFactory::from_model_anonymized(&original).insert(&con);
+
+    assert_ne!(original.id, clone.id);
+    assert_ne!(original.name, clone.name);
+    assert!(clone.name.starts_with("User "));
+    assert_eq!(42, clone.age);
+    assert_eq!(Some(country.id), clone.country_id);
+    assert_eq!(None, clone.home_city_id);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn applying_traits_by_name() {
+    let con = setup();
+
+    let user = UserFactory::default()
+        .with_traits(&["senior", "anonymous"])
+        .insert(&con);
+    assert_eq!(70, user.age);
+    assert_eq!("Anonymous", user.name);
+
+    let user = UserFactory::default().senior().insert(&con);
+    assert_eq!(70, user.age);
+    assert_eq!("Bob", user.name);
+}
+
+#[test]
+#[should_panic(
+    expected = "UserFactory has no trait named \"banned\". Known traits: senior, anonymous"
+)]
+fn applying_unknown_trait_panics() {
+    UserFactory::default().with_traits(&["banned"]);
+}
+
+#[test]
+fn choosing_association_strategies() {
+    let con = setup();
+
+    let denmark = CountryFactory::default().insert(&con);
+
+    let user = UserFactory::default()
+        .country_strategy(FindBy(schema::countries::name.eq("Denmark")))
+        .insert(&con);
+    assert_eq!(Some(denmark.id), user.country_id);
+    assert_eq!(1, count_countries(&con));
+
+    let city = CityFactory::default()
+        .country(&denmark)
+        .country_strategy(Strategy::Create)
+        .insert(&con);
+    assert_ne!(denmark.id, city.country_id);
+    assert_eq!(2, count_countries(&con));
+
+    let user = UserFactory::default()
+        .country_strategy(Strategy::Build)
+        .build();
+    assert!(user.country_id.is_some());
+    assert_ne!(Some(denmark.id), user.country_id);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn inserting_built_association_returns_error() {
+    let con = setup();
+
+    let result = diesel_factories::factory_v2::Insert::insert(
+        CityFactory::default().country_strategy(Strategy::Build),
+        &con,
+    );
+    assert!(result.is_err());
+    assert_eq!(0, count_cities(&con));
+    assert_eq!(0, count_countries(&con));
+}
+
+#[test]
+fn finding_association_with_another_connection_type_returns_error() {
+    let con = CountingConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+
+    let result = diesel_factories::factory_v2::Insert::insert(
+        CityFactory::default().country_strategy(FindBy(schema::countries::name.eq("Denmark"))),
+        &con,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn finding_missing_association_returns_error() {
+    let con = setup();
+
+    let result = diesel_factories::factory_v2::Insert::insert(
+        CityFactory::default().country_strategy(FindBy(schema::countries::name.eq("Narnia"))),
+        &con,
+    );
+    assert_eq!(diesel::result::Error::NotFound, result.err().unwrap());
+}
+
+#[test]
+fn creating_graph_of_records() {
+    let con = setup();
+
+    let graph = diesel_factories::create_graph!(&con, {
+        country: CountryFactory::default().name("Denmark"),
+        capital: CityFactory::default().name("Copenhagen").country(&country),
+        towns: [CityFactory::default().country(&country); 2],
+        user: UserFactory::default()
+            .country(Some(&country))
+            .home_city(Some(&capital)),
+    });
+
+    assert_eq!(graph.country.id, graph.capital.country_id);
+    assert!(graph
+        .towns
+        .iter()
+        .all(|town| town.country_id == graph.country.id));
+    assert_eq!(Some(graph.capital.id), graph.user.home_city_id);
+    assert_eq!(1, count_countries(&con));
+    assert_eq!(3, count_cities(&con));
+    assert_eq!(1, count_users(&con));
+}
+
+#[test]
+fn creating_nested_graph_inserts_parents_first() {
+    let con = setup();
+
+    let graph = diesel_factories::create_graph!(&con, {
+        country: CountryFactory::default().name("Denmark") => {
+            capital: CityFactory::default().name("Copenhagen").country(&country) => {
+                user: UserFactory::default()
+                    .country(Some(&country))
+                    .home_city(Some(&capital)),
+            },
+            town: CityFactory::default().name("Odense").country(&country),
+        },
+        sweden: CountryFactory::default().name("Sweden") => {},
+    });
+
+    assert_eq!(graph.country.id, graph.capital.country_id);
+    assert_eq!(graph.country.id, graph.town.country_id);
+    assert_eq!(Some(graph.capital.id), graph.user.home_city_id);
+    assert_eq!("Sweden", graph.sweden.name);
+    assert_eq!(2, count_countries(&con));
+    assert_eq!(2, count_cities(&con));
+    assert_eq!(1, count_users(&con));
+}
+
+#[test]
+fn creating_graph_evaluates_repeated_factory_for_each_record() {
+    let con = setup();
+
+    let mut evaluated = 0;
+    let graph = diesel_factories::create_graph!(&con, {
+        countries: [{
+            evaluated += 1;
+            CountryFactory::default().name(format!("Country {}", evaluated))
+        }; 3],
+    });
+
+    assert_eq!(3, evaluated);
+    assert_eq!("Country 1", graph.countries[0].name);
+    assert_eq!("Country 3", graph.countries[2].name);
+}
+
+#[test]
+fn inserting_with_string_primary_key() {
+    let con = setup();
+
+    let tag = TagFactory {
+        slug: "rust".into(),
+        name: "Rust".into(),
+    }
+    .insert(&con);
+
+    assert_eq!("rust", tag.slug);
+    assert_eq!("rust", TagFactory::id_for_model(&tag));
+}
+
+#[test]
+fn reselecting_inserted_row_by_primary_key() {
+    let con = setup();
+
+    let tag = ReselectedTagFactory {
+        slug: " diesel ".into(),
+        name: "Diesel".into(),
+    }
+    .insert(&con);
+
+    assert_eq!("diesel", tag.slug);
+    assert_eq!("Diesel", tag.name);
+}
+
+#[test]
+fn profile_provides_defaults_by_field_name() {
+    let con = setup();
+
+    let user = ProfiledUserFactory::default().insert(&con);
+
+    assert!(user.name.starts_with("Person "));
+    assert_eq!(18, user.age);
+    assert_eq!(None, user.country_id);
+}
+
+#[test]
+fn profile_providers_are_built_once_per_factory() {
+    let builds = || PEOPLE_PROFILE_BUILDS.with(|builds| builds.get());
+    let before = builds();
+
+    let factory = FullyProfiledUserFactory::default();
+
+    assert_eq!(before + 1, builds());
+    assert!(factory.name.starts_with("Person "));
+    assert_eq!(42, factory.age);
+}
+
+#[test]
+#[should_panic(expected = "matches UnmatchedProfileFactory.title")]
+fn profile_without_matching_provider_panics() {
+    UnmatchedProfileFactory::default();
+}
+
+#[test]
+fn inserting_nested_container_columns() {
+    let con = setup();
+
+    let untagged = ArticleFactory::default().insert(&con);
+    let article = ArticleFactory::default()
+        .tags(["rust", "diesel"])
+        .ratings(vec![4, 5])
+        .notes(vec![Some("draft".to_string()), None])
+        .insert(&con);
+
+    assert_eq!(None, untagged.tags);
+    assert_eq!(vec![5], untagged.ratings);
+    assert_eq!(
+        Some(vec!["rust".to_string(), "diesel".to_string()]),
+        article.tags
+    );
+    assert_eq!(vec![4, 5], article.ratings);
+    assert_eq!(vec![Some("draft".to_string()), None], article.notes);
+}
+
+#[test]
+fn updating_nested_container_columns_with_changeset_factory() {
+    let con = setup();
+    let article = ArticleFactory::default().insert(&con);
+
+    let changeset = ArticleChangesetFactory::default()
+        .tags(["orm"])
+        .ratings([2, 3])
+        .build();
+    let article = diesel::update(articles::table.find(article.id))
+        .set(&changeset)
+        .get_result::<Article>(&con)
+        .unwrap();
+
+    assert_eq!(Some(vec!["orm".to_string()]), article.tags);
+    assert_eq!(vec![2, 3], article.ratings);
+}
+
+#[test]
+fn building_nested_container_columns() {
+    let article = ArticleFactory::default()
+        .tags(vec!["rust".to_string()])
+        .tags(None)
+        .ratings(&[1, 2][..])
+        .build();
+
+    assert_eq!(None, article.tags);
+    assert_eq!(vec![1, 2], article.ratings);
+}
+
+#[test]
+fn renamed_and_skipped_builder_methods() {
+    let con = setup();
+
+    let language = CustomBuilderLanguageFactory::default()
+        .code("NO")
+        .name("Norwegian")
+        .insert(&con);
+
+    assert_eq!("no", language.code);
+    assert_eq!("Norwegian language", language.name);
+}
+
+#[test]
+fn fixture_budget_returns_closure_value_within_budget() {
+    let con = setup();
+
+    let country = diesel_factories::with_fixture_budget(Duration::from_secs(60), || {
+        CountryFactory::default().name("Iceland").insert(&con)
+    });
+
+    assert_eq!("Iceland", country.name);
+}
+
+#[test]
+fn fixture_budget_panics_with_breakdown_per_table() {
+    let con = setup();
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        diesel_factories::with_fixture_budget(Duration::from_nanos(1), || {
+            CityFactory::default().insert(&con);
+        })
+    }));
+
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("over the budget of 1ns:"), "{}", message);
+    assert!(message.contains("\n    cities: "), "{}", message);
+    assert!(message.contains("\n    countries: "), "{}", message);
+}
+
+#[test]
+fn returning_subset_of_columns() {
+    let con = setup();
+
+    let city = CityNameFactory::default().insert(&con);
+    let found =
+        <CityNameFactory as FindModel<_, _>>::find_model(schema::cities::id.eq(city.id), &con)
+            .unwrap();
+
+    assert_eq!("Aarhus", city.name);
+    assert_eq!(city.country_id, found.country_id);
+}
+
+#[test]
+fn inserting_returning_only_id() {
+    let con = setup();
+
+    let country_id = CountryFactory::default()
+        .name("Norway")
+        .insert_returning_id(&con);
+    let country = find_country_by_id(country_id, &con);
+    let city_id = diesel_factories::factory_v2::InsertReturningId::insert_returning_id(
+        CityNameFactory::default().country(&country),
+        &con,
+    )
+    .unwrap();
+
+    assert_eq!("Norway", country.name);
+    assert_eq!(city_id, find_city_by_country_id(country_id, &con).id);
+}
+
+#[test]
+fn planning_inserts_without_database() {
+    use diesel_factories::plan::{Plan, PlannedKey};
+    use diesel_factories::Shared;
+
+    let country = Shared::new(CountryFactory::default());
+    let factory = UserFactory::default()
+        .country(Some(country.clone()))
+        .home_city(Some(CityFactory::default().country(country)));
+
+    let plan = factory.plan();
+
+    assert_eq!(vec!["countries", "cities", "users"], plan.tables());
+    assert_eq!(
+        vec![
+            ("country_id", PlannedKey::Insert(0)),
+            ("home_city_id", PlannedKey::Insert(1)),
+            ("current_city_id", PlannedKey::Null),
+        ],
+        plan.inserts()[2].foreign_keys,
+    );
+    assert_eq!(
+        "[0] CountryFactory into countries\n\
+         [1] CityFactory into cities (country_id = [0])\n\
+         [2] UserFactory into users (country_id = [0], home_city_id = [1], current_city_id = null)\n",
+        plan.to_string(),
+    );
+
+    let comment = CommentFactory::default().plan();
+    assert_eq!(vec!["countries", "comments"], comment.tables());
+}
+
+#[test]
+fn failed_insert_in_savepoint_keeps_transaction_usable() {
+    let con = setup();
+
+    let tag = TagFactory {
+        slug: "rust".into(),
+        name: "Rust".into(),
+    };
+    tag.clone().insert(&con);
+
+    let result = tag.insert_in_savepoint(&con);
+    assert!(result.is_err());
+
+    let country = CountryFactory::default().insert(&con);
+    assert_eq!(country.id, find_country_by_id(country.id, &con).id);
+}
+
+#[test]
+fn building_with_shared_association_builds_it_once() {
+    let copenhagen = CityFactory::default().shared();
+
+    let alice = UserFactory::default()
+        .home_city(Some(copenhagen.clone()))
+        .build();
+    let bob = UserFactory::default()
+        .home_city(Some(copenhagen.clone()))
+        .build();
+
+    assert!(alice.home_city_id.is_some());
+    assert_eq!(alice.home_city_id, bob.home_city_id);
+    assert!(copenhagen.model().is_none());
+}
+
+#[test]
+fn shared_factory_is_kept_when_inserting_it_fails() {
+    let con = setup();
+
+    let copenhagen = CityFactory::default()
+        .country_strategy(FindBy(schema::countries::name.eq("Denmark")))
+        .shared();
+    let result = UserFactory::default()
+        .home_city(Some(copenhagen.clone()))
+        .insert_in_savepoint(&con);
+    assert!(result.is_err());
+
+    let denmark = CountryFactory::default().insert(&con);
+    let user = UserFactory::default()
+        .home_city(Some(copenhagen.clone()))
+        .insert(&con);
+    let copenhagen = copenhagen.model().unwrap();
+    assert_eq!(Some(copenhagen.id), user.home_city_id);
+    assert_eq!(denmark.id, copenhagen.country_id);
+}
+
+#[test]
+fn panics_inside_savepoint_roll_back() {
+    use diesel_factories::with_savepoint;
+
+    let con = setup();
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        with_savepoint(&con, || -> QueryResult<()> {
+            CityFactory::default().insert(&con);
+            panic!("Something went wrong after inserting")
+        })
+    }));
+    assert!(result.is_err());
+
+    assert_eq!(0, count_countries(&con));
+    assert_eq!(0, count_cities(&con));
+}
+
+#[test]
+fn timestamps_are_set_to_now() {
+    let con = setup();
+    let before = SystemTime::now() - Duration::from_secs(60);
+
+    let event = EventFactory::default().insert(&con);
+    assert_eq!("Launch", event.name);
+    assert!(event.created_at > before);
+    assert!(event.updated_at > before);
+
+    let events = vec![EventFactory::default(); 2].insert_all(&con);
+    assert!(events.iter().all(|event| event.created_at > before));
+
+    let event = EventFactory::default().build();
+    assert!(event.created_at > before);
+}
+
+#[test]
+fn fields_win_over_timestamps() {
+    let con = setup();
+    let before = SystemTime::now() - Duration::from_secs(60);
+
+    let event = BackdatedEventFactory::default().insert(&con);
+    assert!(event.created_at > before);
+    assert_eq!(SystemTime::UNIX_EPOCH, event.updated_at);
+}
+
+#[test]
+fn dry_run_prints_the_insert_without_running_it() {
+    use diesel_factories::dry_run::DryRun;
+
+    let con = setup();
+    let country = CountryFactory::default().insert(&con);
+
+    let sql = UserFactory::default()
+        .country(Some(&country))
+        .home_city(Some(CityFactory::default()))
+        .to_sql();
+    assert_eq!(
+        format!(
+            "INSERT INTO \"users\" (\"name\", \"age\", \"country_id\", \"home_city_id\", \"current_city_id\") \
+             VALUES ($1, $2, $3, /* id of new City */, $4) -- binds: [\"Bob\", 30, Some({}), None]",
+            country.id
+        ),
+        sql
+    );
+
+    let sql = CommentFactory::default().to_sql();
+    assert!(
+        sql.contains("VALUES ($1, /* id of new Country */, $2)"),
+        "{}",
+        sql
+    );
+
+    let sql = EventFactory::default().to_sql();
+    assert!(
+        sql.contains("VALUES ($1, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)"),
+        "{}",
+        sql
+    );
+
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn field_values_insert_defaults_nulls_and_values() {
+    let con = setup();
+
+    let newsletter = NewsletterFactory::default().insert(&con);
+    assert_eq!("draft", newsletter.status);
+    assert_eq!(Some("Weekly".to_string()), newsletter.subtitle);
+
+    let newsletter = NewsletterFactory::default()
+        .status("sent")
+        .subtitle(None)
+        .insert(&con);
+    assert_eq!("sent", newsletter.status);
+    assert_eq!(None, newsletter.subtitle);
+
+    let newsletters = vec![
+        NewsletterFactory::default(),
+        NewsletterFactory::default().subtitle(Some("Monthly".to_string())),
+    ]
+    .insert_all(&con);
+    assert_eq!(
+        vec![Some("Weekly".to_string()), Some("Monthly".to_string())],
+        newsletters
+            .into_iter()
+            .map(|newsletter| newsletter.subtitle)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn factory_from_model_does_not_map_copied_values_again() {
+    let con = setup();
+
+    let denmark = EmphasizedCountryFactory::default().insert(&con);
+    assert_eq!("Denmark!", denmark.name);
+
+    let copy = EmphasizedCountryFactory::from_model(&denmark).insert(&con);
+    assert_eq!("Denmark!", copy.name);
+    let built = EmphasizedCountryFactory::from_model(&denmark).build();
+    assert_eq!("Denmark!", built.name);
+
+    let sweden = EmphasizedCountryFactory::from_model(&denmark)
+        .name("Sweden")
+        .insert(&con);
+    assert_eq!("Sweden!", sweden.name);
+}
+
+#[test]
+fn factory_from_model_copies_fields_and_associations() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let bob = UserFactory::default()
+        .age(42)
+        .country(Some(&country))
+        .insert(&con);
+
+    let alice = UserFactory::from_model(&bob).name("Alice").insert(&con);
+
+    assert_ne!(bob.id, alice.id);
+    assert_eq!("Alice", alice.name);
+    assert_eq!(42, alice.age);
+    assert_eq!(Some(country.id), alice.country_id);
+    assert_eq!(None, alice.home_city_id);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn inserting_with_associations_returns_the_associated_models() {
+    let con = setup();
+
+    let (city, associations) = CityFactory::default().insert_with_associations(&con);
+    assert_eq!(city.country_id, associations.country.id);
+    assert_eq!("Denmark", associations.country.name);
+
+    let (user, associations) = UserFactory::default()
+        .home_city(Some(CityFactory::default().name("Aarhus")))
+        .insert_with_associations(&con);
+    assert_eq!(None, associations.country.map(|country| country.id));
+    assert_eq!(None, associations.current_city.map(|city| city.id));
+    let home_city = associations.home_city.unwrap();
+    assert_eq!(user.home_city_id, Some(home_city.id));
+    assert_eq!("Aarhus", home_city.name);
+    let home_city_associations = associations.home_city_associations.unwrap();
+    assert_eq!(home_city.country_id, home_city_associations.country.id);
+    assert_eq!("Denmark", home_city_associations.country.name);
+
+    let (city, associations) = CityFactory::default()
+        .country_latest()
+        .insert_with_associations(&con);
+    assert_eq!(city.country_id, associations.country.id);
+    assert!(associations.country_associations.is_none());
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn factories_and_models_do_not_have_to_be_clone() {
+    let con = setup();
+
+    let denmark = diesel_factories::Shared::without_retry(UniqueCountryFactory::default());
+    let aarhus = UniqueCityFactory::default()
+        .country(denmark.clone())
+        .insert(&con);
+    let odense = UniqueCityFactory::default()
+        .name("Odense")
+        .country(denmark)
+        .insert(&con);
+    assert_eq!(aarhus.country_id, odense.country_id);
+
+    let (city, associations) = UniqueCityFactory::default().insert_with_associations(&con);
+    assert_eq!(city.country_id, associations.country.id);
+    assert_eq!("Denmark", associations.country.name);
+    assert_eq!(2, count_countries(&con));
+
+    let graph = diesel_factories::create_graph!(&con, {
+        countries: [UniqueCountryFactory::default().name(sequence(|n| format!("Land {}", n))); 2],
+    });
+    assert_ne!(graph.countries[0].name, graph.countries[1].name);
+}
+
+#[test]
+fn factories_can_be_associated_with_their_own_type() {
+    use crate::schema::categories;
+
+    let con = setup();
+
+    let electronics = CategoryFactory::default()
+        .parent_with(|parent| parent.name("Products"))
+        .name("Electronics")
+        .insert(&con);
+    let phones = CategoryFactory::default()
+        .parent(Some(&electronics))
+        .insert(&con);
+    assert_eq!(Some(electronics.id), phones.parent_id);
+
+    let mut category = CategoryFactory::default()
+        .parent_depth(3, || CategoryFactory::default().name("Ancestor"))
+        .insert(&con);
+    let mut depth = 0;
+    while let Some(parent_id) = category.parent_id {
+        category = categories::table.find(parent_id).first(&con).unwrap();
+        depth += 1;
+    }
+    assert_eq!(3, depth);
+    assert_eq!("Ancestor", category.name);
+    assert_eq!(
+        None,
+        CategoryFactory::default()
+            .parent_depth(0, CategoryFactory::default)
+            .insert(&con)
+            .parent_id
+    );
+}
+
+#[test]
+fn scoped_columns_are_filled_from_the_scope() {
+    use crate::schema::projects;
+
+    let con = setup();
+
+    let (task, other) = with_scope(&con, 7, || {
+        let task = TaskFactory::default().insert(&con);
+        let other = with_scope(&con, 8, || ProjectFactory::default().insert(&con));
+        (task, other)
+    });
+    let project = projects::table
+        .find(task.project_id)
+        .first::<Project>(&con)
+        .unwrap();
+    assert_eq!(
+        (7, 7, 8),
+        (task.tenant_id, project.tenant_id, other.tenant_id)
+    );
+
+    let built = with_scope(&con, 9, || ProjectFactory::default().build());
+    assert_eq!(9, built.tenant_id);
+    assert_eq!(
+        "tenant_id",
+        <ProjectFactory as ScopedFactory>::scoped_column()
+    );
+}
+
+#[test]
+fn scopes_of_another_type_are_skipped() {
+    let con = setup();
+
+    let project = with_scope(&con, 7, || {
+        with_scope(&con, "other".to_string(), || {
+            ProjectFactory::default().insert(&con)
+        })
+    });
+
+    assert_eq!(7, project.tenant_id);
+}
+
+#[test]
+#[should_panic(expected = "but every scope is of another type")]
+fn scoped_factories_require_a_scope_of_their_type() {
+    let con = setup();
+
+    with_scope(&con, "other".to_string(), || {
+        ProjectFactory::default().insert(&con)
+    });
+}
+
+#[test]
+#[should_panic(expected = "fills `tenant_id` from the scope, but no scope is set")]
+fn scoped_factories_require_a_scope() {
+    let con = setup();
+
+    let other = setup();
+    with_scope(&other, 7, || ProjectFactory::default().insert(&con));
+}
+
+#[test]
+fn test_contexts_have_sequences_and_seeds_of_their_own() {
+    let name = |n| format!("Country {}", n);
+    sequence(name);
+
+    let ctx = TestContext::new(setup()).seed(7);
+    assert_eq!(
+        "Country 1",
+        ctx.insert(CountryFactory::default().name(sequence(name)))
+            .name
+    );
+    assert_eq!("Country 2", sequence(name));
+    assert_eq!(1, sequence_in("context.name", |n| n));
+    let first = random::next_u64();
+
+    {
+        let nested = TestContext::new(setup()).seed(7);
+        assert_eq!("Country 1", sequence(name));
+        assert_eq!(first, random::next_u64());
+        drop(nested);
+    }
+
+    assert_eq!("Country 3", sequence(name));
+    reset_sequences();
+    assert_eq!("Country 1", sequence(name));
+    assert_eq!(1, sequence_in("context.name", |n| n));
+}
+
+#[test]
+fn test_contexts_can_be_dropped_in_any_order() {
+    let name = |n| format!("Country {}", n);
+
+    let outer = TestContext::new(setup());
+    assert_eq!("Country 1", sequence(name));
+    let inner = TestContext::new(setup());
+    assert_eq!("Country 1", sequence(name));
+    assert_eq!("Country 2", sequence(name));
+
+    drop(outer);
+    assert_eq!("Country 3", sequence(name));
+    drop(inner);
+}
+
+#[test]
+fn test_contexts_have_latest_inserts_of_their_own() {
+    use diesel_factories::latest_inserted_id;
+
+    let con = setup();
+    let denmark = CountryFactory::default().insert(&con);
+
+    let ctx = TestContext::new(&con);
+    assert_eq!(None, latest_inserted_id::<Country, i32, _>(&con));
+    let sweden = CountryFactory::default().name("Sweden").insert(&con);
+    assert_eq!(Some(sweden.id), latest_inserted_id::<Country, i32, _>(&con));
+    drop(ctx);
+
+    assert_eq!(
+        Some(denmark.id),
+        latest_inserted_id::<Country, i32, _>(&con)
+    );
+}
+
+#[test]
+fn test_context_references_are_inserted_once() {
+    let ctx = TestContext::new(setup());
+
+    let denmark = ctx.reference("denmark", || CountryFactory::default().name("Denmark"));
+    let again = ctx.reference("denmark", || CountryFactory::default().name("Sweden"));
+    let city = ctx.insert(CityFactory::default().country(&again));
+
+    assert_eq!(denmark.id, again.id);
+    assert_eq!("Denmark", again.name);
+    assert_eq!(denmark.id, city.country_id);
+    assert_eq!(1, count_countries(ctx.connection()));
+}
+
+#[test]
+#[should_panic(expected = "Reference \"denmark\" is not a")]
+fn test_context_references_keep_their_type() {
+    let ctx = TestContext::new(setup());
+
+    ctx.reference("denmark", CountryFactory::default);
+    ctx.reference("denmark", CityFactory::default);
+}
+
+#[test]
+fn recorded_fixtures_can_be_run_as_seeds() {
+    use crate::schema::{countries, countries_languages, languages};
+
+    let ctx = TestContext::new(setup()).record_fixtures();
+    let con = ctx.connection();
+
+    let (country, languages) = CountryFactory::default()
+        .name("Switzerland")
+        .languages(vec![
+            LanguageFactory::default().code("de").name("German"),
+            LanguageFactory::default().code("fr").name("French"),
+        ])
+        .insert(con);
+    let sql = ctx.dump_fixtures(fixtures::Format::Sql);
+    assert_eq!(5, sql.lines().count());
+
+    let language_ids = languages
+        .iter()
+        .map(|language| language.id)
+        .collect::<Vec<_>>();
+    diesel::delete(
+        countries_languages::table.filter(countries_languages::country_id.eq(country.id)),
+    )
+    .execute(con)
+    .unwrap();
+    diesel::delete(languages::table.filter(languages::id.eq_any(&language_ids)))
+        .execute(con)
+        .unwrap();
+    diesel::delete(countries::table.find(country.id))
+        .execute(con)
+        .unwrap();
+    assert_eq!(0, count_countries(con));
+
+    diesel::connection::SimpleConnection::batch_execute(con, &sql).unwrap();
+    assert_eq!(
+        "Switzerland",
+        countries::table
+            .find(country.id)
+            .first::<Country>(con)
+            .unwrap()
+            .name
+    );
+    assert_eq!(2, count_languages(con));
+    assert_eq!(
+        2,
+        countries_languages::table
+            .filter(countries_languages::country_id.eq(country.id))
+            .count()
+            .get_result::<i64>(con)
+            .unwrap()
+    );
+}
+
+#[test]
+fn fixtures_are_recorded_per_connection() {
+    let con = setup();
+    let other = setup();
+
+    let (country, recorded) = fixtures::record(&con, || {
+        CountryFactory::default().name("Sweden").insert(&other);
+        CountryFactory::default().insert(&con)
+    });
+
+    assert_eq!(1, recorded.len());
+    assert_eq!(
+        format!(
+            "[\n  {{\"table\" : \"countries\", \"row\" : {{\"id\":{},\"name\":\"Denmark\"}}}}\n]\n",
+            country.id
+        ),
+        recorded.dump(fixtures::Format::Json)
+    );
+    assert!(fixtures::record(&con, || ()).1.is_empty());
+}
+
+fn setup() -> PgConnection {
+    let database_url = "postgres://localhost/diesel_factories_test";
+    let con = PgConnection::establish(database_url).unwrap();
+    con.begin_test_transaction().unwrap();
+    con
+}
+
+fn count_users(con: &PgConnection) -> i64 {
+    use crate::schema::users;
+    use diesel::dsl::count_star;
+    users::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_countries(con: &PgConnection) -> i64 {
+    use crate::schema::countries;
+    use diesel::dsl::count_star;
+    countries::table.select(count_star()).first(con).unwrap()
+}
+
+fn find_country_by_id(input: i32, con: &PgConnection) -> Country {
+    use crate::schema::countries::dsl::*;
+    countries
+        .filter(id.eq(&input))
+        .first::<Country>(con)
+        .unwrap()
+}
+
+fn count_cities(con: &PgConnection) -> i64 {
+    use crate::schema::cities;
+    use diesel::dsl::count_star;
+    cities::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_languages(con: &PgConnection) -> i64 {
+    use crate::schema::languages;
+    use diesel::dsl::count_star;
+    languages::table.select(count_star()).first(con).unwrap()
+}
+
+fn find_city_by_country_id(input: i32, con: &PgConnection) -> City {
+    use crate::schema::cities::dsl::*;
+    cities
+        .filter(country_id.eq(&input))
+        .first::<City>(con)
         .unwrap()
 }
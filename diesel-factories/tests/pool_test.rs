@@ -0,0 +1,200 @@
+#![cfg(all(feature = "r2d2", feature = "postgres"))]
+#![allow(
+    proc_macro_derive_resolution_fallback,
+    non_local_definitions,
+    dead_code
+)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool, PooledConnection};
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{
+    factory_v2, fixtures, insert_list_concurrent, latest_inserted_id, with_scope, Association,
+    Factory, InsertWith,
+};
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        projects (id) {
+            id -> Integer,
+            name -> Text,
+            tenant_id -> Integer,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Project {
+    pub id: i32,
+    pub name: String,
+    pub tenant_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Project",
+    table = "crate::schema::projects",
+    scoped_column = "tenant_id"
+)]
+struct ProjectFactory {
+    #[factory(default = "\"Roadmap\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct CountryFactory {
+    #[factory(default = "\"Denmark\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct CityFactory<'a> {
+    #[factory(default = "\"Copenhagen\".into()")]
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+#[test]
+fn inserting_with_pooled_connection() {
+    let con = setup();
+
+    let city = CityFactory::default().insert(&con);
+    let country = InsertWith::insert_with(CountryFactory::default(), &con);
+    let other_city =
+        factory_v2::Insert::insert(CityFactory::default().country(&country), &con).unwrap();
+
+    assert_eq!(country.id, other_city.country_id);
+    assert_ne!(city.country_id, other_city.country_id);
+}
+
+#[test]
+fn latest_inserts_are_found_through_pooled_connections() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    assert_eq!(
+        Some(country.id),
+        latest_inserted_id::<Country, i32, _>(&con)
+    );
+
+    let city = CityFactory::default().country_latest().insert(&con);
+    assert_eq!(country.id, city.country_id);
+}
+
+#[test]
+fn scopes_apply_to_pooled_connections() {
+    let con = setup();
+
+    let project = with_scope(&con, 7, || ProjectFactory::default().insert(&con));
+    let other = with_scope(&con, 8, || {
+        factory_v2::Insert::insert(ProjectFactory::default(), &con).unwrap()
+    });
+
+    assert_eq!((7, 8), (project.tenant_id, other.tenant_id));
+}
+
+#[test]
+fn fixtures_are_recorded_on_pooled_connections() {
+    let con = setup();
+
+    let (city, recorded) = fixtures::record(&con, || CityFactory::default().insert(&con));
+
+    assert_eq!(2, recorded.len());
+    let sql = recorded.dump(fixtures::Format::Sql);
+    assert!(sql.starts_with(&format!(
+        "INSERT INTO countries (id, name) VALUES ('{}', 'Denmark');\n",
+        city.country_id
+    )));
+    assert!(sql.contains(&format!(
+        "VALUES ('{}', 'Copenhagen', '{}'",
+        city.id, city.country_id
+    )));
+}
+
+#[test]
+fn inserting_concurrently_keeps_the_order() {
+    let pool = setup_pool(3);
+
+    let countries = insert_list_concurrent(&pool, 7, 3, |n| {
+        CountryFactory::default().name(format!("Country {}", n))
+    })
+    .unwrap();
+
+    let names = countries
+        .iter()
+        .map(|country| country.name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        (0..7).map(|n| format!("Country {}", n)).collect::<Vec<_>>(),
+        names
+    );
+    let mut ids = countries
+        .iter()
+        .map(|country| country.id)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(7, ids.len());
+}
+
+/// Begins a test transaction on every connection the pool opens.
+#[derive(Debug)]
+struct TestTransaction;
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, con: &mut PgConnection) -> Result<(), r2d2::Error> {
+        con.begin_test_transaction()
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+fn setup_pool(size: u32) -> Pool<ConnectionManager<PgConnection>> {
+    let manager =
+        ConnectionManager::<PgConnection>::new("postgres://localhost/diesel_factories_test");
+    Pool::builder()
+        .max_size(size)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(manager)
+        .unwrap()
+}
+
+fn setup() -> PooledConnection<ConnectionManager<PgConnection>> {
+    let manager =
+        ConnectionManager::<PgConnection>::new("postgres://localhost/diesel_factories_test");
+    let pool = Pool::builder().max_size(1).build(manager).unwrap();
+    let con = pool.get().unwrap();
+    con.begin_test_transaction().unwrap();
+    con
+}
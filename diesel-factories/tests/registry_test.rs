@@ -0,0 +1,138 @@
+#![cfg(feature = "inventory")]
+#![allow(
+    proc_macro_derive_resolution_fallback,
+    non_local_definitions,
+    dead_code
+)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel_factories::registry::RegisteredFactory;
+use diesel_factories::{Association, Factory, PolymorphicAssociation};
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        comments (id) {
+            id -> Integer,
+            body -> Text,
+            commentable_id -> Integer,
+            commentable_type -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Comment {
+    pub id: i32,
+    pub body: String,
+    pub commentable_id: i32,
+    pub commentable_type: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Country", table = "crate::schema::countries")]
+struct CountryFactory {
+    #[factory(default = "\"Denmark\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct CityFactory<'a> {
+    #[factory(default = "\"Copenhagen\".into()")]
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities")]
+struct CapitalFactory<'a> {
+    #[factory(default = "\"Copenhagen\".into()")]
+    pub name: String,
+    #[factory(foreign_key = "country_id")]
+    pub nation: Association<'a, Country, CountryFactory>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "Comment", table = "crate::schema::comments")]
+struct CommentFactory<'a> {
+    pub body: String,
+    #[factory(polymorphic = "commentable", types("Country", "City"))]
+    pub commentable: PolymorphicAssociation<'a>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = "City", table = "crate::schema::cities", abstract)]
+struct BaseCityFactory {
+    pub name: String,
+}
+
+#[test]
+fn derived_factories_are_registered() {
+    let city = RegisteredFactory::find("CityFactory").unwrap();
+
+    assert_eq!(
+        &RegisteredFactory {
+            name: "CityFactory",
+            module_path: "registry_test",
+            model: "City",
+            table: "cities",
+            associations: &[("country_id", "CountryFactory")],
+            polymorphic_associations: &[],
+        },
+        city
+    );
+    assert!(RegisteredFactory::find("CountryFactory").is_some());
+}
+
+#[test]
+fn associations_are_registered_with_their_columns() {
+    let capital = RegisteredFactory::find("CapitalFactory").unwrap();
+    assert_eq!(&[("country_id", "CountryFactory")], capital.associations);
+
+    let comment = RegisteredFactory::find("CommentFactory").unwrap();
+    assert!(comment.associations.is_empty());
+    assert_eq!(
+        &[(
+            "commentable_id",
+            "commentable_type",
+            &["Country", "City"] as &[_]
+        )],
+        comment.polymorphic_associations
+    );
+}
+
+#[test]
+fn abstract_factories_are_not_registered() {
+    assert!(RegisteredFactory::find("BaseCityFactory").is_none());
+    assert_eq!(4, RegisteredFactory::all().count());
+}
@@ -0,0 +1,82 @@
+use diesel::connection::{AnsiTransactionManager, Connection, SimpleConnection};
+use diesel::deserialize::{Queryable, QueryableByName};
+use diesel::pg::{Pg, PgConnection};
+use diesel::query_builder::{AsQuery, QueryFragment, QueryId};
+use diesel::sql_types::HasSqlType;
+use diesel::{ConnectionResult, QueryResult};
+use std::cell::Cell;
+
+/// A `PgConnection` wrapper that counts the queries it runs.
+pub struct CountingConnection {
+    inner: PgConnection,
+    queries: Cell<usize>,
+}
+
+impl CountingConnection {
+    pub fn new(inner: PgConnection) -> Self {
+        Self {
+            inner,
+            queries: Cell::new(0),
+        }
+    }
+
+    pub fn queries(&self) -> usize {
+        self.queries.get()
+    }
+
+    fn count(&self) {
+        self.queries.set(self.queries.get() + 1);
+    }
+}
+
+impl SimpleConnection for CountingConnection {
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        self.inner.batch_execute(query)
+    }
+}
+
+impl Connection for CountingConnection {
+    type Backend = Pg;
+    type TransactionManager = AnsiTransactionManager;
+
+    fn establish(database_url: &str) -> ConnectionResult<Self> {
+        PgConnection::establish(database_url).map(Self::new)
+    }
+
+    fn execute(&self, query: &str) -> QueryResult<usize> {
+        self.count();
+        self.inner.execute(query)
+    }
+
+    fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+    where
+        T: AsQuery,
+        T::Query: QueryFragment<Pg> + QueryId,
+        Pg: HasSqlType<T::SqlType>,
+        U: Queryable<T::SqlType, Pg>,
+    {
+        self.count();
+        self.inner.query_by_index(source)
+    }
+
+    fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+    where
+        T: QueryFragment<Pg> + QueryId,
+        U: QueryableByName<Pg>,
+    {
+        self.count();
+        self.inner.query_by_name(source)
+    }
+
+    fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        self.count();
+        self.inner.execute_returning_count(source)
+    }
+
+    fn transaction_manager(&self) -> &Self::TransactionManager {
+        self.inner.transaction_manager()
+    }
+}
@@ -0,0 +1,262 @@
+#![cfg(feature = "sqlite")]
+#![allow(
+    proc_macro_derive_resolution_fallback,
+    non_local_definitions,
+    dead_code
+)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use diesel_factories::{sequence, Association, Factory, FieldValue};
+
+mod schema {
+    table! {
+        tags (slug) {
+            slug -> Text,
+            name -> Text,
+        }
+    }
+
+    table! {
+        articles (slug) {
+            slug -> Text,
+            title -> Text,
+            tag_id -> Text,
+        }
+    }
+
+    table! {
+        authors (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        books (id) {
+            id -> Integer,
+            title -> Text,
+            author_id -> Integer,
+        }
+    }
+
+    table! {
+        series (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Tag {
+    pub slug: String,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Article {
+    pub slug: String,
+    pub title: String,
+    pub tag_id: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Author {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Book {
+    pub id: i32,
+    pub title: String,
+    pub author_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Series {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Tag",
+    table = "crate::schema::tags",
+    connection = "diesel::sqlite::SqliteConnection",
+    id = "String",
+    primary_key = "slug",
+    reselect
+)]
+struct TagFactory {
+    #[factory(default_with = "tag_slug")]
+    pub slug: String,
+    #[factory(default = "\"Rust\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Article",
+    table = "crate::schema::articles",
+    connection = "diesel::sqlite::SqliteConnection",
+    id = "String",
+    primary_key = "slug",
+    reselect
+)]
+struct ArticleFactory<'a> {
+    #[factory(default = "\"hello-world\".into()")]
+    pub slug: String,
+    #[factory(default = "\"Hello world\".into()")]
+    pub title: String,
+    pub tag: Association<'a, Tag, TagFactory>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Author",
+    table = "crate::schema::authors",
+    connection = "diesel::sqlite::SqliteConnection"
+)]
+struct AuthorFactory {
+    #[factory(default = "\"Ursula\".into()")]
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Book",
+    table = "crate::schema::books",
+    connection = "diesel::sqlite::SqliteConnection"
+)]
+struct BookFactory<'a> {
+    #[factory(default = "\"The Dispossessed\".into()")]
+    pub title: String,
+    pub author: Association<'a, Author, AuthorFactory>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Series",
+    table = "crate::schema::series",
+    connection = "diesel::sqlite::SqliteConnection"
+)]
+struct SeriesFactory {
+    #[factory(default = "FieldValue::Default")]
+    pub name: FieldValue<String>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = "Author",
+    table = "crate::schema::authors",
+    connection = "diesel::pg::PgConnection",
+    connection = "diesel::sqlite::SqliteConnection"
+)]
+struct MultiBackendAuthorFactory {
+    #[factory(default = "\"Octavia\".into()")]
+    pub name: String,
+}
+
+fn tag_slug() -> String {
+    sequence(|n| format!("tag-{}", n))
+}
+
+#[test]
+fn inserting_into_without_rowid_tables() {
+    let con = setup();
+
+    let article = ArticleFactory::default().insert(&con);
+    let tag = schema::tags::table
+        .find(&article.tag_id)
+        .first::<Tag>(&con)
+        .unwrap();
+
+    assert_eq!("hello-world", article.slug);
+    assert_eq!("Rust", tag.name);
+}
+
+#[test]
+fn associating_with_string_keyed_model() {
+    let con = setup();
+
+    let tag = TagFactory::default().slug("sqlite").insert(&con);
+    let article = ArticleFactory::default().tag(&tag).insert(&con);
+
+    assert_eq!("sqlite", article.tag_id);
+}
+
+#[test]
+fn inserting_rows_keyed_by_last_insert_id() {
+    let con = setup();
+
+    let first = BookFactory::default().insert(&con);
+    let second = BookFactory::default().title("Lathe of Heaven").insert(&con);
+    let author_id = AuthorFactory::default().insert_returning_id(&con);
+
+    assert_eq!((1, 2), (first.id, second.id));
+    assert_eq!("Lathe of Heaven", second.title);
+    assert_eq!(2, second.author_id);
+    assert_eq!(3, author_id);
+}
+
+#[test]
+fn leaving_out_default_field_values() {
+    let con = setup();
+
+    let series = SeriesFactory::default().insert(&con);
+    let other = SeriesFactory::default()
+        .name("Earthsea".to_string())
+        .insert(&con);
+
+    assert_eq!("Hainish", series.name);
+    assert_eq!("Earthsea", other.name);
+}
+
+// Without the `postgres` feature the Postgres connection is left out, so SQLite is the first
+#[cfg(not(feature = "postgres"))]
+#[test]
+fn connections_of_disabled_backends_are_left_out() {
+    let con = setup();
+
+    let author = MultiBackendAuthorFactory::default().insert(&con);
+
+    assert_eq!("Octavia", author.name);
+}
+
+fn setup() -> SqliteConnection {
+    let con = SqliteConnection::establish(":memory:").unwrap();
+    con.execute(
+        "CREATE TABLE tags (slug TEXT PRIMARY KEY NOT NULL, name TEXT NOT NULL) WITHOUT ROWID",
+    )
+    .unwrap();
+    con.execute(
+        "CREATE TABLE articles (
+            slug TEXT PRIMARY KEY NOT NULL,
+            title TEXT NOT NULL,
+            tag_id TEXT NOT NULL
+        ) WITHOUT ROWID",
+    )
+    .unwrap();
+    con.execute("CREATE TABLE authors (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL)")
+        .unwrap();
+    con.execute(
+        "CREATE TABLE books (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            author_id INTEGER NOT NULL
+        )",
+    )
+    .unwrap();
+    con.execute(
+        "CREATE TABLE series (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL DEFAULT 'Hainish'
+        )",
+    )
+    .unwrap();
+    con
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "tracing")]
+#![allow(
+    proc_macro_derive_resolution_fallback,
+    non_local_definitions,
+    dead_code,
+    clippy::derivable_impls
+)]
+
+include!("setup/docs_setup_with_city_factory.rs");
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Records each event as the factories of the spans it's in and its `id` field.
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<String>>>,
+    entered: Arc<Mutex<Vec<usize>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(Default)]
+struct Fields(Vec<(&'static str, String)>);
+
+impl Fields {
+    fn get(&self, name: &str) -> String {
+        self.0
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(fields.get("factory"));
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let spans = self.spans.lock().unwrap();
+        let factories = self
+            .entered
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|index| spans[index - 1].clone())
+            .collect::<Vec<_>>();
+        self.events.lock().unwrap().push(format!(
+            "{} id={}",
+            factories.join(" > "),
+            fields.get("id")
+        ));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64() as usize);
+    }
+
+    fn exit(&self, _: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+#[test]
+fn inserts_are_traced_with_their_associations() {
+    let con = PgConnection::establish("postgres://localhost/diesel_factories_test").unwrap();
+    con.begin_test_transaction().unwrap();
+    let recorder = Recorder::default();
+
+    let city =
+        tracing::subscriber::with_default(recorder.clone(), || CityFactory::default().insert(&con));
+
+    assert_eq!(
+        vec![
+            format!("CityFactory > CountryFactory id={}", city.country_id),
+            format!("CityFactory id={}", city.id),
+        ],
+        *recorder.events.lock().unwrap()
+    );
+}
@@ -0,0 +1,5 @@
+CREATE TABLE languages (
+  id SERIAL PRIMARY KEY,
+  code TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE comments (
+  id SERIAL PRIMARY KEY,
+  body TEXT NOT NULL,
+  commentable_id integer NOT NULL,
+  commentable_type TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE countries_languages (
+  country_id integer NOT NULL,
+  language_id integer NOT NULL,
+  PRIMARY KEY (country_id, language_id)
+);
@@ -0,0 +1,4 @@
+CREATE TABLE tags (
+  slug text PRIMARY KEY,
+  name text NOT NULL
+);
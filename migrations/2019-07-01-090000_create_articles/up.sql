@@ -0,0 +1,7 @@
+CREATE TABLE articles (
+  id SERIAL PRIMARY KEY,
+  title text NOT NULL,
+  tags text[],
+  ratings integer[] NOT NULL,
+  notes text[] NOT NULL
+);
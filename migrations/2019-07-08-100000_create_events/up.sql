@@ -0,0 +1,6 @@
+CREATE TABLE events (
+  id SERIAL PRIMARY KEY,
+  name text NOT NULL,
+  created_at timestamp NOT NULL,
+  updated_at timestamp NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE newsletters (
+  id SERIAL PRIMARY KEY,
+  title text NOT NULL,
+  status text NOT NULL DEFAULT 'draft',
+  subtitle text NULL DEFAULT 'Weekly'
+);
@@ -0,0 +1,5 @@
+CREATE TABLE categories (
+  id SERIAL PRIMARY KEY,
+  name text NOT NULL,
+  parent_id integer NULL REFERENCES categories (id)
+);
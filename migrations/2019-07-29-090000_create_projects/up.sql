@@ -0,0 +1,12 @@
+CREATE TABLE projects (
+  id SERIAL PRIMARY KEY,
+  name text NOT NULL,
+  tenant_id integer NOT NULL
+);
+
+CREATE TABLE tasks (
+  id SERIAL PRIMARY KEY,
+  title text NOT NULL,
+  project_id integer NOT NULL REFERENCES projects (id),
+  tenant_id integer NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE bookmarks (
+  id SERIAL PRIMARY KEY,
+  bookmarkable_id text NOT NULL,
+  bookmarkable_type text NOT NULL
+);